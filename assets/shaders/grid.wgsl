@@ -0,0 +1,24 @@
+struct GridMaterial {
+    floor_color: vec4<f32>,
+    line_color: vec4<f32>,
+    time: f32,
+    spacing: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: GridMaterial;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // the lines are fixed in the world, so they don't squeeze as the royale arena shrinks
+    let position = in.world_position.xy / material.spacing + vec2<f32>(material.time * 0.1);
+    // distance to the nearest line in pixels, for lines that stay a pixel wide at any zoom
+    let distance = abs(fract(position - 0.5) - 0.5) / fwidth(position);
+    let line = 1.0 - min(min(distance.x, distance.y), 1.0);
+    let pulse = 0.7 + 0.3 * sin(material.time * 2.0);
+    return mix(material.floor_color, material.line_color, line * pulse);
+}
@@ -0,0 +1,51 @@
+use bevy::utils::default;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tron::{
+    gameplay::{BotPlayers, GameMode, MatchSettings, COUNTDOWN_FRAMES, ROYALE_PLAYERS},
+    networking::SYNCTEST_CHECK_DISTANCE,
+    stepping::{self, Stepper},
+};
+
+// What the rollback schedule costs per frame, on its own and with every frame rolled back and
+// simulated again like a synctest does. A royale of bots on the longest trails the lobby
+// offers, timed from once those trails have grown all the way out. Every iteration starts
+// over from that frame, so later ones don't end up measuring some emptier round.
+fn rollback_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rollback");
+    group
+        .sample_size(10)
+        .throughput(Throughput::Elements(BENCH_FRAMES as u64));
+    for (name, check_distance) in [
+        ("frame", 0),
+        ("frame_resimulated", SYNCTEST_CHECK_DISTANCE),
+    ] {
+        let settings = MatchSettings {
+            seed: BENCH_SEED,
+            trail_length: BENCH_TRAIL_LENGTH,
+            ..default()
+        };
+        let mut app = stepping::match_app(GameMode::Royale, settings);
+        app.world.resource_mut::<BotPlayers>().0 = (0..ROYALE_PLAYERS).collect();
+        let mut stepper = Stepper::new(check_distance);
+        stepper.advance_idle(&mut app.world, COUNTDOWN_FRAMES + BENCH_TRAIL_LENGTH);
+        let start = stepper.save(&app.world);
+
+        // the first few frames after loading have less to roll back, hence the long run
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                stepper.load(&mut app.world, &start);
+                stepper.advance_idle(&mut app.world, BENCH_FRAMES);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rollback_frames);
+criterion_main!(benches);
+
+const BENCH_SEED: u32 = 1;
+
+const BENCH_TRAIL_LENGTH: u32 = 320;
+
+const BENCH_FRAMES: u32 = 120;
@@ -0,0 +1,287 @@
+use crate::gameplay::{BotDifficulty, GameMode, Handicap, Steering};
+
+// What the game was launched with, so several clients can be scripted into the same match
+// without clicking through the menus
+#[derive(Clone, Debug)]
+pub struct Args {
+    // joined onto the usual room name, so only clients passing the same one meet
+    pub room: Option<String>,
+    pub server: String,
+    // tried in order when the server doesn't answer
+    pub fallback_servers: Vec<String>,
+    // replace the STUN/TURN server from the settings
+    pub ice_servers: Vec<String>,
+    pub turn_username: Option<String>,
+    pub turn_credential: Option<String>,
+    // replaces the one from the settings
+    pub spectator_delay: Option<u32>,
+    // people to wait for, bots take whatever is left of the mode's slots
+    pub players: Option<usize>,
+    pub name: Option<String>,
+    // straight into a match against bots
+    pub local: bool,
+    // bots on every handle with extra rollbacks, to shake out desyncs
+    pub synctest: bool,
+    // no window, sound or input, with a bot playing our handle
+    pub headless: bool,
+    // input log to write when the game quits
+    pub record: Option<String>,
+    // input log or link to one to play back, quitting with an error if the simulation strays
+    // from it
+    pub replay: Option<String>,
+    // quit after simulating this many frames
+    pub frames: Option<u32>,
+    // report how long rollback frames take on the way out
+    pub bench: bool,
+    // leaderboard server to post results to and show the top of
+    pub leaderboard: Option<String>,
+    // what to play until the menus pick something else, a duel with analog steering if unset
+    pub mode: Option<GameMode>,
+    pub steering: Option<Steering>,
+    // the settings we'd propose as lobby host, over the ones in tuning.json
+    pub move_speed: Option<f32>,
+    pub turn_speed: Option<f32>,
+    // 0 for trails that never fade
+    pub trail_length: Option<u32>,
+    pub seed: Option<u32>,
+    pub rounds: Option<u32>,
+    pub bot_difficulty: Option<BotDifficulty>,
+    // by handle, anyone past the end plays without one
+    pub handicaps: Vec<Handicap>,
+    pub board_size: Option<f32>,
+    pub player_size: Option<f32>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            room: None,
+            server: option_env!("MATCHBOX_SERVER_ADDR")
+                .unwrap_or(MATCHBOX_SERVER)
+                .to_string(),
+            fallback_servers: Vec::new(),
+            ice_servers: Vec::new(),
+            turn_username: None,
+            turn_credential: None,
+            spectator_delay: None,
+            players: None,
+            name: None,
+            local: false,
+            synctest: false,
+            headless: false,
+            record: None,
+            replay: None,
+            frames: None,
+            bench: false,
+            // the site's own, since a link could point browsers anywhere
+            leaderboard: option_env!("LEADERBOARD_ADDR").map(str::to_string),
+            mode: None,
+            steering: None,
+            move_speed: None,
+            turn_speed: None,
+            trail_length: None,
+            seed: None,
+            rounds: None,
+            bot_difficulty: None,
+            handicaps: Vec::new(),
+            board_size: None,
+            player_size: None,
+        }
+    }
+}
+
+impl Args {
+    // whether to go online as soon as the game is up
+    pub fn join_room(&self) -> bool {
+        self.room.is_some() || self.players.is_some()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn parse() -> Self {
+        let mut args = Self::default();
+        let mut words = std::env::args().skip(1);
+        while let Some(flag) = words.next() {
+            match flag.as_str() {
+                "--room" => args.room = Some(value(&flag, words.next())),
+                "--server" => args.server = value(&flag, words.next()),
+                "--fallback-server" => args.fallback_servers.push(value(&flag, words.next())),
+                "--ice-server" => args.ice_servers.push(value(&flag, words.next())),
+                "--turn-username" => args.turn_username = Some(value(&flag, words.next())),
+                "--turn-credential" => args.turn_credential = Some(value(&flag, words.next())),
+                "--spectator-delay" => match value(&flag, words.next()).parse() {
+                    Ok(seconds) => args.spectator_delay = Some(seconds),
+                    Err(_) => usage_error("--spectator-delay takes a number of seconds"),
+                },
+                "--players" => match value(&flag, words.next()).parse() {
+                    Ok(players) => args.players = Some(players),
+                    Err(_) => usage_error("--players takes a number"),
+                },
+                "--name" => args.name = Some(value(&flag, words.next())),
+                "--local" => args.local = true,
+                "--synctest" => args.synctest = true,
+                "--headless" => args.headless = true,
+                "--record" => args.record = Some(value(&flag, words.next())),
+                "--replay" => args.replay = Some(value(&flag, words.next())),
+                "--frames" => match value(&flag, words.next()).parse() {
+                    Ok(frames) => args.frames = Some(frames),
+                    Err(_) => usage_error("--frames takes a number"),
+                },
+                "--bench" => args.bench = true,
+                "--leaderboard" => args.leaderboard = Some(value(&flag, words.next())),
+                "--mode" => args.mode = Some(parsed(&flag, words.next())),
+                "--steering" => args.steering = Some(parsed(&flag, words.next())),
+                "--move-speed" => args.move_speed = Some(parsed(&flag, words.next())),
+                "--turn-speed" => args.turn_speed = Some(parsed(&flag, words.next())),
+                "--trail-length" => match value(&flag, words.next()).as_str() {
+                    "infinite" => args.trail_length = Some(0),
+                    frames => match frames.parse() {
+                        Ok(frames) => args.trail_length = Some(frames),
+                        Err(_) => usage_error("--trail-length takes a number or infinite"),
+                    },
+                },
+                "--seed" => args.seed = Some(parsed(&flag, words.next())),
+                "--rounds" => args.rounds = Some(parsed(&flag, words.next())),
+                "--bot-difficulty" => args.bot_difficulty = Some(parsed(&flag, words.next())),
+                // like "1/1,0.9/1.5"
+                "--handicaps" => {
+                    args.handicaps = value(&flag, words.next())
+                        .split(',')
+                        .map(|handicap| match handicap.parse() {
+                            Ok(handicap) => handicap,
+                            Err(_) => usage_error(&format!("malformed handicap '{}'", handicap)),
+                        })
+                        .collect()
+                }
+                "--board-size" => args.board_size = Some(parsed(&flag, words.next())),
+                "--player-size" => args.player_size = Some(parsed(&flag, words.next())),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => usage_error(&format!("unexpected argument '{}'", flag)),
+            }
+        }
+        // benchmarks only make sense with every frame rolled back the same way each run. A
+        // replay runs to its end, with inputs that stay put however the bots get changed.
+        if args.bench {
+            args.headless = true;
+            args.synctest = true;
+            if args.replay.is_none() {
+                args.frames.get_or_insert(BENCH_FRAMES);
+            }
+        }
+        args
+    }
+
+    // Browsers have no command line, but links like ?room=abc&name=Sam can carry the same
+    // options, so sharing one gets friends into the same room
+    #[cfg(target_arch = "wasm32")]
+    pub fn parse() -> Self {
+        let mut args = Self::default();
+        let params = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .and_then(|query| web_sys::UrlSearchParams::new_with_str(&query).ok());
+        if let Some(params) = params {
+            args.room = params.get("room");
+            if let Some(server) = params.get("server") {
+                args.server = server;
+            }
+            // comma separated, since a link only carries each parameter once
+            if let Some(fallback) = params.get("fallback") {
+                args.fallback_servers = fallback.split(',').map(str::to_string).collect();
+            }
+            if let Some(ice) = params.get("ice") {
+                args.ice_servers = ice.split(',').map(str::to_string).collect();
+            }
+            args.turn_username = params.get("turn_username");
+            args.turn_credential = params.get("turn_credential");
+            args.players = params
+                .get("players")
+                .and_then(|players| players.parse().ok());
+            args.name = params.get("name");
+        }
+        // a shared replay, which plays as soon as the page loads
+        let hash = web_sys::window().and_then(|window| window.location().hash().ok());
+        args.replay = hash.filter(|hash| hash.starts_with(crate::replay::SHARE_PREFIX));
+        args
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn value(flag: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) if !value.starts_with("--") => value,
+        _ => usage_error(&format!("{} needs a value", flag)),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parsed<T: std::str::FromStr>(flag: &str, word: Option<String>) -> T {
+    let word = value(flag, word);
+    match word.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => usage_error(&format!("invalid value '{}' for {}", word, flag)),
+    }
+}
+
+// this runs before logging is set up, so it goes straight to stderr
+#[cfg(not(target_arch = "wasm32"))]
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
+// unless the build sets MATCHBOX_SERVER_ADDR, which is how the deployed site finds its server
+const MATCHBOX_SERVER: &str = "ws://127.0.0.1:3536";
+
+// long enough for trails to reach their full length
+#[cfg(not(target_arch = "wasm32"))]
+const BENCH_FRAMES: u32 = 60 * 30;
+
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "\
+Usage: tron [OPTIONS]
+
+Options:
+      --room <ROOM>        Only match with clients using the same room, joining one that's
+                           already playing spectates
+      --server <URL>       Matchbox server to find peers on [default: ws://127.0.0.1:3536]
+      --fallback-server <URL>
+                           Server to try when the previous one doesn't answer, can be repeated
+      --ice-server <URL>   stun: or turn: URL for getting through NATs, can be repeated
+                           [default: stun:stun.l.google.com:19302]
+      --turn-username <NAME>
+                           Username for the TURN server
+      --turn-credential <PASSWORD>
+                           Password for the TURN server
+      --spectator-delay <SECONDS>
+                           How far behind our matches anyone watching is kept [default: 3]
+      --players <COUNT>    How many people to wait for, bots fill the other slots
+      --name <NAME>        Name shown to the other players
+      --local              Start a match against bots right away
+      --synctest           Start a match of bots only that resimulates every frame to catch desyncs
+      --headless           Run without a window or sound, a bot plays for us and
+                           a synctest runs unless there's a room to join
+      --record <FILE>      Write every frame's inputs and a checksum of the state on quitting
+      --replay <FILE|LINK> Play back a recording or a shared replay link, failing at the first
+                           frame that differs
+      --frames <COUNT>     Quit after simulating this many frames
+      --bench              Time a headless synctest and report the cost of a rollback frame,
+                           --mode royale gives the most trails [default frames: 1800].
+                           With --replay, times the recording's inputs to the end
+      --leaderboard <URL>  Post match results to an http:// leaderboard server and show its top 10
+      --mode <MODE>        duel, teams, royale, survival, hill or pellets [default: duel]
+      --steering <STEERING>
+                           analog or grid [default: analog]
+      --move-speed <SPEED> Cycle speed to propose as lobby host [default: from tuning.json]
+      --turn-speed <SPEED> Turning speed to propose as lobby host [default: from tuning.json]
+      --trail-length <FRAMES|infinite>
+                           How long trails last [default: from tuning.json]
+      --seed <SEED>        Seed for spawns and pickups [default: 0]
+      --rounds <COUNT>     Rounds in a match, 0 plays forever [default: 5]
+      --bot-difficulty <DIFFICULTY>
+                           easy, medium or hard [default: medium]
+      --handicaps <LIST>   Speed/trail length multipliers by handle, like 1/1,0.9/1.5
+      --board-size <SIZE>  Arena size [default: from tuning.json]
+      --player-size <SIZE> Cycle hitbox size [default: from tuning.json]
+  -h, --help               Print help";
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    gameplay::{SimulationClock, Trail},
+    profiling::RollbackProfile,
+};
+
+// What the rollback schedule costs with --bench, so changes like collision rework can be
+// measured instead of guessed. Reads the same profile as the diagnostics overlay. Each frame
+// is simulated again 8 more times by the synctest, and --replay pins the inputs down so two
+// builds get compared on exactly the same match. cargo bench times the rollback schedule
+// without the wall clock or GGRS in the way.
+pub struct BenchPlugin;
+
+impl Plugin for BenchPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BenchStats::default())
+            .add_system(sample_rollback_cost)
+            .add_system_to_stage(CoreStage::Last, report_bench);
+    }
+}
+
+#[derive(Default)]
+pub struct BenchStats {
+    // time spent simulating by each update that simulated anything, rollbacks included
+    pub updates: Vec<Duration>,
+    pub elapsed: Duration,
+    // counting every re-simulation
+    pub frames: u32,
+    // most trail points alive at once
+    pub trail_points: usize,
+}
+
+fn sample_rollback_cost(
+    mut profile: ResMut<RollbackProfile>,
+    mut stats: ResMut<BenchStats>,
+    trail_query: Query<&Trail>,
+) {
+    let RollbackProfile {
+        elapsed, frames, ..
+    } = std::mem::take(&mut *profile);
+    if frames == 0 {
+        return;
+    }
+    stats.updates.push(elapsed);
+    stats.elapsed += elapsed;
+    stats.frames += frames;
+    let trail_points = trail_query.iter().map(|trail| trail.points.len()).sum();
+    stats.trail_points = stats.trail_points.max(trail_points);
+}
+
+fn report_bench(
+    mut exits: EventReader<AppExit>,
+    stats: Res<BenchStats>,
+    clock: Res<SimulationClock>,
+) {
+    if exits.iter().count() == 0 || stats.updates.is_empty() {
+        return;
+    }
+    let mut updates = stats.updates.clone();
+    updates.sort_unstable();
+    let percentile = |p: f64| updates[((updates.len() - 1) as f64 * p) as usize];
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.;
+
+    info!(
+        "{} frames, {} simulated with rollbacks, up to {} trail points",
+        clock.frame, stats.frames, stats.trail_points
+    );
+    info!(
+        "simulated frame: {:.3} ms on average",
+        millis(stats.elapsed) / stats.frames as f64
+    );
+    info!(
+        "update: {:.3} ms median, {:.3} ms p99, {:.3} ms worst",
+        millis(percentile(0.5)),
+        millis(percentile(0.99)),
+        millis(percentile(1.))
+    );
+}
@@ -0,0 +1,250 @@
+use bevy::{input::InputSystem, prelude::*};
+
+use crate::{
+    gameplay::Scoreboard,
+    networking::{send_chat, Lobby, PeerSocket},
+    ui::show_node,
+    GameState,
+};
+
+// Text chat on the matchmaking screen and on the game-over screen, over the socket's reliable
+// channel. GGRS only takes the other channel, so the lobby's chat carries on after the match.
+// Nobody gets to type while a match is being played.
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChatDraft::default())
+            // ahead of everything else reading the keyboard, so a line being typed can't
+            // also vote for a rematch or send emotes
+            .add_system_to_stage(CoreStage::PreUpdate, type_chat.after(InputSystem))
+            .add_system_set(SystemSet::on_enter(GameState::Matchmaking).with_system(setup_chat_box))
+            .add_system_set(
+                SystemSet::on_update(GameState::Matchmaking).with_system(update_chat_box),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Matchmaking).with_system(cleanup_chat_box),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup_chat_box))
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(update_chat_box))
+            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(cleanup_chat_box));
+    }
+}
+
+// What we're typing, Enter starts and sends a line and Escape throws it away
+#[derive(Default)]
+pub struct ChatDraft {
+    pub typing: bool,
+    pub text: String,
+}
+
+#[derive(Component)]
+pub struct ChatBox;
+
+#[derive(Component)]
+pub struct ChatLog;
+
+#[derive(Component)]
+pub struct ChatPrompt;
+
+// Masks the words in BLOCKED_WORDS, for lines from everyone else
+pub fn filter_chat(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let bare = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if BLOCKED_WORDS.contains(&bare.as_str()) {
+                "*".repeat(word.chars().count())
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn setup_chat_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(360.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(10.),
+                    left: Val::Px(10.),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: UiRect::all(Val::Px(6.)),
+                display: Display::None,
+                ..default()
+            },
+            color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..default()
+        })
+        .insert(ChatBox)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(ChatLog);
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.,
+                        color: Color::GRAY,
+                    },
+                ))
+                .insert(ChatPrompt);
+        });
+}
+
+// The key that starts a line, or None where there's no chat. Enter is taken on the game-over
+// screen, it votes for a rematch.
+fn chat_key(state: &State<GameState>, scoreboard: &Scoreboard) -> Option<KeyCode> {
+    match state.current() {
+        GameState::Matchmaking => Some(KeyCode::Return),
+        GameState::InGame if scoreboard.match_over => Some(GAME_OVER_CHAT_KEY),
+        _ => None,
+    }
+}
+
+fn has_peers(socket: &Option<PeerSocket>) -> bool {
+    socket
+        .as_ref()
+        .map_or(false, |socket| socket.connected_peers().next().is_some())
+}
+
+fn type_chat(
+    state: Res<State<GameState>>,
+    scoreboard: Res<Scoreboard>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut lobby: ResMut<Lobby>,
+    mut draft: ResMut<ChatDraft>,
+) {
+    let typed: String = characters
+        .iter()
+        .map(|character| character.char)
+        .filter(|c| !c.is_control())
+        .collect();
+    // no chat here, or nobody to talk to yet
+    let key = match chat_key(&state, &scoreboard) {
+        Some(key) if has_peers(&socket) => key,
+        _ => {
+            if draft.typing {
+                *draft = default();
+            }
+            return;
+        }
+    };
+    if !draft.typing {
+        if keys.just_pressed(key) {
+            draft.typing = true;
+            keys.clear();
+        }
+        return;
+    }
+
+    // nothing after us gets to see the keys a line is typed with
+    let (escape, enter, back) = (
+        keys.just_pressed(KeyCode::Escape),
+        keys.just_pressed(KeyCode::Return),
+        keys.just_pressed(KeyCode::Back),
+    );
+    keys.clear();
+    if escape {
+        draft.typing = false;
+        draft.text.clear();
+        return;
+    }
+    if enter {
+        draft.typing = false;
+        let text = std::mem::take(&mut draft.text);
+        let text = text.trim();
+        if !text.is_empty() {
+            send_chat(&mut socket, &mut lobby, text.to_string());
+        }
+        return;
+    }
+    if back {
+        draft.text.pop();
+    }
+    for c in typed.chars() {
+        if draft.text.chars().count() < MAX_CHAT_LENGTH {
+            draft.text.push(c);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_chat_box(
+    state: Res<State<GameState>>,
+    scoreboard: Res<Scoreboard>,
+    socket: Res<Option<PeerSocket>>,
+    lobby: Res<Lobby>,
+    draft: Res<ChatDraft>,
+    mut box_query: Query<&mut Style, With<ChatBox>>,
+    mut log_query: Query<&mut Text, (With<ChatLog>, Without<ChatPrompt>)>,
+    mut prompt_query: Query<&mut Text, With<ChatPrompt>>,
+) {
+    let key = chat_key(&state, &scoreboard).filter(|_| has_peers(&socket));
+    for mut style in box_query.iter_mut() {
+        show_node(&mut style, key.is_some());
+    }
+    if !lobby.is_changed() && !draft.is_changed() && !scoreboard.is_changed() {
+        return;
+    }
+
+    let lines: Vec<String> = lobby
+        .chat
+        .iter()
+        .skip(lobby.chat.len().saturating_sub(CHAT_LINES))
+        .map(|(peer, text)| match peer {
+            None => format!("You: {}", text),
+            Some(peer) => format!("{}: {}", lobby.peer_name(peer), filter_chat(text)),
+        })
+        .collect();
+    for mut text in log_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+    let prompt = match key {
+        _ if draft.typing => format!("> {}_", draft.text),
+        Some(KeyCode::Return) => "Press Enter to chat".to_string(),
+        _ => format!("Press {:?} to chat", GAME_OVER_CHAT_KEY),
+    };
+    for mut text in prompt_query.iter_mut() {
+        text.sections[0].value = prompt.clone();
+    }
+}
+
+fn cleanup_chat_box(
+    mut commands: Commands,
+    mut draft: ResMut<ChatDraft>,
+    box_query: Query<Entity, With<ChatBox>>,
+) {
+    *draft = default();
+    for entity in box_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+const CHAT_LINES: usize = 8;
+
+const GAME_OVER_CHAT_KEY: KeyCode = KeyCode::T;
+
+pub const MAX_CHAT_LENGTH: usize = 100;
+
+// only the obvious ones, matched as whole words
+const BLOCKED_WORDS: &[&str] = &["fuck", "shit", "cunt", "bitch", "asshole", "fag", "retard"];
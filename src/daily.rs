@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gameplay::{
+        seed_from_str, GameMode, MatchSettings, RoundState, Scoreboard, COUNTDOWN_FRAMES,
+        TICKS_PER_SECOND,
+    },
+    networking::LocalHandle,
+    settings::Settings,
+    GameState,
+};
+
+// One challenge a day, the same for everyone: a royale against bots with default settings and
+// a seed from the date, so spawns, pickups and every bot's dashing come out identical and
+// survival times can be compared. The steering setting has to match too.
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_in_stack_update(GameState::InGame).with_system(record_daily_time),
+        );
+    }
+}
+
+// The challenge being played, inserted with every session so it's None for anything else
+#[derive(Default)]
+pub struct Daily {
+    pub day: Option<u32>,
+    // seconds we lasted this attempt, once it's over for us
+    pub survived: Option<f32>,
+}
+
+// How we've done at today's challenge, it starts over with the next one
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DailyRecord {
+    pub day: u32,
+    pub attempts: u32,
+    pub best: Option<f32>,
+}
+
+impl DailyRecord {
+    pub fn best_on(&self, day: u32) -> Option<f32> {
+        self.best.filter(|_| self.day == day)
+    }
+
+    pub fn summary(&self, day: u32) -> Option<String> {
+        let best = self.best_on(day)?;
+        Some(format!(
+            "Daily challenge: best {:.1}s in {} attempts",
+            best, self.attempts
+        ))
+    }
+}
+
+pub fn daily_settings(day: u32) -> MatchSettings {
+    MatchSettings {
+        seed: seed_from_str(&format!("daily-{}", day)),
+        rounds: 1,
+        ..default()
+    }
+}
+
+// Days since 1970 in UTC, so the challenge changes over at the same moment everywhere
+#[cfg(not(target_arch = "wasm32"))]
+pub fn today() -> u32 {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_secs() / SECONDS_PER_DAY) as u32
+}
+
+// SystemTime isn't there in browsers, but the page's start time is
+#[cfg(target_arch = "wasm32")]
+pub fn today() -> u32 {
+    let millis = web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0., |performance| {
+            performance.time_origin() + performance.now()
+        });
+    (millis / 1000. / SECONDS_PER_DAY as f64) as u32
+}
+
+// From the end of the countdown until our crash, or until the round's over if we outlast
+// everyone. A rematch is another attempt at the same challenge.
+fn record_daily_time(
+    local: Res<LocalHandle>,
+    round: Res<RoundState>,
+    scoreboard: Res<Scoreboard>,
+    mut daily: ResMut<Daily>,
+    mut options: ResMut<Settings>,
+) {
+    let day = match daily.day {
+        Some(day) => day,
+        None => return,
+    };
+    if !scoreboard.match_over && round.deaths.is_empty() {
+        if daily.survived.is_some() {
+            daily.survived = None;
+        }
+        return;
+    }
+    if daily.survived.is_some() {
+        return;
+    }
+    let death = round.deaths.iter().find(|death| death.victim == local.0);
+    let frame = match death {
+        Some(death) => death.frame,
+        None if scoreboard.match_over => round.frame,
+        None => return,
+    };
+    let survived = frame.saturating_sub(COUNTDOWN_FRAMES) as f32 / TICKS_PER_SECOND as f32;
+    daily.survived = Some(survived);
+
+    let record = &mut options.daily;
+    if record.day != day {
+        *record = DailyRecord { day, ..default() };
+    }
+    record.attempts += 1;
+    record.best = Some(record.best.map_or(survived, |best| best.max(survived)));
+}
+
+pub const DAILY_MODE: GameMode = GameMode::Royale;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
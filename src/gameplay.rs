@@ -0,0 +1,2817 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    f32::consts::{FRAC_PI_2, PI, TAU},
+};
+
+use bevy::{
+    ecs::schedule::ShouldRun,
+    prelude::*,
+    reflect::{FromReflect, GetTypeRegistration},
+};
+use bevy_ggrs::*;
+use ggrs::InputStatus;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::Args,
+    input::{
+        input, INPUT_DASH, INPUT_EMOTE, INPUT_EMOTE_SHIFT, INPUT_FORFEIT, INPUT_LEFT,
+        INPUT_REMATCH, INPUT_RIGHT,
+    },
+    networking::{GgrsConfig, LocalHandle},
+    replay::{log_frame, replay_inputs, Replay},
+    tuning::Tuning,
+    GameState,
+};
+
+// The simulation itself: everything in here runs under rollback and has to stay deterministic
+pub struct GameplayPlugin;
+
+impl Plugin for GameplayPlugin {
+    fn build(&self, app: &mut App) {
+        register_rollback_types(GGRSPlugin::<GgrsConfig>::new())
+            // however fast we draw, so everyone plays at the same speed
+            .with_update_frequency(TICKS_PER_SECOND as usize)
+            .with_input_system(input)
+            .with_rollback_schedule(rollback_schedule())
+            .build(app);
+
+        let args = app.world.resource::<Args>();
+        let mode = args.mode.unwrap_or(GameMode::Duel);
+        let steering = args.steering.unwrap_or(Steering::Analog);
+
+        app.add_event::<DeathEvent>()
+            .add_event::<RoundEndEvent>()
+            .insert_resource(mode)
+            .insert_resource(steering)
+            .insert_resource(BotPlayers::default())
+            .insert_resource(Ghost {
+                replay: GhostReplay::load(),
+                ..default()
+            })
+            .insert_resource(Drill::default())
+            .insert_resource(Training::default())
+            .insert_resource(KillcamLog::default())
+            .insert_resource(Scoreboard::default())
+            .insert_resource(RoundState::default())
+            .insert_resource(MatchStats::default())
+            .insert_resource(TrailIndex::default())
+            .insert_resource(SimulationClock::default())
+            .insert_resource(FramesSimulated::default())
+            .insert_resource(CueLog::default())
+            .insert_resource(Emotes::default())
+            // GGRS advances its frames in a stage of its own just before Update, so this one
+            // lands after it and everything in Update sees where things ended up
+            .add_stage_before(
+                CoreStage::Update,
+                SYNC_TRANSFORMS_STAGE,
+                SystemStage::single_threaded().with_system(sync_transforms),
+            )
+            .add_startup_system(spawn_board)
+            .add_system_to_stage(CoreStage::PreUpdate, apply_match_arena)
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(spawn_ghost))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(emit_death_events)
+                    .with_system(emit_round_events),
+            );
+    }
+}
+
+// Everything simulated each frame, run by GGRS and by the Stepper
+pub fn rollback_schedule() -> Schedule {
+    Schedule::default()
+        .with_stage(
+            "ROLLBACK_STAGE",
+            SystemStage::single_threaded()
+                .with_run_criteria(training_frame)
+                .with_system(remember_positions)
+                .with_system(tick_round_clock.after(remember_positions))
+                // trails only shrink before the index is built and only grow after
+                // it's used, so the indices in it stay good for the whole frame
+                .with_system(kill_trail.after(tick_round_clock))
+                .with_system(index_trails.after(kill_trail))
+                .with_system(steer_bots.after(index_trails))
+                .with_system(play_drill.after(steer_bots))
+                .with_system(
+                    replay_inputs
+                        .after(tick_round_clock)
+                        .after(steer_bots)
+                        .after(play_drill),
+                )
+                .with_system(record_drill.after(replay_inputs))
+                // deaths despawn players with commands, so theirs go after the power up
+                // inserts on the same players or those would land on nothing
+                .with_system(forfeit.after(replay_inputs).after(collect_power_ups))
+                .with_system(rotate_players.after(replay_inputs))
+                .with_system(move_players_forward.after(rotate_players))
+                .with_system(spawn_trail.after(move_players_forward))
+                .with_system(spawn_power_ups.after(tick_round_clock))
+                .with_system(
+                    collect_power_ups
+                        .after(move_players_forward)
+                        .after(spawn_power_ups),
+                )
+                .with_system(border_death.after(spawn_trail).after(collect_power_ups))
+                .with_system(trail_death.after(border_death).after(index_trails))
+                .with_system(collect_pellets.after(move_players_forward))
+                .with_system(track_stats.after(trail_death))
+                .with_system(receive_emotes.after(replay_inputs))
+                .with_system(record_ghost.after(replay_inputs))
+                .with_system(move_ghost.after(tick_round_clock)),
+        )
+        // deaths are despawned with commands, so count survivors in a later stage
+        .with_stage_after(
+            "ROLLBACK_STAGE",
+            "ROUND_STAGE",
+            SystemStage::single_threaded()
+                .with_run_criteria(training_frame)
+                .with_system(accrue_survival_time)
+                .with_system(accrue_hill_time)
+                .with_system(respawn_players)
+                .with_system(record_killcam)
+                .with_system(
+                    check_round_end
+                        .after(accrue_survival_time)
+                        .after(accrue_hill_time)
+                        .after(respawn_players)
+                        .after(record_killcam),
+                )
+                .with_system(vote_rematch.after(check_round_end)),
+        )
+        // after the round stage's commands, so the checksum sees this frame's spawns
+        .with_stage_after(
+            "ROUND_STAGE",
+            "LOG_STAGE",
+            SystemStage::single_threaded()
+                .with_run_criteria(training_frame)
+                .with_system(log_frame),
+        )
+        // whether to simulate at all, for the stages after it to check
+        .with_stage_before(
+            "ROLLBACK_STAGE",
+            "TRAINING_STAGE",
+            SystemStage::single_threaded().with_system(decide_training_frame),
+        )
+}
+
+// Something rollback state can be registered with, so GGRS and the Stepper save the same things
+pub trait RollbackTypes {
+    fn register<T: GetTypeRegistration + Reflect + Default + Component>(self) -> Self;
+}
+
+impl RollbackTypes for GGRSPlugin<GgrsConfig> {
+    fn register<T: GetTypeRegistration + Reflect + Default + Component>(self) -> Self {
+        self.register_rollback_type::<T>()
+    }
+}
+
+// Anything holding a Vec is reflected as a value. Restoring a snapshot applies it over what's
+// there, and applying a list only ever grows it, so deaths and trail points from frames that
+// got rolled back would stay behind.
+pub fn register_rollback_types<R: RollbackTypes>(types: R) -> R {
+    types
+        .register::<Player>()
+        .register::<Position>()
+        .register::<Heading>()
+        .register::<PreviousPosition>()
+        .register::<TrailSpawner>()
+        .register::<Trail>()
+        .register::<PowerUp>()
+        .register::<Pellet>()
+        .register::<Shield>()
+        .register::<Slowed>()
+        .register::<InputHistory>()
+        .register::<DashEnergy>()
+        .register::<BotMemory>()
+        .register::<GhostCycle>()
+        .register::<Scoreboard>()
+        .register::<RoundState>()
+        .register::<MatchStats>()
+        .register::<Emotes>()
+        .register::<SimulationClock>()
+}
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct Player {
+    pub handle: usize,
+}
+
+// Where something is as far as the simulation knows. Transform is only for drawing, and
+// gets copied over from this outside of rollback by sync_transforms.
+#[derive(Component, Clone, Copy, Default, Reflect)]
+pub struct Position(pub Vec2);
+
+// Which way a cycle faces, in radians counter-clockwise from +x and kept within 0..TAU
+#[derive(Component, Clone, Copy, Default, Reflect)]
+pub struct Heading(pub f32);
+
+impl Heading {
+    pub fn new(angle: f32) -> Self {
+        Self(angle.rem_euclid(TAU))
+    }
+
+    pub fn direction(&self) -> Vec2 {
+        Vec2::new(self.0.cos(), self.0.sin())
+    }
+
+    pub fn turn(&mut self, angle: f32) {
+        self.0 = (self.0 + angle).rem_euclid(TAU);
+    }
+}
+
+// Where something that moves was as the frame started, so what's drawn can be blended from
+// there instead of stepping along 60 times a second
+#[derive(Component, Clone, Copy, Default, Reflect)]
+pub struct PreviousPosition {
+    pub position: Vec2,
+    pub heading: f32,
+}
+
+// How far what's drawn is from the blend, usually after a rollback moved something out from
+// under it. Eased away over a few frames rather than showing up all at once. Only ever
+// touched outside of rollback.
+#[derive(Component, Default)]
+pub struct Smoothing {
+    pub offset: Vec2,
+    pub rotation: Quat,
+}
+
+#[derive(Component)]
+pub struct Arena;
+
+// The king-of-the-hill scoring circle in the middle of the arena
+#[derive(Component)]
+pub struct Hill;
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct Pellet;
+
+// A ring sector of the arena that speeds up anyone driving through it
+#[derive(Component)]
+pub struct BoostZone {
+    pub start_angle: f32,
+    pub end_angle: f32,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+}
+
+impl BoostZone {
+    pub fn contains(&self, point: Vec2) -> bool {
+        let distance = point.length();
+        let angle = point.y.atan2(point.x).rem_euclid(TAU);
+        distance >= self.inner_radius
+            && distance <= self.outer_radius
+            && angle >= self.start_angle
+            && angle <= self.end_angle
+    }
+}
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct Shield;
+
+// Movement penalty handed to every opponent of whoever grabbed a slow power-up
+#[derive(Component, Default, Reflect, Hash)]
+pub struct Slowed {
+    pub frames_left: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Emote {
+    Gg,
+    Nice,
+    Oops,
+    Taunt,
+}
+
+impl Emote {
+    // clockwise from the top of the wheel
+    pub const ALL: [Emote; 4] = [Emote::Gg, Emote::Nice, Emote::Oops, Emote::Taunt];
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            Emote::Gg => "gg",
+            Emote::Nice => "Nice!",
+            Emote::Oops => "Oops",
+            Emote::Taunt => "Catch me!",
+        }
+    }
+
+    pub fn key(&self) -> KeyCode {
+        match self {
+            Emote::Gg => KeyCode::Key1,
+            Emote::Nice => KeyCode::Key2,
+            Emote::Oops => KeyCode::Key3,
+            Emote::Taunt => KeyCode::Key4,
+        }
+    }
+
+    // 1-based, so no emote bits means no emote
+    pub fn to_input(self) -> u8 {
+        (self as u8 + 1) << INPUT_EMOTE_SHIFT
+    }
+
+    pub fn from_input(input: u8) -> Option<Emote> {
+        let index = ((input & INPUT_EMOTE) >> INPUT_EMOTE_SHIFT) as usize;
+        Self::ALL.get(index.checked_sub(1)?).copied()
+    }
+}
+
+// Dashing burns energy that only recharges while you're not holding dash
+#[derive(Component, Reflect, Hash)]
+pub struct DashEnergy {
+    pub energy: u32,
+    // whether the last frame dashed, for the effects
+    pub dashing: bool,
+}
+
+impl Default for DashEnergy {
+    fn default() -> Self {
+        Self {
+            energy: DASH_ENERGY,
+            dashing: false,
+        }
+    }
+}
+
+impl DashEnergy {
+    // whether this frame's input actually gets to dash
+    pub fn dash(&mut self, input: u8) -> bool {
+        self.dashing = false;
+        if input & INPUT_DASH == 0 {
+            self.energy = (self.energy + 1).min(DASH_ENERGY);
+            return false;
+        }
+        if self.energy < DASH_DRAIN {
+            return false;
+        }
+        self.energy -= DASH_DRAIN;
+        self.dashing = true;
+        true
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.energy as f32 / DASH_ENERGY as f32
+    }
+}
+
+// Handles driven by the bot instead of a person, identical on every peer
+#[derive(Default)]
+pub struct BotPlayers(pub Vec<usize>);
+
+// A finished single-player run, replayed as a ghost in later rounds
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GhostReplay {
+    pub spawn_position: [f32; 2],
+    pub spawn_rotation: f32,
+    // local inputs indexed by round frame, ends when the run died
+    pub inputs: Vec<u8>,
+}
+
+impl GhostReplay {
+    pub fn survival_seconds(&self) -> f32 {
+        self.inputs.len().saturating_sub(COUNTDOWN_FRAMES as usize) as f32 / TICKS_PER_SECOND as f32
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Option<Self> {
+        let bytes = std::fs::read(GHOST_FILE).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+
+    // there's no filesystem in the browser, so ghosts only last until the tab closes
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Option<Self> {
+        None
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) {
+        let bytes = bincode::serialize(self).expect("failed to serialize ghost");
+        if let Err(e) = std::fs::write(GHOST_FILE, bytes) {
+            warn!("failed to save ghost: {}", e);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) {}
+}
+
+#[derive(Default)]
+pub struct Ghost {
+    // what's being replayed this round
+    pub replay: Option<GhostReplay>,
+    // what's being recorded this round
+    pub recording: GhostReplay,
+    pub recording_round: u32,
+}
+
+// Offline training, where the simulation can be frozen and stepped a frame at a time. GGRS
+// keeps asking for frames regardless, so while paused the rollback stages just don't run,
+// which is only safe in local matches that never roll back.
+#[derive(Default)]
+pub struct Training {
+    pub enabled: bool,
+    pub paused: bool,
+    // frames still to simulate while paused
+    pub steps: u32,
+    // decided once per GGRS frame, before any stage that checks it
+    pub simulate: bool,
+}
+
+// A stretch of our own driving recorded in a local match, which the first bot then drives
+// from the same spot every round, laying a real trail to practice cutting off
+#[derive(Default)]
+pub struct Drill {
+    // None while we aren't recording
+    pub recording: Option<DrillSnippet>,
+    pub snippet: Option<DrillSnippet>,
+}
+
+impl Drill {
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            if recording.start.is_some() {
+                self.snippet = Some(recording);
+            }
+        }
+    }
+}
+
+// The last few seconds of every cycle by SimulationClock::frame, for the killcam. Kept outside
+// the rollback like the replay log, so re-simulated frames just overwrite themselves.
+#[derive(Default)]
+pub struct KillcamLog {
+    pub frames: VecDeque<KillcamFrame>,
+}
+
+#[derive(Clone, Default)]
+pub struct KillcamFrame {
+    pub frame: u32,
+    // (handle, position, heading) of everyone alive at the end of the frame
+    pub cycles: Vec<(usize, Vec2, f32)>,
+    // every trail by handle, only on frames someone died since they're big
+    pub trails: Option<Vec<(usize, Vec<TrailPoint>)>>,
+}
+
+#[derive(Clone, Default)]
+pub struct DrillSnippet {
+    // (position, heading) when recording started, None until a frame has been recorded
+    pub start: Option<(Vec2, f32)>,
+    // played from the end of the countdown
+    pub inputs: Vec<u8>,
+}
+
+impl DrillSnippet {
+    pub fn seconds(&self) -> f32 {
+        self.inputs.len() as f32 / TICKS_PER_SECOND as f32
+    }
+}
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct GhostCycle {
+    pub previous_input: u8,
+    pub dash: DashEnergy,
+}
+
+// Decisions a bot has made but not acted on yet, which is how slower bots react late
+#[derive(Component, Clone, Default, Reflect, Hash)]
+#[reflect_value(Component)]
+pub struct BotMemory {
+    pub pending: Vec<u8>,
+}
+
+// Last frame's input, so grid steering can react to presses rather than holds
+#[derive(Component, Default, Reflect, Hash)]
+pub struct InputHistory {
+    pub previous: u8,
+}
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct TrailSpawner {
+    pub timer: FrameTimer,
+    // frames left on the wide trail power-up
+    pub wide_frames_left: u32,
+}
+
+impl TrailSpawner {
+    pub fn trail_size(&self) -> f32 {
+        if self.wide_frames_left > 0 {
+            WIDE_TRAIL_SIZE
+        } else {
+            TRAIL_SIZE
+        }
+    }
+}
+
+// Everything one handle has laid as a polyline, oldest first. Just the data for collisions,
+// see TrailRibbon for how it's drawn.
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect_value(Component)]
+pub struct Trail {
+    pub player_handle: usize,
+    pub points: Vec<TrailPoint>,
+}
+
+#[derive(Clone, Copy, Default, Reflect, FromReflect)]
+pub struct TrailPoint {
+    pub position: Vec2,
+    pub heading: Vec2,
+    pub size: f32,
+    // SimulationClock::frame it was laid on
+    pub frame: u32,
+    // the point is gone when this runs out, or straight away when it's burned
+    pub frames_left: u32,
+    // laid while dashing, which draws it brighter
+    pub dashed: bool,
+    // joined to the point before it, false after a gap like a respawn or a shield burn
+    pub connected: bool,
+}
+
+impl Trail {
+    // Each point owns the stretch back to the point before it, or is just a dot if it
+    // starts a new run
+    pub fn segment(&self, i: usize) -> (Vec2, Vec2) {
+        let point = self.points[i];
+        match i.checked_sub(1).map(|previous| self.points[previous]) {
+            Some(previous) if point.connected && previous.frames_left > 0 => {
+                (previous.position, point.position)
+            }
+            _ => (point.position, point.position),
+        }
+    }
+
+    pub fn distance(&self, i: usize, position: Vec2) -> f32 {
+        let (start, end) = self.segment(i);
+        let along = end - start;
+        let t = if along.length_squared() > 0. {
+            ((position - start).dot(along) / along.length_squared()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        position.distance(start + along * t)
+    }
+
+    // Whether a cycle reaching this far from its center is touching point i's stretch. The
+    // newest few points of your own trail never count, or cutting a corner would clip the
+    // trail behind you.
+    pub fn touches(&self, i: usize, handle: usize, position: Vec2, reach: f32) -> bool {
+        if self.points[i].frames_left == 0 {
+            return false;
+        }
+        if handle == self.player_handle && i + OWN_TRAIL_GRACE_POINTS >= self.points.len() {
+            return false;
+        }
+        self.distance(i, position) < reach + self.points[i].size / 2.
+    }
+
+    // Burned points stop counting right away but stay put until kill_trail, so indices
+    // into the trail hold for the rest of the frame
+    pub fn burn(&mut self, i: usize) {
+        self.points[i].frames_left = 0;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash, Reflect)]
+#[reflect_value(PartialEq, Hash)]
+pub enum PowerUpKind {
+    #[default]
+    WideTrail,
+    Shield,
+    Slow,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 3] = [
+        PowerUpKind::WideTrail,
+        PowerUpKind::Shield,
+        PowerUpKind::Slow,
+    ];
+
+    pub fn color(&self) -> Color {
+        match self {
+            PowerUpKind::WideTrail => Color::WHITE,
+            PowerUpKind::Shield => Color::CYAN,
+            PowerUpKind::Slow => Color::MAROON,
+        }
+    }
+}
+
+#[derive(Component, Default, Reflect, Hash)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+}
+
+#[derive(Component, Clone, Default, Reflect, Hash)]
+#[reflect_value(Resource)]
+pub struct Scoreboard {
+    // round 0 means the first round hasn't been spawned yet
+    pub round: u32,
+    pub scores: Vec<u32>,
+    // per handle, suicides don't count
+    pub kills: Vec<u32>,
+    // set once the match is decided, either by the clock or by the last round
+    pub match_over: bool,
+    // by handle, collected on the game-over screen
+    pub rematch_votes: Vec<bool>,
+    // frame the rematch starts anyway, 0 until the match is over
+    pub rematch_deadline: u32,
+}
+
+impl Scoreboard {
+    pub fn leader(&self) -> Option<usize> {
+        let best = self.scores.iter().max()?;
+        self.scores.iter().position(|score| score == best)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash, Reflect, FromReflect)]
+#[reflect_value(PartialEq, Hash)]
+pub enum DeathCause {
+    #[default]
+    Border,
+    Trail,
+    // gave up from the pause menu
+    Forfeit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash, Reflect, FromReflect)]
+pub struct DeathEvent {
+    pub victim: usize,
+    // same as the victim for suicides
+    pub killer: usize,
+    pub cause: DeathCause,
+    pub frame: u32,
+}
+
+impl DeathEvent {
+    pub fn is_suicide(&self) -> bool {
+        self.killer == self.victim
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Reflect, FromReflect)]
+pub struct PlayerStats {
+    pub distance: f32,
+    pub dash_frames: u32,
+    pub trails_laid: u32,
+    pub near_misses: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    // only meaningful once deaths is nonzero
+    pub last_death: DeathEvent,
+    // whether an enemy trail was close enough to count as a near miss last frame
+    pub near_trail: bool,
+}
+
+// Running totals for the post-game screen, kept for the whole match rather than per round
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect_value(Resource)]
+pub struct MatchStats {
+    // by handle
+    pub players: Vec<PlayerStats>,
+}
+
+impl MatchStats {
+    pub fn player(&mut self, handle: usize) -> &mut PlayerStats {
+        if self.players.len() <= handle {
+            self.players.resize(handle + 1, default());
+        }
+        &mut self.players[handle]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, Reflect, FromReflect)]
+pub struct ShownEmote {
+    // input bits of the emote, 0 for none
+    pub emote: u8,
+    pub frames_left: u32,
+}
+
+// Emotes arrive with the inputs, so they're part of the simulation like everything else
+#[derive(Component, Clone, Default, Reflect, Hash)]
+#[reflect_value(Resource)]
+pub struct Emotes {
+    // by handle
+    pub shown: Vec<ShownEmote>,
+}
+
+// Trail stretches bucketed by cell, so collision checks only look at the ones nearby. It's
+// rebuilt at the start of every simulated frame, so there's nothing in it to roll back.
+#[derive(Default)]
+pub struct TrailIndex {
+    // (trail entity, point index) for every stretch overlapping the cell
+    pub cells: HashMap<(i32, i32), Vec<(Entity, usize)>>,
+}
+
+impl TrailIndex {
+    pub fn cell(position: Vec2) -> (i32, i32) {
+        let cell = (position / TRAIL_CELL_SIZE).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    pub fn insert(&mut self, entity: Entity, i: usize, (start, end): (Vec2, Vec2)) {
+        let (min_x, min_y) = TrailIndex::cell(start.min(end));
+        let (max_x, max_y) = TrailIndex::cell(start.max(end));
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.cells.entry((x, y)).or_default().push((entity, i));
+            }
+        }
+    }
+
+    // every stretch within reach of position, and maybe a few more, possibly twice
+    pub fn near(&self, position: Vec2, reach: f32) -> impl Iterator<Item = (Entity, usize)> + '_ {
+        let (min_x, min_y) = TrailIndex::cell(position - Vec2::splat(reach));
+        let (max_x, max_y) = TrailIndex::cell(position + Vec2::splat(reach));
+        (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+// Frames simulated since the session started, unlike RoundState this never resets
+#[derive(Component, Default, Reflect, Hash)]
+#[reflect(Resource)]
+pub struct SimulationClock {
+    pub frame: u32,
+}
+
+// Every frame simulated, re-simulations included. Not rolled back, so whoever's watching can
+// tell how much rolling back went on.
+#[derive(Default)]
+pub struct FramesSimulated(pub u32);
+
+// Moments worth a sound or a shake. The simulation only notes them, what they sound and
+// look like is up to the sound and rendering plugins.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cue {
+    CountdownBeep,
+    Go,
+    PowerUp { handle: usize, position: Vec2 },
+    Death { death: DeathEvent, position: Vec2 },
+    NearMiss(usize),
+}
+
+// Rollback systems note cues in here, which is deliberately not rolled back. Simulating a
+// frame again replaces whatever an earlier go at it noted, so readers see every frame as it
+// was last simulated and decide for themselves whether to wait for it to be confirmed.
+#[derive(Default)]
+pub struct CueLog {
+    // SimulationClock::frame of the frame being simulated
+    pub frame: u32,
+    // with the frame they happened on, oldest first
+    pub cues: Vec<(u32, Cue)>,
+}
+
+impl CueLog {
+    pub fn start_frame(&mut self, frame: u32) {
+        self.frame = frame;
+        self.cues
+            .retain(|(queued, _)| *queued < frame && *queued + CUE_HISTORY_FRAMES >= frame);
+    }
+
+    pub fn push(&mut self, cue: Cue) {
+        self.cues.push((self.frame, cue));
+    }
+}
+
+// Sent outside the simulation once the scores say a round is over
+pub struct RoundEndEvent {
+    // None for a draw
+    pub winner: Option<usize>,
+}
+
+#[derive(Component, Clone, Default, Reflect, Hash)]
+#[reflect_value(Resource)]
+pub struct RoundState {
+    // frames simulated since the round started
+    pub frame: u32,
+    // in the order they happened this round
+    pub deaths: Vec<DeathEvent>,
+    // frame sudden death kicks in, 0 for modes without overtime
+    pub overtime_frame: u32,
+    // frame of the first death in overtime, 0 while sudden death is still on
+    pub overtime_end_frame: u32,
+}
+
+impl RoundState {
+    // players sit still until the countdown runs out
+    pub fn in_countdown(&self) -> bool {
+        self.frame <= COUNTDOWN_FRAMES
+    }
+
+    // arena closing in fast and trails stuck in place, until the first death in overtime
+    pub fn sudden_death(&self) -> bool {
+        self.overtime_frame != 0 && self.frame > self.overtime_frame && self.overtime_end_frame == 0
+    }
+
+    pub fn overtime_frames(&self) -> u32 {
+        if self.overtime_frame == 0 {
+            return 0;
+        }
+        let end = match self.overtime_end_frame {
+            0 => self.frame,
+            frame => frame,
+        };
+        end.saturating_sub(self.overtime_frame)
+    }
+}
+
+#[derive(Default, Reflect, Hash)]
+pub struct FrameTimer {
+    pub frames_left: u32,
+    pub reset_to: u32,
+}
+
+impl FrameTimer {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames_left: frames,
+            reset_to: frames,
+        }
+    }
+
+    pub fn tick(&mut self) -> &Self {
+        self.frames_left -= 1;
+        if self.frames_left == 0 {
+            self.frames_left = self.reset_to;
+        }
+        self
+    }
+
+    pub fn finished(&self) -> bool {
+        // todo: sloppy
+        self.frames_left == self.reset_to
+    }
+}
+
+// default for MatchSettings
+const ROUNDS: u32 = 5;
+
+// what the lobby host can pick from, 0 being endless for both
+const TRAIL_LENGTHS: [u32; 5] = [40, 80, 160, 320, 0];
+
+const ROUND_COUNTS: [u32; 5] = [1, 3, 5, 7, 0];
+
+const SPEED_SCALES: [f32; 4] = [0.75, 1., 1.25, 1.5];
+
+const ARENA_SIZES: [(f32, &str); 3] = [(7., "Small"), (9., "Normal"), (12., "Large")];
+
+// slower cycles first, then longer trails on top
+const HANDICAP_STEPS: [Handicap; 5] = [
+    Handicap {
+        move_speed: 1.,
+        trail_length: 1.,
+    },
+    Handicap {
+        move_speed: 0.9,
+        trail_length: 1.,
+    },
+    Handicap {
+        move_speed: 0.8,
+        trail_length: 1.,
+    },
+    Handicap {
+        move_speed: 0.9,
+        trail_length: 1.5,
+    },
+    Handicap {
+        move_speed: 0.8,
+        trail_length: 2.,
+    },
+];
+
+// the most a lobby will accept, endless aside
+const MAX_ROUNDS: u32 = 15;
+
+// what a lobby will accept, in units per frame and radians per frame
+const MOVE_SPEEDS: std::ops::RangeInclusive<f32> = 0.005..=0.15;
+
+const TURN_SPEEDS: std::ops::RangeInclusive<f32> = 0.02..=0.6;
+
+const BOARD_SIZES: std::ops::RangeInclusive<f32> = 4.0..=24.0;
+
+const PLAYER_SIZES: std::ops::RangeInclusive<f32> = 0.25..=1.5;
+
+// range of either handicap multiplier a lobby will accept
+const HANDICAPS: std::ops::RangeInclusive<f32> = 0.25..=4.;
+
+const MIN_TRAIL_LENGTH: u32 = 10;
+
+const MAX_TRAIL_LENGTH: u32 = 60 * 60;
+
+const TRAIL_SIZE: f32 = 0.2;
+
+pub const WIDE_TRAIL_SIZE: f32 = 0.45;
+
+const WIDE_TRAIL_FRAMES: u32 = 300;
+
+const TRAIL_CELL_SIZE: f32 = 1.;
+
+const OWN_TRAIL_GRACE_POINTS: usize = 3;
+
+pub const POWER_UP_SIZE: f32 = 0.4;
+
+const POWER_UP_INTERVAL: u32 = 240;
+
+const MAX_POWER_UPS: usize = 3;
+
+const SLOW_FRAMES: u32 = 180;
+
+const SLOW_MULTIPLIER: f32 = 0.6;
+
+const GRID_SIZE: f32 = 0.25;
+
+// a segment is laid this often, so a longer gap between two is a hole in the trail
+const TRAIL_SPAWN_FRAMES: u32 = 2;
+
+pub const COUNTDOWN_FRAMES: u32 = 180;
+
+// 45 seconds after the countdown
+const OVERTIME_FRAME: u32 = COUNTDOWN_FRAMES + 45 * 60;
+
+const OVERTIME_SHRINK_SPEED: f32 = 0.01;
+
+// two minutes after the countdown
+pub const SURVIVAL_MATCH_FRAMES: u32 = COUNTDOWN_FRAMES + 120 * 60;
+
+const RESPAWN_FRAMES: u32 = 120;
+
+pub const HILL_RADIUS: f32 = 1.25;
+
+// frames spent on the hill needed to win
+const HILL_WIN_FRAMES: u32 = 30 * 60;
+
+pub const PELLET_SIZE: f32 = 0.2;
+
+const PELLET_COUNT: u32 = 40;
+
+const GHOST_FILE: &str = "ghost.replay";
+
+const DRILL_MAX_FRAMES: usize = TICKS_PER_SECOND as usize * 10;
+
+// a few seconds more than the killcam shows, for the end of the round to be confirmed in
+const KILLCAM_LOG_FRAMES: usize = TICKS_PER_SECOND as usize * 5;
+
+const BOT_PROBES: u32 = 6;
+
+// how far off the current heading the bot looks when deciding to turn
+const BOT_SWERVE_ANGLE: f32 = 0.6;
+
+pub const ROYALE_PLAYERS: usize = 8;
+
+const ROYALE_BOARD_SIZE: f32 = 16.0;
+
+const ROYALE_MIN_BOARD_SIZE: f32 = 4.0;
+
+// the arena holds still for 5 seconds, then closes in
+const ROYALE_SHRINK_DELAY: u32 = 300;
+
+const ROYALE_SHRINK_SPEED: f32 = 0.004;
+
+// extra gap around the collision distance that still counts as a close call
+const NEAR_MISS_MARGIN: f32 = 0.15;
+
+const REMATCH_TIMEOUT_FRAMES: u32 = 30 * 60;
+
+const EMOTE_FRAMES: u32 = 120;
+
+const BOOST_MULTIPLIER: f32 = 1.5;
+
+// (center angle, angular width) of each boost sector
+const BOOST_SECTORS: [(f32, f32); 4] = [
+    (TAU / 8., 0.5),
+    (3. * TAU / 8., 0.5),
+    (5. * TAU / 8., 0.5),
+    (7. * TAU / 8., 0.5),
+];
+
+// 90 frames of dashing from full, and twice that to recharge
+const DASH_ENERGY: u32 = 180;
+
+pub const DASH_DRAIN: u32 = 2;
+
+// Simulated frames a second, whatever the display runs at. Frame counts like 45 * 60 above
+// are written in seconds at this rate.
+pub const TICKS_PER_SECOND: u32 = 60;
+
+const FRAME_SECONDS: f32 = 1. / TICKS_PER_SECOND as f32;
+
+// what's left of a correction after a second of easing it away
+const CORRECTION_DECAY: f32 = 0.001;
+
+// further than this in a frame is a respawn or a restart, not movement
+const SNAP_DISTANCE: f32 = 1.;
+
+const SYNC_TRANSFORMS_STAGE: &str = "SYNC_TRANSFORMS_STAGE";
+
+// way past anything GGRS will roll back
+const CUE_HISTORY_FRAMES: u32 = 120;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash, Serialize, Deserialize)]
+pub enum GameMode {
+    Duel,
+    // 2v2, even handles against odd handles
+    Teams,
+    // free-for-all on a shrinking arena, scored by placement
+    Royale,
+    // one long match with respawns, scored by frames spent alive
+    Survival,
+    // one long match with respawns, first to hold the center long enough wins
+    Hill,
+    // one round of collecting pellets, over once they're gone or someone dies
+    Pellets,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash, Serialize, Deserialize)]
+pub enum Steering {
+    // hold left/right to turn gradually
+    Analog,
+    // tap left/right for an instant 90 degree turn on the grid
+    Grid,
+}
+
+impl std::str::FromStr for Steering {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "analog" => Ok(Steering::Analog),
+            "grid" => Ok(Steering::Grid),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Steering {
+    pub fn room_suffix(&self) -> &'static str {
+        match self {
+            Steering::Analog => "",
+            Steering::Grid => "_grid",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BotDifficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub fn lookahead(&self) -> f32 {
+        match self {
+            BotDifficulty::Easy => 0.8,
+            BotDifficulty::Medium => 1.5,
+            BotDifficulty::Hard => 2.5,
+        }
+    }
+
+    pub fn reaction_frames(&self) -> usize {
+        match self {
+            BotDifficulty::Easy => 12,
+            BotDifficulty::Medium => 6,
+            BotDifficulty::Hard => 0,
+        }
+    }
+
+    // chance out of 100 to dash on any frame with a clear road ahead
+    pub fn dash_percent(&self) -> u32 {
+        match self {
+            BotDifficulty::Easy => 0,
+            BotDifficulty::Medium => 10,
+            BotDifficulty::Hard => 60,
+        }
+    }
+}
+
+impl std::str::FromStr for GameMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "duel" => Ok(GameMode::Duel),
+            "teams" => Ok(GameMode::Teams),
+            "royale" => Ok(GameMode::Royale),
+            "survival" => Ok(GameMode::Survival),
+            "hill" => Ok(GameMode::Hill),
+            "pellets" => Ok(GameMode::Pellets),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::str::FromStr for BotDifficulty {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(BotDifficulty::Easy),
+            "medium" => Ok(BotDifficulty::Medium),
+            "hard" => Ok(BotDifficulty::Hard),
+            _ => Err(()),
+        }
+    }
+}
+
+// Evens out a match between players of different skill, multiplies that player's settings
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Handicap {
+    pub move_speed: f32,
+    pub trail_length: f32,
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Self {
+            move_speed: 1.,
+            trail_length: 1.,
+        }
+    }
+}
+
+// "speed/trail", e.g. "0.9/1.5" for 10% slower with half again as much trail
+impl std::str::FromStr for Handicap {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (move_speed, trail_length) = s.split_once('/').ok_or(())?;
+        let handicap = Self {
+            move_speed: move_speed.trim().parse().map_err(|_| ())?,
+            trail_length: trail_length.trim().parse().map_err(|_| ())?,
+        };
+        if handicap.move_speed <= 0. || handicap.trail_length <= 0. {
+            return Err(());
+        }
+        Ok(handicap)
+    }
+}
+
+// Tuning knobs agreed on in the lobby, fixed for the rest of the session
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchSettings {
+    pub move_speed: f32,
+    pub turn_speed: f32,
+    // frames a trail segment sticks around, 0 means they never fade
+    pub trail_length: u32,
+    // shared source of randomness for spawns and pickups
+    pub seed: u32,
+    // rounds in a match for modes that play in rounds, 0 keeps going forever
+    pub rounds: u32,
+    pub bot_difficulty: BotDifficulty,
+    // indexed by handle, big enough for the largest mode
+    pub handicaps: [Handicap; ROYALE_PLAYERS],
+    // across the arena, for the modes that don't bring their own size
+    pub board_size: f32,
+    // across a cycle's hitbox
+    pub player_size: f32,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        let tuning = Tuning::default();
+        Self {
+            move_speed: tuning.move_speed,
+            turn_speed: tuning.turn_speed,
+            trail_length: tuning.trail_length,
+            seed: 0,
+            rounds: ROUNDS,
+            bot_difficulty: BotDifficulty::Medium,
+            handicaps: [Handicap::default(); ROYALE_PLAYERS],
+            board_size: tuning.board_size,
+            player_size: tuning.player_size,
+        }
+    }
+}
+
+impl MatchSettings {
+    // the command line wins over tuning.json
+    pub fn from_args(args: &Args, tuning: &Tuning) -> Self {
+        let default = Self::default();
+        let mut handicaps = default.handicaps;
+        for (handicap, arg) in handicaps.iter_mut().zip(&args.handicaps) {
+            *handicap = *arg;
+        }
+        Self {
+            move_speed: args.move_speed.unwrap_or(tuning.move_speed),
+            turn_speed: args.turn_speed.unwrap_or(tuning.turn_speed),
+            trail_length: match args.trail_length.unwrap_or(tuning.trail_length) {
+                0 => 0,
+                frames => frames.clamp(MIN_TRAIL_LENGTH, MAX_TRAIL_LENGTH),
+            },
+            seed: args.seed.unwrap_or(default.seed),
+            rounds: args.rounds.unwrap_or(default.rounds),
+            bot_difficulty: args.bot_difficulty.unwrap_or(default.bot_difficulty),
+            handicaps,
+            board_size: args.board_size.unwrap_or(tuning.board_size),
+            player_size: args.player_size.unwrap_or(tuning.player_size),
+        }
+    }
+
+    // The lobby host cycles through these. Speeds are relative to tuning.json, so a faster
+    // match still turns in the same circles.
+    pub fn speed_scale(&self, tuning: &Tuning) -> f32 {
+        self.move_speed / tuning.move_speed
+    }
+
+    pub fn next_speed_scale(&self, tuning: &Tuning) -> f32 {
+        let scale = self.speed_scale(tuning);
+        let i = SPEED_SCALES
+            .iter()
+            .position(|s| (s - scale).abs() < 0.01)
+            .map_or(0, |i| i + 1);
+        SPEED_SCALES[i % SPEED_SCALES.len()]
+    }
+
+    pub fn next_trail_length(&self) -> u32 {
+        let i = TRAIL_LENGTHS
+            .iter()
+            .position(|length| *length == self.trail_length)
+            .map_or(0, |i| i + 1);
+        TRAIL_LENGTHS[i % TRAIL_LENGTHS.len()]
+    }
+
+    pub fn next_rounds(&self) -> u32 {
+        let i = ROUND_COUNTS
+            .iter()
+            .position(|rounds| *rounds == self.rounds)
+            .map_or(0, |i| i + 1);
+        ROUND_COUNTS[i % ROUND_COUNTS.len()]
+    }
+
+    pub fn next_board_size(&self) -> f32 {
+        let i = ARENA_SIZES
+            .iter()
+            .position(|(size, _)| *size == self.board_size)
+            .map_or(0, |i| i + 1);
+        ARENA_SIZES[i % ARENA_SIZES.len()].0
+    }
+
+    pub fn next_handicap(&self, handle: usize) -> Handicap {
+        let i = HANDICAP_STEPS
+            .iter()
+            .position(|handicap| *handicap == self.handicaps[handle])
+            .map_or(0, |i| i + 1);
+        HANDICAP_STEPS[i % HANDICAP_STEPS.len()]
+    }
+
+    pub fn arena_name(&self) -> &'static str {
+        ARENA_SIZES
+            .iter()
+            .find(|(size, _)| *size == self.board_size)
+            .map_or("Custom", |(_, name)| *name)
+    }
+
+    // Sane for any tuning.json or command line, not just what our own lobby buttons go
+    // through, since the host's can be set up differently
+    pub fn in_range(&self) -> bool {
+        MOVE_SPEEDS.contains(&self.move_speed)
+            && TURN_SPEEDS.contains(&self.turn_speed)
+            && (self.trail_length == 0
+                || (MIN_TRAIL_LENGTH..=MAX_TRAIL_LENGTH).contains(&self.trail_length))
+            && self.rounds <= MAX_ROUNDS
+            && BOARD_SIZES.contains(&self.board_size)
+            && PLAYER_SIZES.contains(&self.player_size)
+            && self.handicaps.iter().all(|handicap| {
+                HANDICAPS.contains(&handicap.move_speed)
+                    && HANDICAPS.contains(&handicap.trail_length)
+            })
+    }
+
+    pub fn trail_length_for(&self, handle: usize) -> u32 {
+        let frames = self.trail_length as f32 * self.handicaps[handle].trail_length;
+        (frames.round() as u32).max(1)
+    }
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 6] = [
+        GameMode::Duel,
+        GameMode::Teams,
+        GameMode::Royale,
+        GameMode::Survival,
+        GameMode::Hill,
+        GameMode::Pellets,
+    ];
+
+    // the next mode that fits the same number of players, for a lobby that's already full
+    pub fn next_alike(&self) -> GameMode {
+        let i = GameMode::ALL
+            .iter()
+            .position(|mode| mode == self)
+            .unwrap_or(0);
+        (1..=GameMode::ALL.len())
+            .map(|offset| GameMode::ALL[(i + offset) % GameMode::ALL.len()])
+            .find(|mode| mode.num_players() == self.num_players())
+            .unwrap_or(*self)
+    }
+
+    pub fn num_players(&self) -> usize {
+        match self {
+            GameMode::Duel | GameMode::Survival | GameMode::Hill | GameMode::Pellets => 2,
+            GameMode::Teams => 4,
+            GameMode::Royale => ROYALE_PLAYERS,
+        }
+    }
+
+    pub fn num_teams(&self) -> usize {
+        match self {
+            GameMode::Duel
+            | GameMode::Teams
+            | GameMode::Survival
+            | GameMode::Hill
+            | GameMode::Pellets => 2,
+            GameMode::Royale => ROYALE_PLAYERS,
+        }
+    }
+
+    pub fn team_of(&self, handle: usize) -> usize {
+        match self {
+            GameMode::Duel
+            | GameMode::Royale
+            | GameMode::Survival
+            | GameMode::Hill
+            | GameMode::Pellets => handle,
+            GameMode::Teams => handle % 2,
+        }
+    }
+
+    pub fn board_size(&self, tuning: &Tuning) -> f32 {
+        match self {
+            GameMode::Duel
+            | GameMode::Teams
+            | GameMode::Survival
+            | GameMode::Hill
+            | GameMode::Pellets => tuning.board_size,
+            GameMode::Royale => ROYALE_BOARD_SIZE,
+        }
+    }
+
+    pub fn overtime_frame(&self) -> u32 {
+        match self {
+            GameMode::Duel | GameMode::Teams | GameMode::Royale => OVERTIME_FRAME,
+            // respawns mean nobody would ever stop sudden death
+            GameMode::Survival | GameMode::Hill => 0,
+            // the first death ends the match anyway
+            GameMode::Pellets => 0,
+        }
+    }
+
+    // single-round modes where dead players come back instead of ending the round
+    pub fn respawns(&self) -> bool {
+        matches!(self, GameMode::Survival | GameMode::Hill)
+    }
+
+    pub fn arena_radius(&self, round: &RoundState, tuning: &Tuning) -> f32 {
+        let radius = match self {
+            GameMode::Duel
+            | GameMode::Teams
+            | GameMode::Survival
+            | GameMode::Hill
+            | GameMode::Pellets => tuning.board_size / 2.,
+            GameMode::Royale => {
+                let shrink =
+                    round.frame.saturating_sub(ROYALE_SHRINK_DELAY) as f32 * ROYALE_SHRINK_SPEED;
+                (ROYALE_BOARD_SIZE / 2. - shrink).max(ROYALE_MIN_BOARD_SIZE / 2.)
+            }
+        };
+        let overtime_shrink = round.overtime_frames() as f32 * OVERTIME_SHRINK_SPEED;
+        (radius - overtime_shrink).max(tuning.player_size)
+    }
+
+    pub fn room_name(&self) -> &'static str {
+        match self {
+            GameMode::Duel => "extreme_bevy",
+            GameMode::Teams => "extreme_bevy_teams",
+            GameMode::Royale => "extreme_bevy_royale",
+            GameMode::Survival => "extreme_bevy_survival",
+            GameMode::Hill => "extreme_bevy_hill",
+            GameMode::Pellets => "extreme_bevy_pellets",
+        }
+    }
+}
+
+fn spawn_board(mut commands: Commands, mode: Res<GameMode>, tuning: Res<Tuning>) {
+    spawn_board_pieces(&mut commands, *mode, &tuning);
+}
+
+// The lobby can pick another mode or arena size than the one the board was built for, so it
+// gets rebuilt once a match's settings are in. Everything else reads the sizes from Tuning,
+// which keeps them the same on every peer.
+#[allow(clippy::type_complexity)]
+fn apply_match_arena(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    settings: Option<Res<MatchSettings>>,
+    mut tuning: ResMut<Tuning>,
+    board_query: Query<Entity, Or<(With<BoostZone>, With<Hill>)>>,
+) {
+    let settings = match settings {
+        Some(settings) if settings.is_changed() || mode.is_changed() => settings,
+        _ => return,
+    };
+    if tuning.board_size != settings.board_size {
+        tuning.board_size = settings.board_size;
+    }
+    if tuning.player_size != settings.player_size {
+        tuning.player_size = settings.player_size;
+    }
+    for entity in board_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_board_pieces(&mut commands, *mode, &tuning);
+}
+
+// Their meshes are left to the rendering plugin
+fn spawn_board_pieces(commands: &mut Commands, mode: GameMode, tuning: &Tuning) {
+    // boost zones sit in a ring between 55% and 75% of the way to the border
+    let board_radius = mode.board_size(tuning) / 2.;
+    for (center, width) in BOOST_SECTORS {
+        let zone = BoostZone {
+            start_angle: center - width / 2.,
+            end_angle: center + width / 2.,
+            inner_radius: board_radius * 0.55,
+            outer_radius: board_radius * 0.75,
+        };
+        commands
+            .spawn_bundle(TransformBundle::default())
+            .insert(zone);
+    }
+
+    if mode == GameMode::Hill {
+        commands
+            .spawn_bundle(TransformBundle::default())
+            .insert(Hill);
+    }
+}
+
+fn spawn_point(mode: GameMode, tuning: &Tuning, seed: u32, handle: usize) -> (Position, Heading) {
+    // players are spread evenly around a ring that's rotated by a random amount each round
+    let roll = pseudo_random(seed);
+    let base_angle = (roll % 3600) as f32 / 3600. * TAU;
+    let angle = base_angle + TAU * handle as f32 / mode.num_players() as f32;
+    let ring: f32 = match mode {
+        GameMode::Duel | GameMode::Survival | GameMode::Pellets => 0.3,
+        // start well clear of the hill so nobody scores off the spawn
+        GameMode::Hill => 0.7,
+        GameMode::Teams => 0.4,
+        // stagger players between an inner and outer ring
+        GameMode::Royale if handle % 2 == 0 => 0.6,
+        GameMode::Royale => 0.4,
+    };
+    let direction = Vec2::new(angle.cos(), angle.sin());
+    let position = direction * ring * mode.board_size(tuning) / 2.;
+
+    // roughly counter-clockwise, give or take 45 degrees
+    let heading_roll = pseudo_random(roll.wrapping_add(handle as u32 + 1));
+    let heading_offset = ((heading_roll % 1000) as f32 / 1000. - 0.5) * FRAC_PI_2;
+    (
+        Position(position),
+        Heading::new(angle + FRAC_PI_2 + heading_offset),
+    )
+}
+
+fn spawn_players(
+    commands: &mut Commands,
+    rip: &mut RollbackIdProvider,
+    mode: GameMode,
+    tuning: &Tuning,
+    seed: u32,
+) {
+    for handle in 0..mode.num_players() {
+        spawn_player(commands, rip, mode, tuning, seed, handle);
+    }
+}
+
+// The sprite and everything drawn around it are left to the rendering plugin
+fn spawn_player(
+    commands: &mut Commands,
+    rip: &mut RollbackIdProvider,
+    mode: GameMode,
+    tuning: &Tuning,
+    seed: u32,
+    handle: usize,
+) {
+    let (position, heading) = spawn_point(mode, tuning, seed, handle);
+    commands
+        .spawn_bundle(TransformBundle::from_transform(
+            Transform::from_translation(position.0.extend(0.))
+                .with_rotation(Quat::from_rotation_z(heading.0)),
+        ))
+        .insert(Player { handle })
+        .insert(position)
+        .insert(heading)
+        .insert(PreviousPosition {
+            position: position.0,
+            heading: heading.0,
+        })
+        .insert(Smoothing::default())
+        .insert(InputHistory::default())
+        .insert(DashEnergy::default())
+        .insert(TrailSpawner {
+            timer: FrameTimer::new(TRAIL_SPAWN_FRAMES),
+            wide_frames_left: 0,
+        })
+        .insert(Rollback::new(rip.next_id()));
+}
+
+// Copies the simulation's state onto Transform for everything that draws or plays from it.
+// Anything that moves gets drawn partway between its last two frames, as far along as time
+// has got since the latest one. Depth stays whatever the entity spawned with.
+fn sync_transforms(
+    time: Res<Time>,
+    clock: Res<SimulationClock>,
+    mut since_frame: Local<(u32, f32)>,
+    mut still_query: Query<(&mut Transform, &Position, Option<&Heading>), Without<Smoothing>>,
+    mut moving_query: Query<(
+        &mut Transform,
+        &Position,
+        &Heading,
+        &PreviousPosition,
+        &mut Smoothing,
+    )>,
+) {
+    for (mut transform, position, heading) in still_query.iter_mut() {
+        transform.translation = position.0.extend(transform.translation.z);
+        if let Some(heading) = heading {
+            transform.rotation = Quat::from_rotation_z(heading.0);
+        }
+    }
+
+    let (last_frame, elapsed) = &mut *since_frame;
+    let advanced = clock.frame != *last_frame;
+    if advanced {
+        *last_frame = clock.frame;
+        *elapsed = 0.;
+    }
+    *elapsed += time.delta_seconds();
+    let blend = (*elapsed / FRAME_SECONDS).min(1.);
+    let decay = CORRECTION_DECAY.powf(time.delta_seconds());
+
+    for (mut transform, position, heading, previous, mut smoothing) in moving_query.iter_mut() {
+        let to = Quat::from_rotation_z(heading.0);
+        if previous.position.distance(position.0) > SNAP_DISTANCE {
+            *smoothing = default();
+            transform.translation = position.0.extend(transform.translation.z);
+            transform.rotation = to;
+            continue;
+        }
+        let from = Quat::from_rotation_z(previous.heading);
+        if advanced {
+            // whatever's between what's on screen and where the new blend starts is a
+            // correction, and on a steady connection it's next to nothing
+            smoothing.offset = transform.translation.truncate() - previous.position;
+            smoothing.rotation = transform.rotation * from.inverse();
+            if smoothing.offset.length() > SNAP_DISTANCE {
+                *smoothing = default();
+            }
+        } else {
+            smoothing.offset *= decay;
+            smoothing.rotation = Quat::IDENTITY.slerp(smoothing.rotation, decay);
+        }
+        let drawn = previous.position.lerp(position.0, blend) + smoothing.offset;
+        transform.translation = drawn.extend(transform.translation.z);
+        transform.rotation = smoothing.rotation * from.slerp(to, blend);
+    }
+}
+
+// Deaths are recorded in rollback state, this turns new ones into events for everything
+// outside the simulation
+pub fn emit_death_events(
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    // (round, deaths already sent)
+    mut cursor: Local<(u32, usize)>,
+    mut death_events: EventWriter<DeathEvent>,
+) {
+    if cursor.0 != scoreboard.round {
+        *cursor = (scoreboard.round, 0);
+    }
+    for death in round.deaths.iter().skip(cursor.1) {
+        death_events.send(*death);
+    }
+    cursor.1 = round.deaths.len();
+}
+
+// Whoever gained the most points won the round that just ended
+pub fn emit_round_events(
+    mode: Res<GameMode>,
+    scoreboard: Res<Scoreboard>,
+    // (round, match over, scores) as of the last round we announced
+    mut last: Local<(u32, bool, Vec<u32>)>,
+    mut round_events: EventWriter<RoundEndEvent>,
+) {
+    // the last round of a match ends the match rather than starting another round, and
+    // rematches start back at round 0
+    let ended = scoreboard.round > last.0 || (scoreboard.match_over && !last.1);
+    if ended && last.0 > 0 && !mode.respawns() && *mode != GameMode::Pellets {
+        let gains: Vec<u32> = scoreboard
+            .scores
+            .iter()
+            .zip(last.2.iter().chain(std::iter::repeat(&0)))
+            .map(|(score, last)| score.saturating_sub(*last))
+            .collect();
+        let best = gains.iter().copied().max().unwrap_or(0);
+        let winners: Vec<usize> = (0..gains.len()).filter(|t| gains[*t] == best).collect();
+        let winner = match winners[..] {
+            [winner] if best > 0 => Some(winner),
+            _ => None,
+        };
+        round_events.send(RoundEndEvent { winner });
+    }
+    if scoreboard.round != last.0 || scoreboard.match_over != last.1 {
+        *last = (
+            scoreboard.round,
+            scoreboard.match_over,
+            scoreboard.scores.clone(),
+        );
+    }
+}
+
+// Picks whichever of straight / left / right has the most room ahead. This runs inside the
+// rollback schedule so every peer derives the same bot inputs from the same state.
+#[allow(clippy::too_many_arguments)]
+fn steer_bots(
+    mut commands: Commands,
+    bots: Res<BotPlayers>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    steering: Res<Steering>,
+    round: Res<RoundState>,
+    mut inputs: ResMut<Vec<(u8, InputStatus)>>,
+    index: Res<TrailIndex>,
+    mut player_query: Query<(Entity, &Position, &Heading, &Player, Option<&mut BotMemory>)>,
+    trail_query: Query<&Trail>,
+) {
+    let difficulty = settings.bot_difficulty;
+    let lookahead = difficulty.lookahead();
+    let radius = mode.arena_radius(&round, &tuning);
+    let swerve = match *steering {
+        Steering::Analog => BOT_SWERVE_ANGLE,
+        Steering::Grid => FRAC_PI_2,
+    };
+    for (entity, position, heading, player, memory) in player_query.iter_mut() {
+        if !bots.0.contains(&player.handle) {
+            continue;
+        }
+
+        let position = position.0;
+        let heading = heading.direction();
+        let clearance = |angle: f32| {
+            let direction = Vec2::new(
+                heading.x * angle.cos() - heading.y * angle.sin(),
+                heading.x * angle.sin() + heading.y * angle.cos(),
+            );
+            for i in 1..=BOT_PROBES {
+                let distance = lookahead * i as f32 / BOT_PROBES as f32;
+                let probe = position + direction * distance;
+                let blocked = probe.length() > radius - tuning.player_size / 2.
+                    || index.near(probe, tuning.trail_reach()).any(|(trail, i)| {
+                        let trail = match trail_query.get(trail) {
+                            Ok(trail) => trail,
+                            Err(_) => return false,
+                        };
+                        let friendly = trail.player_handle != player.handle
+                            && mode.team_of(trail.player_handle) == mode.team_of(player.handle);
+                        !friendly && trail.touches(i, player.handle, probe, tuning.player_size / 2.)
+                    });
+                if blocked {
+                    return distance;
+                }
+            }
+            lookahead * 2.
+        };
+
+        // ties go to going straight
+        let straight = clearance(0.);
+        let left = clearance(swerve);
+        let right = clearance(-swerve);
+        let mut input = 0;
+        if straight < left || straight < right {
+            input |= if left >= right {
+                INPUT_LEFT
+            } else {
+                INPUT_RIGHT
+            };
+        } else if straight > lookahead {
+            // nothing ahead at all, maybe floor it. Seeded like the spawns, so every daily
+            // challenge gets bots of its own.
+            let roll = pseudo_random(
+                settings.seed ^ round.frame.wrapping_add(player.handle as u32 * 7919),
+            );
+            if roll % 100 < difficulty.dash_percent() {
+                input |= INPUT_DASH;
+            }
+        }
+
+        // act on the decision from a few frames back
+        let delay = difficulty.reaction_frames();
+        inputs[player.handle].0 = match memory {
+            _ if delay == 0 => input,
+            Some(mut memory) => {
+                memory.pending.push(input);
+                memory.pending.remove(0)
+            }
+            None => {
+                let mut pending = vec![0; delay];
+                pending.push(input);
+                pending.remove(0);
+                commands.entity(entity).insert(BotMemory { pending });
+                0
+            }
+        };
+    }
+}
+
+fn rotate_players(
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    settings: Res<MatchSettings>,
+    steering: Res<Steering>,
+    round: Res<RoundState>,
+    mut player_query: Query<(&mut Position, &mut Heading, &Player, &mut InputHistory)>,
+) {
+    if round.in_countdown() {
+        return;
+    }
+    for (mut position, mut heading, player, mut history) in player_query.iter_mut() {
+        let (input, _) = inputs[player.handle];
+        steer(
+            &mut position,
+            &mut heading,
+            input,
+            history.previous,
+            *steering,
+            settings.turn_speed,
+        );
+        history.previous = input;
+    }
+}
+
+fn steer(
+    position: &mut Position,
+    heading: &mut Heading,
+    input: u8,
+    previous: u8,
+    steering: Steering,
+    turn_speed: f32,
+) {
+    if steering == Steering::Grid {
+        let pressed = input & !previous;
+        let mut quarter_turns = 0.;
+        if pressed & INPUT_RIGHT != 0 {
+            quarter_turns -= 1.;
+        }
+        if pressed & INPUT_LEFT != 0 {
+            quarter_turns += 1.;
+        }
+        // also snaps headings that didn't start out axis-aligned
+        let quarters = (heading.0 / FRAC_PI_2).round() + quarter_turns;
+        *heading = Heading::new(quarters * FRAC_PI_2);
+        if quarter_turns != 0. {
+            position.0 = (position.0 / GRID_SIZE).round() * GRID_SIZE;
+        }
+        return;
+    }
+
+    let mut angle = 0.;
+    if input & INPUT_RIGHT != 0 {
+        angle -= 1.;
+    }
+    if input & INPUT_LEFT != 0 {
+        angle += 1.;
+    }
+    if angle == 0. {
+        return;
+    }
+    heading.turn(angle * turn_speed)
+}
+
+#[allow(clippy::type_complexity)]
+fn move_players_forward(
+    mut commands: Commands,
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    settings: Res<MatchSettings>,
+    round: Res<RoundState>,
+    mut stats: ResMut<MatchStats>,
+    mut player_query: Query<(
+        Entity,
+        &mut Position,
+        &Heading,
+        &Player,
+        &mut DashEnergy,
+        Option<&mut Slowed>,
+    )>,
+    zone_query: Query<&BoostZone>,
+) {
+    if round.in_countdown() {
+        return;
+    }
+    for (entity, mut position, heading, player, mut dash, slowed) in player_query.iter_mut() {
+        let (input, _) = inputs[player.handle];
+        let stats = stats.player(player.handle);
+
+        let mut speed_multiplier = 1.;
+        if dash.dash(input) {
+            speed_multiplier *= 2.;
+            stats.dash_frames += 1;
+        }
+        if let Some(mut slowed) = slowed {
+            speed_multiplier *= SLOW_MULTIPLIER;
+            slowed.frames_left = slowed.frames_left.saturating_sub(1);
+            if slowed.frames_left == 0 {
+                commands.entity(entity).remove::<Slowed>();
+            }
+        }
+        if zone_query.iter().any(|zone| zone.contains(position.0)) {
+            speed_multiplier *= BOOST_MULTIPLIER;
+        }
+        speed_multiplier *= settings.handicaps[player.handle].move_speed;
+
+        position.0 += heading.direction() * settings.move_speed * speed_multiplier;
+        stats.distance += settings.move_speed * speed_multiplier;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_trail(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    clock: Res<SimulationClock>,
+    round: Res<RoundState>,
+    mut stats: ResMut<MatchStats>,
+    mut trail_spawner_query: Query<(&Position, &Heading, &Player, &DashEnergy, &mut TrailSpawner)>,
+    mut trail_query: Query<&mut Trail>,
+) {
+    if round.in_countdown() {
+        return;
+    }
+    for (position, heading, player, dash, mut trail_spawner) in trail_spawner_query.iter_mut() {
+        trail_spawner.wide_frames_left = trail_spawner.wide_frames_left.saturating_sub(1);
+        if !trail_spawner.timer.tick().finished() {
+            continue;
+        }
+        let size = trail_spawner.trail_size();
+        let heading = heading.direction();
+        let mut point = TrailPoint {
+            position: position.0 - (tuning.player_size + size) / 2. * heading,
+            heading,
+            size,
+            frame: clock.frame,
+            frames_left: settings.trail_length_for(player.handle),
+            dashed: dash.dashing,
+            connected: false,
+        };
+        stats.player(player.handle).trails_laid += 1;
+        let trail = trail_query
+            .iter_mut()
+            .find(|trail| trail.player_handle == player.handle);
+        match trail {
+            Some(mut trail) => {
+                // anything longer than the usual spacing means we weren't around in between
+                point.connected = trail
+                    .points
+                    .last()
+                    .map_or(false, |last| point.frame - last.frame <= TRAIL_SPAWN_FRAMES);
+                trail.points.push(point);
+            }
+            None => {
+                commands
+                    .spawn()
+                    .insert(Trail {
+                        player_handle: player.handle,
+                        points: vec![point],
+                    })
+                    .insert(Rollback::new(rip.next_id()));
+            }
+        }
+    }
+}
+
+fn index_trails(mut index: ResMut<TrailIndex>, trail_query: Query<(Entity, &Trail)>) {
+    // keep the buckets around, the arena only has so many cells
+    for cell in index.cells.values_mut() {
+        cell.clear();
+    }
+    for (entity, trail) in trail_query.iter() {
+        for i in 0..trail.points.len() {
+            index.insert(entity, i, trail.segment(i));
+        }
+    }
+}
+
+fn kill_trail(
+    settings: Res<MatchSettings>,
+    round: Res<RoundState>,
+    mut trail_query: Query<&mut Trail>,
+) {
+    // trails stop aging in sudden death and when they're endless, burned points go regardless
+    let aging = settings.trail_length != 0 && !round.sudden_death();
+    for mut trail in trail_query.iter_mut() {
+        if aging {
+            for point in trail.points.iter_mut() {
+                point.frames_left = point.frames_left.saturating_sub(1);
+            }
+        }
+        if trail.points.iter().all(|point| point.frames_left > 0) {
+            continue;
+        }
+        let mut after_gap = false;
+        for mut point in std::mem::take(&mut trail.points) {
+            if point.frames_left == 0 {
+                after_gap = true;
+                continue;
+            }
+            if after_gap {
+                point.connected = false;
+                after_gap = false;
+            }
+            trail.points.push(point);
+        }
+    }
+}
+
+fn receive_emotes(inputs: Res<Vec<(u8, InputStatus)>>, mut emotes: ResMut<Emotes>) {
+    emotes.shown.resize(inputs.len(), default());
+    for (shown, (input, _)) in emotes.shown.iter_mut().zip(inputs.iter()) {
+        if Emote::from_input(*input).is_some() {
+            *shown = ShownEmote {
+                emote: input & INPUT_EMOTE,
+                frames_left: EMOTE_FRAMES,
+            };
+        } else {
+            shown.frames_left = shown.frames_left.saturating_sub(1);
+        }
+    }
+}
+
+fn remember_positions(mut query: Query<(&mut PreviousPosition, &Position, &Heading)>) {
+    for (mut previous, position, heading) in query.iter_mut() {
+        previous.position = position.0;
+        previous.heading = heading.0;
+    }
+}
+
+fn tick_round_clock(
+    scoreboard: Res<Scoreboard>,
+    mut clock: ResMut<SimulationClock>,
+    mut round: ResMut<RoundState>,
+    mut simulated: ResMut<FramesSimulated>,
+    mut cues: ResMut<CueLog>,
+) {
+    clock.frame += 1;
+    round.frame += 1;
+    simulated.0 += 1;
+    cues.start_frame(clock.frame);
+
+    // a beep for every number of the countdown and a stinger on "GO!"
+    if scoreboard.round == 0 || scoreboard.match_over {
+        return;
+    }
+    if round.in_countdown() && round.frame % 60 == 1 {
+        cues.push(Cue::CountdownBeep);
+    } else if round.frame == COUNTDOWN_FRAMES + 1 {
+        cues.push(Cue::Go);
+    }
+}
+
+// FNV-1a
+pub fn seed_from_str(s: &str) -> u32 {
+    s.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn round_seed(seed: u32, round: u32) -> u32 {
+    seed ^ round.wrapping_mul(0x85eb_ca6b)
+}
+
+// xorshift, so both peers roll the same numbers without sharing an rng
+pub fn pseudo_random(seed: u32) -> u32 {
+    let mut x = seed.wrapping_mul(0x9e37_79b9) | 1;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    x
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_power_ups(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    power_up_query: Query<&PowerUp>,
+) {
+    if round.frame % POWER_UP_INTERVAL != 0 || power_up_query.iter().count() >= MAX_POWER_UPS {
+        return;
+    }
+
+    let roll = pseudo_random(round_seed(settings.seed, scoreboard.round).wrapping_add(round.frame));
+    let angle = (roll % 360) as f32 / 360. * TAU;
+    let distance = (pseudo_random(roll) % 100) as f32 / 100. * mode.board_size(&tuning) / 2. * 0.8;
+    let kind = PowerUpKind::ALL[(roll / 360) as usize % PowerUpKind::ALL.len()];
+    let position = Vec2::new(angle.cos(), angle.sin()) * distance;
+    commands
+        .spawn_bundle(TransformBundle::from_transform(
+            Transform::from_translation(position.extend(0.)),
+        ))
+        .insert(PowerUp { kind })
+        .insert(Position(position))
+        .insert(Rollback::new(rip.next_id()));
+}
+
+fn collect_power_ups(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    mut cues: ResMut<CueLog>,
+    mut player_query: Query<(Entity, &Position, &Player, &mut TrailSpawner)>,
+    power_up_query: Query<(Entity, &Position, &PowerUp)>,
+) {
+    for (power_up_entity, power_up_position, power_up) in power_up_query.iter() {
+        let position = power_up_position.0;
+        // the lowest handle wins a tie, query order isn't the same on every peer
+        let collector = player_query
+            .iter()
+            .filter(|(_, player_position, _, _)| {
+                player_position.0.distance(position) < (tuning.player_size + POWER_UP_SIZE) / 2.
+            })
+            .min_by_key(|(_, _, player, _)| player.handle);
+        let (player_entity, collector_handle) = match collector {
+            Some((entity, _, player, _)) => (entity, player.handle),
+            None => continue,
+        };
+        cues.push(Cue::PowerUp {
+            handle: collector_handle,
+            position,
+        });
+
+        match power_up.kind {
+            PowerUpKind::WideTrail => {
+                if let Ok((_, _, _, mut trail_spawner)) = player_query.get_mut(player_entity) {
+                    trail_spawner.wide_frames_left = WIDE_TRAIL_FRAMES;
+                }
+            }
+            PowerUpKind::Shield => {
+                commands.entity(player_entity).insert(Shield);
+            }
+            PowerUpKind::Slow => {
+                for (entity, _, player, _) in player_query.iter() {
+                    if mode.team_of(player.handle) != mode.team_of(collector_handle) {
+                        commands.entity(entity).insert(Slowed {
+                            frames_left: SLOW_FRAMES,
+                        });
+                    }
+                }
+            }
+        }
+        commands.entity(power_up_entity).despawn_recursive();
+    }
+}
+
+fn eliminate(
+    commands: &mut Commands,
+    round: &mut RoundState,
+    cues: &mut CueLog,
+    entity: Entity,
+    position: Vec2,
+    death: DeathEvent,
+) {
+    // a player can hit several things on the same frame, but only dies once
+    if round
+        .deaths
+        .iter()
+        .any(|d| d.victim == death.victim && d.frame == death.frame)
+    {
+        return;
+    }
+    round.deaths.push(death);
+    cues.push(Cue::Death { death, position });
+    if round.sudden_death() {
+        round.overtime_end_frame = round.frame;
+    }
+    commands.entity(entity).despawn_recursive();
+}
+
+// Whoever forfeits takes their whole team out, which hands the round to everyone else. Modes
+// played as a single round have no next round to move on to, so the match ends there with the
+// forfeiting team's score wiped.
+fn forfeit(
+    mut commands: Commands,
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    mode: Res<GameMode>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut round: ResMut<RoundState>,
+    mut cues: ResMut<CueLog>,
+    player_query: Query<(Entity, &Position, &Player)>,
+) {
+    if scoreboard.match_over {
+        return;
+    }
+    for (handle, (input, _)) in inputs.iter().enumerate() {
+        if input & INPUT_FORFEIT == 0 {
+            continue;
+        }
+        let team = mode.team_of(handle);
+        for (entity, position, player) in player_query.iter() {
+            if mode.team_of(player.handle) != team {
+                continue;
+            }
+            let death = DeathEvent {
+                victim: player.handle,
+                killer: player.handle,
+                cause: DeathCause::Forfeit,
+                frame: round.frame,
+            };
+            eliminate(
+                &mut commands,
+                &mut round,
+                &mut cues,
+                entity,
+                position.0,
+                death,
+            );
+        }
+        if mode.respawns() || *mode == GameMode::Pellets {
+            if let Some(score) = scoreboard.scores.get_mut(team) {
+                *score = 0;
+            }
+        }
+        // check_round_end already ends pellet matches on the first death
+        if mode.respawns() && scoreboard.round > 0 {
+            scoreboard.match_over = true;
+            for (entity, _, _) in player_query.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            return;
+        }
+    }
+}
+
+fn border_death(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    mut round: ResMut<RoundState>,
+    mut cues: ResMut<CueLog>,
+    mut player_query: Query<(
+        Entity,
+        &mut Position,
+        &mut Heading,
+        &Player,
+        Option<&Shield>,
+    )>,
+) {
+    let radius = mode.arena_radius(&round, &tuning);
+    // by handle, so deaths in the same frame are listed in the same order on every peer
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|(_, _, _, player, _)| player.handle);
+    for (entity, mut position, mut heading, player, shield) in players {
+        if position.0.distance(Vec2::ZERO) <= radius {
+            continue;
+        }
+        if shield.is_some() {
+            // the shield bounces you back into the arena
+            commands.entity(entity).remove::<Shield>();
+            position.0 = position.0.normalize() * radius;
+            heading.turn(PI);
+        } else {
+            let death = DeathEvent {
+                victim: player.handle,
+                killer: player.handle,
+                cause: DeathCause::Border,
+                frame: round.frame,
+            };
+            eliminate(
+                &mut commands,
+                &mut round,
+                &mut cues,
+                entity,
+                position.0,
+                death,
+            );
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trail_death(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    mut round: ResMut<RoundState>,
+    mut cues: ResMut<CueLog>,
+    index: Res<TrailIndex>,
+    player_query: Query<(Entity, &Position, &Player, Option<&Shield>)>,
+    mut trail_query: Query<(Entity, &mut Trail)>,
+) {
+    let mut burns: Vec<(Entity, usize)> = Vec::new();
+    // by handle, so deaths in the same frame are listed in the same order on every peer
+    let mut players: Vec<_> = player_query.iter().collect();
+    players.sort_by_key(|(_, _, player, _)| player.handle);
+    for (entity, position, player, shield) in players {
+        let position = position.0;
+        let mut hits = Vec::new();
+        for (trail_entity, i) in index.near(position, tuning.trail_reach()) {
+            let trail = match trail_query.get(trail_entity) {
+                Ok((_, trail)) => trail,
+                Err(_) => continue,
+            };
+            // teammates' trails are harmless, but your own still isn't
+            if trail.player_handle != player.handle
+                && mode.team_of(trail.player_handle) == mode.team_of(player.handle)
+            {
+                continue;
+            }
+            if trail.touches(i, player.handle, position, tuning.player_size / 2.) {
+                hits.push((trail_entity, i, trail.player_handle));
+            }
+        }
+        if hits.is_empty() {
+            continue;
+        }
+        // the index hands them back in whatever order its map keeps, which differs per peer
+        hits.sort_by_key(|(_, i, owner)| (*owner, *i));
+
+        if shield.is_some() {
+            // the shield burns through whatever it touched instead of you
+            commands.entity(entity).remove::<Shield>();
+            burns.extend(hits.iter().map(|(trail_entity, i, _)| (*trail_entity, *i)));
+        } else {
+            // credit someone else's trail over your own if you hit both at once
+            let killer = hits
+                .iter()
+                .map(|(_, _, owner)| *owner)
+                .find(|owner| *owner != player.handle)
+                .unwrap_or(player.handle);
+            let death = DeathEvent {
+                victim: player.handle,
+                killer,
+                cause: DeathCause::Trail,
+                frame: round.frame,
+            };
+            eliminate(
+                &mut commands,
+                &mut round,
+                &mut cues,
+                entity,
+                position,
+                death,
+            );
+        }
+    }
+
+    for (trail_entity, i) in burns {
+        if let Ok((_, mut trail)) = trail_query.get_mut(trail_entity) {
+            trail.burn(i);
+        }
+    }
+}
+
+// Runs after both kinds of death, while this frame's victims are still around
+#[allow(clippy::too_many_arguments)]
+fn track_stats(
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    round: Res<RoundState>,
+    mut stats: ResMut<MatchStats>,
+    mut cues: ResMut<CueLog>,
+    index: Res<TrailIndex>,
+    player_query: Query<(&Position, &Player)>,
+    trail_query: Query<&Trail>,
+) {
+    for death in round
+        .deaths
+        .iter()
+        .filter(|death| death.frame == round.frame)
+    {
+        let victim = stats.player(death.victim);
+        victim.deaths += 1;
+        victim.last_death = *death;
+        victim.near_trail = false;
+        if !death.is_suicide() {
+            stats.player(death.killer).kills += 1;
+        }
+    }
+
+    for (position, player) in player_query.iter() {
+        let died = round
+            .deaths
+            .iter()
+            .any(|death| death.victim == player.handle && death.frame == round.frame);
+        if died {
+            continue;
+        }
+        // your own trail is always right behind you, so only enemy trails count
+        let position = position.0;
+        let reach = tuning.player_size / 2. + NEAR_MISS_MARGIN;
+        let near_trail = index
+            .near(position, tuning.trail_reach() + NEAR_MISS_MARGIN)
+            .any(|(trail, i)| match trail_query.get(trail) {
+                Ok(trail) => {
+                    mode.team_of(trail.player_handle) != mode.team_of(player.handle)
+                        && trail.touches(i, player.handle, position, reach)
+                }
+                Err(_) => false,
+            });
+        let stats = stats.player(player.handle);
+        // a near miss is counted once you're clear of the trail again
+        if stats.near_trail && !near_trail {
+            stats.near_misses += 1;
+            cues.push(Cue::NearMiss(player.handle));
+        }
+        stats.near_trail = near_trail;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_round_end(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut round: ResMut<RoundState>,
+    player_query: Query<(Entity, &Player)>,
+    pellet_query: Query<(), With<Pellet>>,
+    leftover_query: Query<Entity, With<PowerUp>>,
+    mut trail_query: Query<&mut Trail>,
+) {
+    if scoreboard.match_over {
+        return;
+    }
+    if *mode == GameMode::Pellets && scoreboard.round > 0 {
+        // highest score wins once the board is cleared or anyone crashes
+        if !round.deaths.is_empty() || pellet_query.is_empty() {
+            scoreboard.match_over = true;
+            for (entity, _) in player_query.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        return;
+    }
+    if mode.respawns() && scoreboard.round > 0 {
+        // a single round that only ends when the clock runs out or someone holds the hill
+        let over = match *mode {
+            GameMode::Hill => scoreboard
+                .scores
+                .iter()
+                .any(|score| *score >= HILL_WIN_FRAMES),
+            _ => round.frame >= SURVIVAL_MATCH_FRAMES,
+        };
+        if over {
+            scoreboard.match_over = true;
+            for (entity, _) in player_query.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        return;
+    }
+
+    let mut team_alive = vec![false; mode.num_teams()];
+    for (_, player) in player_query.iter() {
+        team_alive[mode.team_of(player.handle)] = true;
+    }
+    let alive_teams: Vec<usize> = (0..mode.num_teams()).filter(|t| team_alive[*t]).collect();
+    if alive_teams.len() > 1 {
+        return;
+    }
+
+    if scoreboard.round == 0 {
+        scoreboard.scores = vec![0; mode.num_teams()];
+        scoreboard.kills = vec![0; mode.num_players()];
+    } else if *mode == GameMode::Royale {
+        // one point for every player you outlasted
+        for (place, death) in round.deaths.iter().enumerate() {
+            scoreboard.scores[death.victim] += place as u32;
+        }
+        if let Some(&winner) = alive_teams.first() {
+            scoreboard.scores[winner] += round.deaths.len() as u32;
+        }
+    } else if let Some(&winner) = alive_teams.first() {
+        scoreboard.scores[winner] += 1;
+    }
+    for death in round.deaths.iter().filter(|death| !death.is_suicide()) {
+        scoreboard.kills[death.killer] += 1;
+    }
+    if settings.rounds != 0 && scoreboard.round >= settings.rounds {
+        scoreboard.match_over = true;
+        for (entity, _) in player_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        for entity in leftover_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        for mut trail in trail_query.iter_mut() {
+            trail.points.clear();
+        }
+        return;
+    }
+    scoreboard.round += 1;
+    *round = RoundState {
+        overtime_frame: mode.overtime_frame(),
+        ..default()
+    };
+
+    for (entity, _) in player_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for entity in leftover_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // trails hang around between rounds, emptied, rather than being respawned every time
+    for mut trail in trail_query.iter_mut() {
+        trail.points.clear();
+    }
+    let seed = round_seed(settings.seed, scoreboard.round);
+    spawn_players(&mut commands, &mut rip, *mode, &tuning, seed);
+    if *mode == GameMode::Pellets {
+        spawn_pellets(&mut commands, &mut rip, *mode, &tuning, seed);
+    }
+}
+
+// Votes come in as inputs so every peer sees them on the same frame and restarts together.
+// Bots are always up for another go, and stragglers get dragged along once time runs out.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn vote_rematch(
+    mut commands: Commands,
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    bots: Res<BotPlayers>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut stats: ResMut<MatchStats>,
+    mut round: ResMut<RoundState>,
+    leftover_query: Query<Entity, Or<(With<PowerUp>, With<Pellet>)>>,
+    mut trail_query: Query<&mut Trail>,
+) {
+    if !scoreboard.match_over {
+        return;
+    }
+    if scoreboard.rematch_deadline == 0 {
+        scoreboard.rematch_deadline = round.frame + REMATCH_TIMEOUT_FRAMES;
+        scoreboard.rematch_votes = (0..inputs.len())
+            .map(|handle| bots.0.contains(&handle))
+            .collect();
+    }
+    for (handle, (input, _)) in inputs.iter().enumerate() {
+        if input & INPUT_REMATCH != 0 {
+            scoreboard.rematch_votes[handle] = true;
+        }
+    }
+    if scoreboard.rematch_votes.iter().all(|voted| *voted)
+        || round.frame >= scoreboard.rematch_deadline
+    {
+        // back to how things were before the first round, check_round_end takes it from there
+        *scoreboard = default();
+        *stats = default();
+        *round = default();
+        for entity in leftover_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        for mut trail in trail_query.iter_mut() {
+            trail.points.clear();
+        }
+    }
+}
+
+fn spawn_pellets(
+    commands: &mut Commands,
+    rip: &mut RollbackIdProvider,
+    mode: GameMode,
+    tuning: &Tuning,
+    seed: u32,
+) {
+    for i in 0..PELLET_COUNT {
+        let roll = pseudo_random(seed.wrapping_add(i));
+        let angle = (roll % 3600) as f32 / 3600. * TAU;
+        // sqrt spreads them evenly over the area instead of bunching up in the middle
+        let distance =
+            ((pseudo_random(roll) % 1000) as f32 / 1000.).sqrt() * mode.board_size(tuning) / 2.
+                * 0.85;
+        let position = Vec2::new(angle.cos(), angle.sin()) * distance;
+        commands
+            .spawn_bundle(TransformBundle::from_transform(
+                Transform::from_translation(position.extend(0.)),
+            ))
+            .insert(Pellet)
+            .insert(Position(position))
+            .insert(Rollback::new(rip.next_id()));
+    }
+}
+
+fn collect_pellets(
+    mut commands: Commands,
+    tuning: Res<Tuning>,
+    mut scoreboard: ResMut<Scoreboard>,
+    player_query: Query<(&Position, &Player)>,
+    pellet_query: Query<(Entity, &Position), With<Pellet>>,
+) {
+    for (pellet_entity, pellet_position) in pellet_query.iter() {
+        // the lowest handle wins a tie, query order isn't the same on every peer
+        let collector = player_query
+            .iter()
+            .filter(|(position, _)| {
+                position.0.distance(pellet_position.0) < (tuning.player_size + PELLET_SIZE) / 2.
+            })
+            .min_by_key(|(_, player)| player.handle);
+        if let Some((_, player)) = collector {
+            scoreboard.scores[player.handle] += 1;
+            commands.entity(pellet_entity).despawn_recursive();
+        }
+    }
+}
+
+fn accrue_survival_time(
+    mode: Res<GameMode>,
+    round: Res<RoundState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    player_query: Query<&Player>,
+) {
+    if *mode != GameMode::Survival || round.in_countdown() || scoreboard.match_over {
+        return;
+    }
+    for player in player_query.iter() {
+        scoreboard.scores[player.handle] += 1;
+    }
+}
+
+fn accrue_hill_time(
+    mode: Res<GameMode>,
+    round: Res<RoundState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    player_query: Query<(&Position, &Player)>,
+) {
+    if *mode != GameMode::Hill || round.in_countdown() || scoreboard.match_over {
+        return;
+    }
+    for (position, player) in player_query.iter() {
+        if position.0.length() < HILL_RADIUS {
+            scoreboard.scores[player.handle] += 1;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn respawn_players(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    player_query: Query<&Player>,
+) {
+    if !mode.respawns() || scoreboard.match_over {
+        return;
+    }
+    for handle in 0..mode.num_players() {
+        if player_query.iter().any(|player| player.handle == handle) {
+            continue;
+        }
+        let last_death = round.deaths.iter().rev().find(|d| d.victim == handle);
+        if let Some(death) = last_death {
+            if round.frame >= death.frame + RESPAWN_FRAMES {
+                // vary the spawn point with every death so respawns don't all land on one spot
+                let seed = round_seed(settings.seed, scoreboard.round) ^ round.deaths.len() as u32;
+                spawn_player(&mut commands, &mut rip, *mode, &tuning, seed, handle);
+            }
+        }
+    }
+}
+
+fn spawn_ghost(mut commands: Commands, mut rip: ResMut<RollbackIdProvider>, bots: Res<BotPlayers>) {
+    // ghosts are a single-player thing
+    if bots.0.is_empty() {
+        return;
+    }
+    commands
+        .spawn_bundle(TransformBundle::default())
+        .insert(GhostCycle::default())
+        .insert(Position::default())
+        .insert(Heading::default())
+        .insert(PreviousPosition::default())
+        .insert(Smoothing::default())
+        .insert(Rollback::new(rip.next_id()));
+}
+
+// Records our own inputs by round frame, so re-simulated frames just overwrite themselves
+fn record_ghost(
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    bots: Res<BotPlayers>,
+    local: Res<LocalHandle>,
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    mut ghost: ResMut<Ghost>,
+    player_query: Query<(&Position, &Heading, &Player)>,
+) {
+    if bots.0.is_empty() || scoreboard.round < ghost.recording_round {
+        return;
+    }
+    if scoreboard.round > ghost.recording_round {
+        // the last round is over, race against it from now on
+        let finished = std::mem::take(&mut ghost.recording);
+        if !finished.inputs.is_empty() {
+            finished.save();
+            ghost.replay = Some(finished);
+        }
+        ghost.recording_round = scoreboard.round;
+    }
+
+    let (position, heading) = match player_query
+        .iter()
+        .find(|(_, _, player)| player.handle == local.0)
+    {
+        Some((position, heading, _)) => (position, heading),
+        None => return,
+    };
+    if round.frame == 1 {
+        ghost.recording.spawn_position = position.0.to_array();
+        ghost.recording.spawn_rotation = heading.0;
+    }
+    let index = round.frame as usize - 1;
+    ghost.recording.inputs.resize(index, 0);
+    ghost.recording.inputs.push(inputs[local.0].0);
+}
+
+fn decide_training_frame(mut training: ResMut<Training>) {
+    training.simulate = if !training.enabled || !training.paused {
+        true
+    } else if training.steps > 0 {
+        training.steps -= 1;
+        true
+    } else {
+        false
+    };
+}
+
+fn training_frame(training: Res<Training>) -> ShouldRun {
+    if training.simulate {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Stops by itself when we die or the drill gets too long
+fn record_drill(
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    local: Res<LocalHandle>,
+    round: Res<RoundState>,
+    mut drill: ResMut<Drill>,
+    player_query: Query<(&Position, &Heading, &Player)>,
+) {
+    if drill.recording.is_none() || round.in_countdown() {
+        return;
+    }
+    let player = player_query
+        .iter()
+        .find(|(_, _, player)| player.handle == local.0);
+    let full = match (drill.recording.as_mut(), player) {
+        (Some(recording), Some((position, heading, _))) => {
+            recording.start.get_or_insert((position.0, heading.0));
+            recording.inputs.push(inputs[local.0].0);
+            recording.inputs.len() >= DRILL_MAX_FRAMES
+        }
+        _ => true,
+    };
+    if full {
+        drill.stop_recording();
+    }
+}
+
+// Before check_round_end empties the trails, and a stage after the deaths were despawned, so
+// whoever died this frame is already gone
+fn record_killcam(
+    clock: Res<SimulationClock>,
+    round: Res<RoundState>,
+    mut log: ResMut<KillcamLog>,
+    player_query: Query<(&Position, &Heading, &Player)>,
+    trail_query: Query<&Trail>,
+) {
+    while matches!(log.frames.back(), Some(last) if last.frame >= clock.frame) {
+        log.frames.pop_back();
+    }
+    let died = matches!(round.deaths.last(), Some(death) if death.frame == round.frame);
+    let trails = died.then(|| {
+        trail_query
+            .iter()
+            .map(|trail| (trail.player_handle, trail.points.clone()))
+            .collect()
+    });
+    log.frames.push_back(KillcamFrame {
+        frame: clock.frame,
+        cycles: player_query
+            .iter()
+            .map(|(position, heading, player)| (player.handle, position.0, heading.0))
+            .collect(),
+        trails,
+    });
+    if log.frames.len() > KILLCAM_LOG_FRAMES {
+        log.frames.pop_front();
+    }
+}
+
+// Not while watching a replay, whose inputs already drive every bot. Ones with a drill never
+// get exported in the first place.
+fn play_drill(
+    local: Res<LocalHandle>,
+    bots: Res<BotPlayers>,
+    round: Res<RoundState>,
+    drill: Res<Drill>,
+    mut replay: ResMut<Replay>,
+    mut inputs: ResMut<Vec<(u8, InputStatus)>>,
+    mut player_query: Query<(&mut Position, &mut Heading, &Player)>,
+) {
+    if replay.log.is_some() {
+        return;
+    }
+    let (snippet, (start_position, start_heading)) = match &drill.snippet {
+        Some(
+            snippet @ DrillSnippet {
+                start: Some(start), ..
+            },
+        ) => (snippet, *start),
+        _ => return,
+    };
+    let handle = match bots.0.iter().find(|handle| **handle != local.0) {
+        Some(handle) => *handle,
+        None => return,
+    };
+    for (mut position, mut heading, player) in player_query.iter_mut() {
+        if player.handle != handle {
+            continue;
+        }
+        // it sits there through the countdown like everyone else
+        if round.frame == 1 {
+            position.0 = start_position;
+            *heading = Heading::new(start_heading);
+            replay.drilled = true;
+        }
+        if round.in_countdown() {
+            continue;
+        }
+        let index = (round.frame - COUNTDOWN_FRAMES - 1) as usize;
+        if let Some(input) = snippet.inputs.get(index) {
+            inputs[handle].0 = *input;
+        }
+    }
+}
+
+fn move_ghost(
+    settings: Res<MatchSettings>,
+    steering: Res<Steering>,
+    round: Res<RoundState>,
+    ghost: Res<Ghost>,
+    mut ghost_query: Query<(&mut Position, &mut Heading, &mut GhostCycle)>,
+    zone_query: Query<&BoostZone>,
+) {
+    let replay = match &ghost.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+    for (mut position, mut heading, mut cycle) in ghost_query.iter_mut() {
+        if round.frame == 1 {
+            *position = Position(Vec2::from(replay.spawn_position));
+            *heading = Heading::new(replay.spawn_rotation);
+            cycle.previous_input = 0;
+            cycle.dash = DashEnergy::default();
+        }
+        if round.in_countdown() {
+            continue;
+        }
+        let input = match replay.inputs.get(round.frame as usize - 1) {
+            Some(input) => *input,
+            None => continue,
+        };
+
+        steer(
+            &mut position,
+            &mut heading,
+            input,
+            cycle.previous_input,
+            *steering,
+            settings.turn_speed,
+        );
+        cycle.previous_input = input;
+
+        let mut speed_multiplier = 1.;
+        if cycle.dash.dash(input) {
+            speed_multiplier *= 2.;
+        }
+        if zone_query.iter().any(|zone| zone.contains(position.0)) {
+            speed_multiplier *= BOOST_MULTIPLIER;
+        }
+        position.0 += heading.direction() * settings.move_speed * speed_multiplier;
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use bevy::{app::ScheduleRunnerSettings, prelude::*, window::WindowSettings};
+
+use crate::gameplay::TICKS_PER_SECOND;
+
+// Stands in for DefaultPlugins and the rendering, sound and UI plugins with --headless. The
+// simulation runs as usual, but nothing is drawn or played and no window opens. Logging is
+// left to whoever adds it, since tests put several of these in one process and it can only
+// be set up once.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1. / TICKS_PER_SECOND as f64,
+        )))
+        // without a window to close, the app would quit as soon as it noticed
+        .insert_resource(WindowSettings {
+            add_primary_window: false,
+            exit_on_all_closed: false,
+            ..default()
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(bevy::transform::TransformPlugin)
+        .add_plugin(bevy::hierarchy::HierarchyPlugin)
+        .add_plugin(bevy::input::InputPlugin)
+        .add_plugin(bevy::window::WindowPlugin)
+        .add_plugin(bevy::asset::AssetPlugin);
+    }
+}
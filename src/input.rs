@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gameplay::{BotPlayers, Emote, Heading, Player, Position, SimulationClock},
+    networking::LocalHandle,
+    settings::Settings,
+    GameState,
+};
+
+// Turns keys, mice, touches and gamepads into the inputs GGRS sends around
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MouseSteering::default())
+            .insert_resource(PendingEmote::default())
+            .insert_resource(PendingForfeit::default())
+            .insert_resource(RematchVote::default())
+            .insert_resource(PauseMenu::default())
+            .insert_resource(RecentInputs::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(layout_touch_zones)
+                    .with_system(aim_with_mouse),
+            );
+    }
+}
+
+// Picked from the wheel but not sent yet, goes out with the next input
+#[derive(Default)]
+pub struct PendingEmote(pub Option<Emote>);
+
+// Conceded from the pause menu, goes out with the next input
+#[derive(Default)]
+pub struct PendingForfeit(pub bool);
+
+// Whether we've asked for a rematch, sent to everyone else as an input bit
+#[derive(Default)]
+pub struct RematchVote(pub bool);
+
+// Whether the pause overlay is up. Only we see it, the match carries on underneath and our
+// inputs go out empty until it's closed.
+#[derive(Default)]
+pub struct PauseMenu(pub bool);
+
+// The last few changes to what we send, for the diagnostics overlay, so a turn that really
+// went out late can be told apart from one held up by the connection
+#[derive(Default)]
+pub struct RecentInputs {
+    // frames between reading an input and simulating it, 0 outside online matches
+    pub delay: usize,
+    // (frame it was read for, input), newest last
+    pub changes: VecDeque<(u32, u8)>,
+}
+
+impl RecentInputs {
+    fn record(&mut self, frame: u32, input: u8) {
+        if self.changes.back().map(|(_, last)| *last) == Some(input) {
+            return;
+        }
+        self.changes.push_back((frame, input));
+        if self.changes.len() > RECENT_INPUTS_LENGTH {
+            self.changes.pop_front();
+        }
+    }
+}
+
+// On-screen button for phones, only shown once the screen has been touched
+#[derive(Component)]
+pub struct TouchZone(pub InputAction);
+
+// Input bits worked out from the cursor each frame, only used with mouse steering on
+#[derive(Default)]
+pub struct MouseSteering(pub u8);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputAction {
+    Left,
+    Right,
+    Dash,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 3] = [InputAction::Left, InputAction::Right, InputAction::Dash];
+
+    // gamepad controls are fixed, these just tell the player what they are
+    pub fn pad_glyph(&self) -> &'static str {
+        match self {
+            InputAction::Left => "D-Pad \u{2190} / Stick \u{2190}",
+            InputAction::Right => "D-Pad \u{2192} / Stick \u{2192}",
+            InputAction::Dash => "A / RT",
+        }
+    }
+
+    // (left, top, width, height) in percent of the window, turning goes to the thumbs and
+    // dash sits in between in landscape, or along the bottom in portrait
+    pub fn touch_rect(&self, portrait: bool) -> (f32, f32, f32, f32) {
+        match (self, portrait) {
+            (InputAction::Left, false) => (0., 0., 35., 100.),
+            (InputAction::Right, false) => (65., 0., 35., 100.),
+            (InputAction::Dash, false) => (35., 50., 30., 50.),
+            (InputAction::Left, true) => (0., 40., 50., 40.),
+            (InputAction::Right, true) => (50., 40., 50., 40.),
+            (InputAction::Dash, true) => (0., 80., 100., 20.),
+        }
+    }
+
+    pub fn touch_label(&self) -> &'static str {
+        match self {
+            InputAction::Left => "\u{25c0}",
+            InputAction::Right => "\u{25b6}",
+            InputAction::Dash => "DASH",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub dash: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            left: vec![KeyCode::Left, KeyCode::A],
+            right: vec![KeyCode::Right, KeyCode::D],
+            dash: vec![KeyCode::Space, KeyCode::Return],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: InputAction) -> &Vec<KeyCode> {
+        match action {
+            InputAction::Left => &self.left,
+            InputAction::Right => &self.right,
+            InputAction::Dash => &self.dash,
+        }
+    }
+
+    pub fn keys_mut(&mut self, action: InputAction) -> &mut Vec<KeyCode> {
+        match action {
+            InputAction::Left => &mut self.left,
+            InputAction::Right => &mut self.right,
+            InputAction::Dash => &mut self.dash,
+        }
+    }
+
+    // The new key becomes the primary one and the old primary sticks around as the
+    // alternative, a key only ever belongs to one action
+    pub fn bind(&mut self, action: InputAction, key: KeyCode) {
+        for other in InputAction::ALL {
+            self.keys_mut(other).retain(|k| *k != key);
+        }
+        let keys = self.keys_mut(action);
+        keys.insert(0, key);
+        keys.truncate(MAX_KEYS_PER_ACTION);
+    }
+}
+
+// held down to bring up the emote wheel
+pub const EMOTE_WHEEL_KEY: KeyCode = KeyCode::Tab;
+
+pub const INPUT_LEFT: u8 = 1 << 0;
+
+pub const INPUT_RIGHT: u8 = 1 << 1;
+
+pub const INPUT_DASH: u8 = 1 << 2;
+
+pub const INPUT_REMATCH: u8 = 1 << 3;
+
+pub const INPUT_EMOTE_SHIFT: u8 = 4;
+
+pub const INPUT_EMOTE: u8 = 0b111 << INPUT_EMOTE_SHIFT;
+
+pub const INPUT_FORFEIT: u8 = 1 << 7;
+
+const MAX_KEYS_PER_ACTION: usize = 2;
+
+const RECENT_INPUTS_LENGTH: usize = 8;
+
+// how far the stick has to lean before it counts as a turn
+const STICK_THRESHOLD: f32 = 0.5;
+
+// radians off our heading the cursor can be before we turn towards it
+const MOUSE_DEADZONE: f32 = 0.1;
+
+fn layout_touch_zones(
+    windows: Res<Windows>,
+    touches: Res<Touches>,
+    mut touched: Local<bool>,
+    mut zone_query: Query<(&TouchZone, &mut Style, &mut Visibility)>,
+) {
+    // keyboard players never see them
+    *touched |= touches.iter().next().is_some();
+    let portrait = match windows.get_primary() {
+        Some(window) => window.height() > window.width(),
+        None => return,
+    };
+    for (zone, mut style, mut visibility) in zone_query.iter_mut() {
+        visibility.is_visible = *touched;
+        let (left, top, width, height) = zone.0.touch_rect(portrait);
+        style.position = UiRect {
+            left: Val::Percent(left),
+            top: Val::Percent(top),
+            ..default()
+        };
+        style.size = Size::new(Val::Percent(width), Val::Percent(height));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn input(
+    handle: In<ggrs::PlayerHandle>,
+    keys: Res<Input<KeyCode>>,
+    bots: Res<BotPlayers>,
+    options: Res<Settings>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    touches: Res<Touches>,
+    windows: Res<Windows>,
+    mouse: Res<MouseSteering>,
+    rematch: Res<RematchVote>,
+    pause: Res<PauseMenu>,
+    mut emote: ResMut<PendingEmote>,
+    mut forfeit: ResMut<PendingForfeit>,
+    clock: Res<SimulationClock>,
+    mut recent: ResMut<RecentInputs>,
+) -> u8 {
+    let mut input = 0u8;
+
+    // bot inputs are filled in by steer_bots during the simulation
+    if bots.0.contains(&handle.0) {
+        return input;
+    }
+    // conceding happens from the pause menu, so it has to get through while that's open
+    if std::mem::take(&mut forfeit.0) {
+        input |= INPUT_FORFEIT;
+    }
+    // the match doesn't wait for us, we just stop steering
+    if pause.0 {
+        return input;
+    }
+
+    let bindings = &options.key_bindings;
+    if keys.any_pressed(bindings.left.iter().copied()) {
+        input |= INPUT_LEFT
+    }
+    if keys.any_pressed(bindings.right.iter().copied()) {
+        input |= INPUT_RIGHT;
+    }
+    if keys.any_pressed(bindings.dash.iter().copied()) {
+        input |= INPUT_DASH;
+    }
+
+    for gamepad in gamepads.iter() {
+        input |= gamepad_input(*gamepad, &buttons, &axes);
+    }
+    if let Some(window) = windows.get_primary() {
+        input |= touch_input(&touches, window);
+    }
+    if options.mouse_steering {
+        input |= mouse.0;
+    }
+    if rematch.0 {
+        input |= INPUT_REMATCH;
+    }
+    if let Some(emote) = emote.0.take() {
+        input |= emote.to_input();
+    }
+    // asked for before the frame after the last one simulated, and only the steering since
+    // emotes and votes would just be noise
+    recent.record(
+        clock.frame + 1,
+        input & (INPUT_LEFT | INPUT_RIGHT | INPUT_DASH),
+    );
+
+    input
+}
+
+// Quantizes "which side of my heading is the cursor on" into the usual turn bits, so it's
+// no different from the keyboard as far as the rollback is concerned
+fn aim_with_mouse(
+    options: Res<Settings>,
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    local: Res<LocalHandle>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    // what's drawn lags the simulation a touch, so aim from where it really is
+    player_query: Query<(&Position, &Heading, &Player)>,
+    mut mouse: ResMut<MouseSteering>,
+) {
+    mouse.0 = 0;
+    if !options.mouse_steering {
+        return;
+    }
+    let (window, (camera, camera_transform)) =
+        match (windows.get_primary(), camera_query.get_single()) {
+            (Some(window), Ok(camera)) => (window, camera),
+            _ => return,
+        };
+    let (position, heading, _) = match player_query.iter().find(|(_, _, p)| p.handle == local.0) {
+        Some(player) => player,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (cursor / window_size) * 2. - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let cursor_world = ndc_to_world.project_point3(ndc.extend(-1.)).truncate();
+
+    let to_cursor = cursor_world - position.0;
+    let angle = heading.direction().angle_between(to_cursor);
+    if angle > MOUSE_DEADZONE {
+        mouse.0 |= INPUT_LEFT;
+    } else if angle < -MOUSE_DEADZONE {
+        mouse.0 |= INPUT_RIGHT;
+    }
+    if mouse_buttons.pressed(MouseButton::Left) {
+        mouse.0 |= INPUT_DASH;
+    }
+}
+
+fn touch_input(touches: &Touches, window: &Window) -> u8 {
+    let mut input = 0u8;
+    let portrait = window.height() > window.width();
+    for touch in touches.iter() {
+        // touch positions start at the top left, same as the ui
+        let x = touch.position().x / window.width() * 100.;
+        let y = touch.position().y / window.height() * 100.;
+        for action in InputAction::ALL {
+            let (left, top, width, height) = action.touch_rect(portrait);
+            if x >= left && x < left + width && y >= top && y < top + height {
+                input |= match action {
+                    InputAction::Left => INPUT_LEFT,
+                    InputAction::Right => INPUT_RIGHT,
+                    InputAction::Dash => INPUT_DASH,
+                };
+            }
+        }
+    }
+    input
+}
+
+fn gamepad_input(gamepad: Gamepad, buttons: &Input<GamepadButton>, axes: &Axis<GamepadAxis>) -> u8 {
+    let mut input = 0u8;
+    let pressed = |button_type| buttons.pressed(GamepadButton::new(gamepad, button_type));
+    let stick_x = axes
+        .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+        .unwrap_or(0.);
+
+    if pressed(GamepadButtonType::DPadLeft) || stick_x < -STICK_THRESHOLD {
+        input |= INPUT_LEFT;
+    }
+    if pressed(GamepadButtonType::DPadRight) || stick_x > STICK_THRESHOLD {
+        input |= INPUT_RIGHT;
+    }
+    if pressed(GamepadButtonType::South) || pressed(GamepadButtonType::RightTrigger2) {
+        input |= INPUT_DASH;
+    }
+
+    input
+}
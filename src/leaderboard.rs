@@ -0,0 +1,279 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::Args,
+    gameplay::{GameMode, Scoreboard, SimulationClock, TICKS_PER_SECOND},
+    networking::LocalHandle,
+    rendering::Cosmetics,
+    GameState,
+};
+
+// Posts finished matches to a leaderboard server given with --leaderboard, and fetches the top
+// of the board for the main menu. Without one nothing here does anything. Requests run in the
+// background and get checked on every frame, so a slow server never holds up a frame.
+pub struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        let endpoint = app.world.resource::<Args>().leaderboard.clone();
+        app.insert_resource(Leaderboard {
+            endpoint,
+            entries: Vec::new(),
+        })
+        .insert_non_send_resource(LeaderboardRequests::default())
+        .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(fetch_leaderboard))
+        .add_system_set(SystemSet::on_in_stack_update(GameState::InGame).with_system(submit_result))
+        .add_system(poll_requests);
+    }
+}
+
+pub struct Leaderboard {
+    // base URL, results get posted to /results and the board comes from /top
+    pub endpoint: Option<String>,
+    // best first, empty until the first fetch comes back
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: u32,
+}
+
+// What gets posted once a match is decided
+#[derive(Serialize)]
+pub struct MatchResult {
+    pub mode: GameMode,
+    // names on the winning team, None for a shared lead
+    pub winner: Option<String>,
+    pub score: u32,
+    // seconds from the first countdown to the end
+    pub duration: f32,
+}
+
+// Browser requests can't leave the main thread, so these stay there on every platform
+#[derive(Default)]
+pub struct LeaderboardRequests {
+    pub fetch: Option<PendingRequest>,
+    pub submits: Vec<PendingRequest>,
+}
+
+fn fetch_leaderboard(leaderboard: Res<Leaderboard>, mut requests: NonSendMut<LeaderboardRequests>) {
+    let endpoint = match &leaderboard.endpoint {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+    if requests.fetch.is_none() {
+        let url = format!("{}/top?count={}", endpoint.trim_end_matches('/'), TOP_COUNT);
+        requests.fetch = Some(PendingRequest::send("GET", &url, None));
+    }
+}
+
+// Only whoever has handle 0 posts, so a match doesn't show up once per peer
+#[allow(clippy::too_many_arguments)]
+fn submit_result(
+    leaderboard: Res<Leaderboard>,
+    mode: Res<GameMode>,
+    local: Res<LocalHandle>,
+    cosmetics: Res<Cosmetics>,
+    scoreboard: Res<Scoreboard>,
+    clock: Res<SimulationClock>,
+    mut requests: NonSendMut<LeaderboardRequests>,
+    // (whether this match has been posted, frame it started on)
+    mut submitted: Local<(bool, u32)>,
+) {
+    let (posted, started) = &mut *submitted;
+    if !scoreboard.match_over {
+        if *posted {
+            *posted = false;
+            *started = clock.frame;
+        }
+        return;
+    }
+    if *posted {
+        return;
+    }
+    *posted = true;
+    let endpoint = match &leaderboard.endpoint {
+        Some(endpoint) if local.0 == 0 => endpoint,
+        _ => return,
+    };
+
+    let score = scoreboard.scores.iter().max().copied().unwrap_or(0);
+    let leaders: Vec<usize> = (0..scoreboard.scores.len())
+        .filter(|team| scoreboard.scores[*team] == score)
+        .collect();
+    let winner = match leaders[..] {
+        [team] => Some(
+            (0..mode.num_players())
+                .filter(|handle| mode.team_of(*handle) == team)
+                .map(|handle| cosmetics.player_name(handle))
+                .collect::<Vec<_>>()
+                .join(" & "),
+        ),
+        _ => None,
+    };
+    let result = MatchResult {
+        mode: *mode,
+        winner,
+        score,
+        duration: clock.frame.saturating_sub(*started) as f32 / TICKS_PER_SECOND as f32,
+    };
+    let body = serde_json::to_string(&result).expect("failed to serialize match result");
+    let url = format!("{}/results", endpoint.trim_end_matches('/'));
+    requests
+        .submits
+        .push(PendingRequest::send("POST", &url, Some(body)));
+}
+
+fn poll_requests(
+    mut leaderboard: ResMut<Leaderboard>,
+    mut requests: NonSendMut<LeaderboardRequests>,
+) {
+    if let Some(response) = requests.fetch.as_ref().and_then(PendingRequest::poll) {
+        requests.fetch = None;
+        let entries = response.and_then(|body| {
+            serde_json::from_str::<Vec<LeaderboardEntry>>(&body).map_err(|e| e.to_string())
+        });
+        match entries {
+            Ok(entries) => leaderboard.entries = entries,
+            Err(e) => warn!("failed to fetch the leaderboard: {}", e),
+        }
+    }
+    requests.submits.retain(|request| match request.poll() {
+        Some(Err(e)) => {
+            warn!("failed to post the match result: {}", e);
+            false
+        }
+        Some(Ok(_)) => false,
+        None => true,
+    });
+}
+
+// A request in flight, with the response body once it's back
+#[cfg(not(target_arch = "wasm32"))]
+pub struct PendingRequest(std::sync::Arc<std::sync::Mutex<Option<Result<String, String>>>>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PendingRequest {
+    fn send(method: &'static str, url: &str, body: Option<String>) -> Self {
+        let response: std::sync::Arc<std::sync::Mutex<_>> = std::sync::Arc::default();
+        let slot = std::sync::Arc::clone(&response);
+        let url = url.to_string();
+        // the socket stays blocked for the whole request, which would hold up the matchbox
+        // message loop if this ran on the IO pool with it
+        bevy::tasks::AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let result = http_request(method, &url, body.as_deref());
+                *slot.lock().unwrap() = Some(result);
+            })
+            .detach();
+        Self(response)
+    }
+
+    fn poll(&self) -> Option<Result<String, String>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+// Plain HTTP/1.0, so the body never comes back chunked. No TLS, a leaderboard behind https
+// needs a proxy in front of it.
+#[cfg(not(target_arch = "wasm32"))]
+fn http_request(method: &str, url: &str, body: Option<&str>) -> Result<String, String> {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("{} isn't an http:// URL", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(&address).map_err(|e| e.to_string())?;
+    let timeout = Some(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS));
+    stream
+        .set_read_timeout(timeout)
+        .map_err(|e| e.to_string())?;
+    stream
+        .set_write_timeout(timeout)
+        .map_err(|e| e.to_string())?;
+    let body = body.unwrap_or_default();
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        host,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("malformed response")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(format!("server answered {}", status));
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct PendingRequest(Option<web_sys::XmlHttpRequest>);
+
+// XHR rather than fetch, since its state can be polled without any callbacks into Rust
+#[cfg(target_arch = "wasm32")]
+impl PendingRequest {
+    fn send(method: &'static str, url: &str, body: Option<String>) -> Self {
+        let request = web_sys::XmlHttpRequest::new().ok().filter(|request| {
+            request.open(method, url).is_ok()
+                && request
+                    .set_request_header("Content-Type", "application/json")
+                    .is_ok()
+                && request.send_with_opt_str(body.as_deref()).is_ok()
+        });
+        if let Some(request) = &request {
+            request.set_timeout(REQUEST_TIMEOUT_SECS as u32 * 1000);
+        }
+        Self(request)
+    }
+
+    fn poll(&self) -> Option<Result<String, String>> {
+        let request = match &self.0 {
+            Some(request) => request,
+            None => return Some(Err("couldn't send the request".to_string())),
+        };
+        // DONE, which is also where errors and timeouts end up
+        if request.ready_state() != 4 {
+            return None;
+        }
+        let status = request.status().unwrap_or(0);
+        if !(200..300).contains(&status) {
+            return Some(Err(format!("server answered {}", status)));
+        }
+        Some(Ok(request
+            .response_text()
+            .ok()
+            .flatten()
+            .unwrap_or_default()))
+    }
+}
+
+const TOP_COUNT: usize = 10;
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
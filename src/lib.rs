@@ -0,0 +1,29 @@
+pub mod args;
+pub mod bench;
+pub mod chat;
+pub mod daily;
+pub mod gameplay;
+pub mod headless;
+pub mod input;
+pub mod leaderboard;
+pub mod networking;
+pub mod profile;
+pub mod profiling;
+pub mod rendering;
+pub mod replay;
+pub mod settings;
+pub mod sound;
+pub mod stepping;
+pub mod tuning;
+pub mod ui;
+pub mod victory;
+
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub enum GameState {
+    MainMenu,
+    SettingsMenu,
+    Matchmaking,
+    InGame,
+    // pushed over InGame once a match is won
+    Victory,
+}
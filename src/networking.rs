@@ -0,0 +1,1727 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+use bevy_ggrs::*;
+use ggrs::{
+    GGRSEvent, GGRSRequest, Message, NonBlockingSocket, P2PSession, PlayerType, SessionState,
+    SpectatorSession,
+};
+use matchbox_socket::{
+    MultipleChannels, PeerId, PeerState, RtcIceServerConfig, WebRtcChannel, WebRtcSocket,
+    WebRtcSocketBuilder,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    args::Args,
+    chat::{ChatDraft, MAX_CHAT_LENGTH},
+    daily::Daily,
+    gameplay::{
+        pseudo_random, seed_from_str, BotPlayers, GameMode, MatchSettings, SimulationClock,
+        Steering, Training, TICKS_PER_SECOND,
+    },
+    input::RecentInputs,
+    rendering::Cosmetics,
+    replay::Replay,
+    settings::{IceServerSettings, PlayerProfile, Settings},
+    tuning::Tuning,
+    GameState,
+};
+
+// Finding peers, agreeing on match settings and starting the GGRS session
+pub struct NetworkingPlugin;
+
+impl Plugin for NetworkingPlugin {
+    fn build(&self, app: &mut App) {
+        let args = app.world.resource::<Args>();
+        let candidates = std::iter::once(args.server.clone())
+            .chain(args.fallback_servers.iter().cloned())
+            .collect();
+        let queue = args.room.clone().map_or(Queue::Casual, Queue::Custom);
+        let proposal = MatchSettings::from_args(args, app.world.resource::<Tuning>());
+        app.insert_resource(LocalHandle::default())
+            .insert_resource(queue)
+            .insert_resource(ServerStatus {
+                candidates,
+                ..default()
+            })
+            .insert_non_send_resource(None::<ServerProbe>)
+            .insert_resource(None::<PeerSocket>)
+            .insert_resource(ConnectionStatus::default())
+            .insert_resource(FrameSkip::default())
+            .insert_resource(Broadcast::default())
+            .insert_resource(Reconnect::default())
+            .insert_resource(Lobby {
+                proposal,
+                ..default()
+            })
+            .add_system_set(SystemSet::on_update(GameState::MainMenu).with_system(skip_menu))
+            // the socket outlives matchmaking, chat carries on over it after the match
+            .add_system_to_stage(CoreStage::PreUpdate, poll_socket)
+            .add_system_set(SystemSet::on_enter(GameState::Matchmaking).with_system(start_probing))
+            .add_system_set(
+                SystemSet::on_update(GameState::Matchmaking)
+                    .with_system(poll_server_probe)
+                    .with_system(wait_for_players)
+                    .with_system(ping_peers.before(wait_for_players))
+                    .with_system(time_out_matchmaking.after(wait_for_players))
+                    .with_system(start_bot_match),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Matchmaking).with_system(stop_probing))
+            .add_system_set(
+                SystemSet::on_in_stack_update(GameState::InGame)
+                    .with_system(watch_connection)
+                    .with_system(feed_spectators)
+                    .with_system(report_watched)
+                    .with_system(poll_reconnect.after(watch_connection)),
+            )
+            // either side of the GGRS stage, which comes right before Update
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                park_session.exclusive_system().at_end(),
+            )
+            .add_system_to_stage(
+                CoreStage::Update,
+                unpark_session.exclusive_system().at_start(),
+            );
+    }
+}
+
+// Which handle is ours, for the parts of the HUD that only show our own cycle
+#[derive(Default)]
+pub struct LocalHandle(pub usize);
+
+impl LocalHandle {
+    // past every handle there is, spectators have no cycle of their own
+    pub const SPECTATOR: LocalHandle = LocalHandle(usize::MAX);
+
+    pub fn spectating(&self) -> bool {
+        self.0 == Self::SPECTATOR.0
+    }
+}
+
+// Which rooms we look for peers in. Queues never share a room, so ranked players only ever
+// meet others playing by the standard rules.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Queue {
+    // anyone playing the same mode
+    Casual,
+    // rooms filled by people only, on the default settings whatever tuning.json says
+    Ranked,
+    // only whoever has the same room code, on whichever settings handle 0 proposes
+    Custom(String),
+}
+
+impl Queue {
+    pub fn room_name(&self, mode: GameMode) -> String {
+        match self {
+            Queue::Casual => mode.room_name().to_string(),
+            Queue::Ranked => format!("{}_ranked", mode.room_name()),
+            // the host picks the mode, so only the number of players has to line up
+            Queue::Custom(room) => format!("{}_custom_{}", room, mode.num_players()),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Queue::Casual => "Casual".to_string(),
+            Queue::Ranked => "Ranked".to_string(),
+            Queue::Custom(room) => format!("Custom room {}", room),
+        }
+    }
+
+    // how many of the room get a cycle, the rest watch
+    pub fn num_humans(&self, mode: GameMode, args: &Args) -> usize {
+        match self {
+            Queue::Ranked => mode.num_players(),
+            Queue::Casual | Queue::Custom(_) => num_humans(mode, args),
+        }
+    }
+
+    pub fn proposal(&self, proposal: &MatchSettings) -> MatchSettings {
+        match self {
+            Queue::Ranked => MatchSettings {
+                seed: proposal.seed,
+                ..default()
+            },
+            Queue::Casual | Queue::Custom(_) => *proposal,
+        }
+    }
+}
+
+// A fresh code for a custom room, for friends to pass along with --room or ?room=
+pub fn custom_room_code(time: &Time) -> String {
+    let mut seed = seed_from_str(&time.seconds_since_startup().to_string());
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| {
+            seed = pseudo_random(seed);
+            (b'a' + (seed % 26) as u8) as char
+        })
+        .collect()
+}
+
+// How the search is going, so a dead server or an empty room show up as such instead of an
+// endless search
+#[derive(Default)]
+pub struct ServerStatus {
+    // --server first, then each --fallback-server in order
+    pub candidates: Vec<String>,
+    pub current: usize,
+    // waiting to hear whether the current candidate answers
+    pub probing: bool,
+    // why the last candidate failed, once there's none left to try
+    pub error: Option<String>,
+    // seconds since startup to give up on finding peers at, None without a socket or a timeout
+    pub deadline: Option<f64>,
+    // nobody joined before the deadline
+    pub timed_out: bool,
+    // searches that timed out since we started, each one waits longer than the last
+    pub retries: u32,
+}
+
+impl ServerStatus {
+    pub fn server(&self) -> &str {
+        &self.candidates[self.current]
+    }
+
+    // still looking for a server rather than for peers
+    pub fn connecting(&self) -> bool {
+        self.probing || self.failed()
+    }
+
+    // the search is over without a match, until someone retries
+    pub fn failed(&self) -> bool {
+        self.error.is_some() || self.timed_out
+    }
+
+    // seconds to wait for peers this time, doubling with each retry
+    pub fn timeout(&self, base: u32) -> f64 {
+        base as f64 * 2f64.powi(self.retries.min(MAX_TIMEOUT_DOUBLINGS) as i32)
+    }
+}
+
+// How the peers of a running match are doing, from the events GGRS queues up. A peer that goes
+// quiet gets until the disconnect timeout to come back, over a new socket if it has to (see
+// Reconnect), after which its cycle rides on with empty inputs.
+#[derive(Default)]
+pub struct ConnectionStatus {
+    pub handles: HashMap<PeerId, usize>,
+    // (roundtrips done, roundtrips needed) for each peer still being synchronized with
+    pub synchronizing: HashMap<PeerId, (u32, u32)>,
+    // seconds since startup each quiet peer gets dropped at
+    pub interrupted: HashMap<PeerId, f64>,
+    // with the seconds since startup they dropped at
+    pub disconnected: Vec<(PeerId, f64)>,
+    // seconds since startup GGRS last asked us to wait for a peer that's falling behind
+    pub last_wait: Option<f64>,
+    // whoever has handle 0, while we're only watching them
+    pub watching: Option<PeerId>,
+}
+
+impl ConnectionStatus {
+    pub fn handle(&self, peer: &PeerId) -> Option<usize> {
+        self.handles.get(peer).copied()
+    }
+}
+
+// Frames GGRS asked us to sit out, so a peer that's fallen behind can catch up instead of
+// rolling back every frame
+#[derive(Default)]
+struct FrameSkip {
+    // seconds since startup to stop skipping at
+    until: f64,
+    // the real session, while the GGRS stage is shown a stand-in
+    session: Option<P2PSession<GgrsConfig>>,
+}
+
+// For the stand-in spectator session, which never hears from a host so it never advances
+struct NoSocket;
+
+impl NonBlockingSocket<PeerId> for NoSocket {
+    fn send_to(&mut self, _: &Message, _: &PeerId) {}
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        Vec::new()
+    }
+}
+
+// What the matchmaking socket stays as once the match starts: GGRS takes the unreliable
+// channel, and the lobby keeps the reliable one for chat
+pub type PeerSocket = WebRtcSocket<MultipleChannels>;
+
+const GGRS_CHANNEL: usize = 0;
+
+const LOBBY_CHANNEL: usize = 1;
+
+// spectators watch over a channel of their own, the match's session already has the first
+const SPECTATE_CHANNEL: usize = 2;
+
+// how far a late joiner's stream gets ahead of what they last said they'd watched, short of
+// the 60 frames a GGRS spectator can hold on to
+const SPECTATOR_LEAD_FRAMES: u32 = 45;
+
+// how often a spectator tells the host how far they've got
+const SPECTATOR_REPORT_FRAMES: u32 = 10;
+
+// Spectators watch from the first frame, fast forwarding this many frames a tick while
+// they're more than SPECTATOR_MAX_FRAMES_BEHIND behind
+const SPECTATOR_CATCHUP_SPEED: usize = 8;
+
+const SPECTATOR_MAX_FRAMES_BEHIND: usize = 12;
+
+const CHAT_HISTORY: usize = 50;
+
+const PING_INTERVAL_FRAMES: u32 = 30;
+
+// how much of the old average each new ping keeps
+const PING_SMOOTHING: f64 = 0.7;
+
+// as high as the settings menu goes
+const MAX_INPUT_DELAY: usize = 8;
+
+// long enough to ride out a wifi hiccup or a tab briefly in the background, GGRS gives up on a
+// peer after 2 seconds by default
+const DISCONNECT_TIMEOUT_SECS: u64 = 10;
+
+const DISCONNECT_NOTIFY_MILLIS: u64 = 500;
+
+// retries past this many wait no longer than the last
+const MAX_TIMEOUT_DOUBLINGS: u32 = 3;
+
+const ROOM_CODE_LENGTH: usize = 5;
+
+// how many frames a synctest rolls back and resimulates every frame, as deep as a P2P
+// session's default prediction window lets a rollback go
+pub const SYNCTEST_CHECK_DISTANCE: usize = 8;
+
+pub struct GgrsConfig;
+
+impl ggrs::Config for GgrsConfig {
+    // 4-directions + fire fits easily in a single byte
+    type Input = u8;
+    type State = u8;
+    type Address = PeerId;
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum LobbyMessage {
+    Settings(Proposal),
+    Profile(PlayerProfile),
+    // the proposal we're ready to play and everyone we can see in the room, None while we
+    // aren't
+    Ready(Option<(Proposal, Vec<PeerId>)>),
+    Chat(String),
+    // seconds since the sender's startup, echoed straight back as a Pong
+    Ping(f64),
+    Pong(f64),
+    // the match running in the room, from whoever has handle 0 to anyone without a cycle in it
+    Spectate(Watch),
+    // SimulationClock::frame a spectator has got to, so the stream doesn't outrun them
+    Watched(u32),
+    // who we were at the start of the match, to whoever we meet on a reconnect socket
+    Resume(PeerId),
+}
+
+// What a spectator needs to know to watch the match
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Watch {
+    pub proposal: Proposal,
+    // by handle, for everyone who got a cycle
+    pub profiles: Vec<Option<PlayerProfile>>,
+    pub bots: Vec<usize>,
+}
+
+// What whoever gets handle 0 decides for everyone in the lobby
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Proposal {
+    pub mode: GameMode,
+    pub settings: MatchSettings,
+}
+
+// Pre-session handshake state, and the chat that carries on past it
+#[derive(Default)]
+pub struct Lobby {
+    pub proposal: MatchSettings,
+    // settings proposed by each remote peer, keyed by peer id
+    pub proposals: HashMap<PeerId, Proposal>,
+    // likewise for their profiles
+    pub profiles: HashMap<PeerId, PlayerProfile>,
+    // whether we've pressed ready, kept across retries, and what each peer said they're ready
+    // to play, with who they could see at the time
+    pub ready: bool,
+    pub ready_peers: HashMap<PeerId, (Proposal, Vec<PeerId>)>,
+    // handle 0's proposal when we last looked, a new one sends everyone back to pressing ready
+    pub agreed: Option<Proposal>,
+    // Everyone we could see when we last said we were ready. Without a target size a custom
+    // room can be caught half-connected, so a peer that sees someone we don't would seat
+    // everyone differently.
+    pub room: Vec<PeerId>,
+    // everything said so far, oldest first, with None for our own lines
+    pub chat: Vec<(Option<PeerId>, String)>,
+    // round trip to each peer in milliseconds, smoothed over the last few pings
+    pub pings: HashMap<PeerId, f64>,
+    // peers whose last proposal was out of range, which holds the match up until they send
+    // one we can play
+    pub rejected: HashSet<PeerId>,
+    // the match we walked in on, and whoever's streaming it to us
+    pub watch: Option<(PeerId, Watch)>,
+    // how far each of our own spectators has got, by SimulationClock::frame
+    pub watched: HashMap<PeerId, u32>,
+}
+
+impl Lobby {
+    // what we propose, with the queue's rules applied
+    pub fn our_proposal(&self, queue: &Queue, mode: GameMode) -> Proposal {
+        Proposal {
+            mode,
+            settings: queue.proposal(&self.proposal),
+        }
+    }
+
+    pub fn ready_for(&self) -> Option<(Proposal, Vec<PeerId>)> {
+        self.agreed
+            .filter(|_| self.ready)
+            .map(|agreed| (agreed, self.room.clone()))
+    }
+
+    // forgets everyone we met on a socket that's gone
+    pub fn clear_peers(&mut self) {
+        self.proposals.clear();
+        self.rejected.clear();
+        self.profiles.clear();
+        self.ready_peers.clear();
+        self.agreed = None;
+        self.room.clear();
+        self.chat.clear();
+        self.pings.clear();
+        self.watch = None;
+        self.watched.clear();
+    }
+
+    // Whoever leaves stops holding the lobby up. Their profile stays, for the name on what
+    // they said.
+    fn forget_peer(&mut self, peer: &PeerId) {
+        self.proposals.remove(peer);
+        self.rejected.remove(peer);
+        self.ready_peers.remove(peer);
+        self.pings.remove(peer);
+        self.watched.remove(peer);
+    }
+
+    pub fn peer_name(&self, peer: &PeerId) -> &str {
+        self.profiles
+            .get(peer)
+            .and_then(|profile| profile.name.as_deref())
+            .unwrap_or("Player")
+    }
+
+    // Enough frames of delay to cover the trip one way to the furthest peer, which is about
+    // when rollbacks stop being noticeable
+    pub fn suggested_input_delay(&self) -> Option<usize> {
+        let worst = self.pings.values().copied().reduce(f64::max)?;
+        let frame_millis = 1000. / TICKS_PER_SECOND as f64;
+        Some(((worst / 2. / frame_millis).ceil() as usize).min(MAX_INPUT_DELAY))
+    }
+
+    fn add_chat(&mut self, peer: Option<PeerId>, mut text: String) {
+        // our box stops at the limit, but a peer's client might not
+        if text.chars().count() > MAX_CHAT_LENGTH {
+            text = text.chars().take(MAX_CHAT_LENGTH).collect();
+        }
+        self.chat.push((peer, text));
+        if self.chat.len() > CHAT_HISTORY {
+            self.chat.remove(0);
+        }
+    }
+}
+
+// Acts on --replay, --local, --synctest, --room and --players the first time the menu comes
+// up
+#[allow(clippy::too_many_arguments)]
+fn skip_menu(
+    mut commands: Commands,
+    mut done: Local<bool>,
+    args: Res<Args>,
+    replay: Res<Replay>,
+    mut state: ResMut<State<GameState>>,
+    mode: Res<GameMode>,
+    lobby: Res<Lobby>,
+    options: Res<Settings>,
+    mut bots: ResMut<BotPlayers>,
+) {
+    if *done {
+        return;
+    }
+    *done = true;
+    if let Some(log) = &replay.log {
+        start_local_match(
+            &mut commands,
+            &mut state,
+            *mode,
+            log.settings,
+            &options,
+            &mut bots,
+            true,
+        );
+        // the log already has what the bots did
+        bots.0.clear();
+        return;
+    }
+    // with nobody to play our handle, a headless run is always a synctest when it's local
+    if args.local || args.synctest || (args.headless && !args.join_room()) {
+        start_local_match(
+            &mut commands,
+            &mut state,
+            *mode,
+            lobby.proposal,
+            &options,
+            &mut bots,
+            args.synctest || args.headless,
+        );
+    } else if args.join_room() {
+        state.set(GameState::Matchmaking).unwrap();
+    }
+}
+
+// How many people the room waits for, the rest of the mode's handles go to bots
+fn num_humans(mode: GameMode, args: &Args) -> usize {
+    let num_players = mode.num_players();
+    match args.players {
+        Some(players) if players < 2 || players > num_players => {
+            warn!(
+                "{:?} needs 2 to {} players, ignoring --players {}",
+                mode, num_players, players
+            );
+            num_players
+        }
+        Some(players) => players,
+        None => num_players,
+    }
+}
+
+pub fn start_probing(
+    time: Res<Time>,
+    args: Res<Args>,
+    mut lobby: ResMut<Lobby>,
+    mut spectators: ResMut<Broadcast>,
+    mut reconnect: ResMut<Reconnect>,
+    mut status: ResMut<ServerStatus>,
+    mut probe: NonSendMut<Option<ServerProbe>>,
+) {
+    lobby.clear_peers();
+    // the last match's spectators and reconnect socket went with it
+    *spectators = Broadcast::default();
+    *reconnect = Reconnect::default();
+    // there's nobody at a headless client to press ready
+    lobby.ready = args.headless;
+    status.retries = 0;
+    status.timed_out = false;
+    retry_servers(&time, &mut status, &mut probe);
+}
+
+// Starts over from the first candidate
+pub fn retry_servers(time: &Time, status: &mut ServerStatus, probe: &mut Option<ServerProbe>) {
+    if status.timed_out {
+        status.retries += 1;
+    }
+    status.current = 0;
+    status.error = None;
+    status.timed_out = false;
+    status.probing = true;
+    *probe = Some(ServerProbe::start(
+        status.server(),
+        time.seconds_since_startup(),
+    ));
+}
+
+fn stop_probing(mut status: ResMut<ServerStatus>, mut probe: NonSendMut<Option<ServerProbe>>) {
+    *probe = None;
+    status.probing = false;
+    status.error = None;
+    status.deadline = None;
+    status.timed_out = false;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn poll_server_probe(
+    mut commands: Commands,
+    time: Res<Time>,
+    args: Res<Args>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    steering: Res<Steering>,
+    options: Res<Settings>,
+    mut status: ResMut<ServerStatus>,
+    mut probe: NonSendMut<Option<ServerProbe>>,
+) {
+    let now = time.seconds_since_startup();
+    let result = match (*probe).as_ref().and_then(|probe| probe.poll(now)) {
+        Some(result) => result,
+        None => return,
+    };
+    *probe = None;
+    match result {
+        Ok(()) => {
+            status.probing = false;
+            status.deadline = options
+                .matchmaking_timeout
+                .map(|timeout| now + status.timeout(timeout));
+            start_matchbox_socket(
+                &mut commands,
+                status.server(),
+                &args,
+                &queue,
+                *mode,
+                *steering,
+                &options.ice_server,
+            );
+        }
+        Err(e) => {
+            warn!("matchbox server {} didn't answer: {}", status.server(), e);
+            if status.current + 1 < status.candidates.len() {
+                status.current += 1;
+                *probe = Some(ServerProbe::start(status.server(), now));
+            } else {
+                status.probing = false;
+                status.error = Some(e);
+            }
+        }
+    }
+}
+
+// Drops the socket once the deadline passes without a match. Headless clients have nobody to
+// press retry, so they search again by themselves.
+#[allow(clippy::too_many_arguments)]
+fn time_out_matchmaking(
+    time: Res<Time>,
+    args: Res<Args>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    options: Res<Settings>,
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut lobby: ResMut<Lobby>,
+    mut status: ResMut<ServerStatus>,
+    mut probe: NonSendMut<Option<ServerProbe>>,
+) {
+    let deadline = match status.deadline {
+        Some(deadline) => deadline,
+        None => return,
+    };
+    let socket_players = match socket.as_ref() {
+        Some(socket) => players(socket).len(),
+        // the match started just in time
+        None => {
+            status.deadline = None;
+            return;
+        }
+    };
+    // a full lobby is only waiting on people pressing ready
+    let full = socket_players >= queue.num_humans(*mode, &args);
+    if full || time.seconds_since_startup() < deadline {
+        return;
+    }
+    if let Some(timeout) = options.matchmaking_timeout {
+        warn!(
+            "nobody joined within {:.0} seconds",
+            status.timeout(timeout)
+        );
+    }
+    *socket = None;
+    lobby.clear_peers();
+    status.deadline = None;
+    status.timed_out = true;
+    if args.headless {
+        retry_servers(&time, &mut status, &mut probe);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_matchbox_socket(
+    commands: &mut Commands,
+    server: &str,
+    args: &Args,
+    queue: &Queue,
+    mode: GameMode,
+    steering: Steering,
+    ice_server: &IceServerSettings,
+) {
+    let mut room_url = format!(
+        "{}/{}{}",
+        server,
+        queue.room_name(mode),
+        steering.room_suffix()
+    );
+    // The server splits the public queues into rooms of as many as a match takes. Anyone with
+    // a custom room's code gets into it, and whoever turns up after the match started watches.
+    if !matches!(queue, Queue::Custom(_)) {
+        room_url += &format!("?next={}", queue.num_humans(mode, args));
+    }
+    commands.insert_resource(Some(open_socket(room_url, ice_server)));
+}
+
+fn open_socket(room_url: String, ice_server: &IceServerSettings) -> PeerSocket {
+    info!("connecting to matchbox server: {:?}", room_url);
+    let (socket, message_loop) = WebRtcSocketBuilder::new(room_url)
+        .ice_server(RtcIceServerConfig {
+            urls: ice_server.urls.clone(),
+            username: ice_server.username.clone(),
+            credential: ice_server.credential.clone(),
+        })
+        .add_ggrs_channel()
+        .add_reliable_channel()
+        .add_ggrs_channel()
+        .build();
+
+    // The message loop needs to be awaited, or nothing will happen.
+    // We do this here using bevy's task system.
+    IoTaskPool::get().spawn(message_loop).detach();
+
+    socket
+}
+
+// Anyone who already has our old profile needs to hear about the change
+pub fn broadcast_profile(socket: &mut Option<PeerSocket>, profile: &PlayerProfile) {
+    if let Some(socket) = socket.as_mut() {
+        broadcast(socket, &LobbyMessage::Profile(profile.clone()));
+    }
+}
+
+pub fn broadcast_ready(socket: &mut Option<PeerSocket>, lobby: &Lobby) {
+    if let Some(socket) = socket.as_mut() {
+        broadcast(socket, &LobbyMessage::Ready(lobby.ready_for()));
+    }
+}
+
+pub fn send_chat(socket: &mut Option<PeerSocket>, lobby: &mut Lobby, text: String) {
+    if let Some(socket) = socket.as_mut() {
+        broadcast(socket, &LobbyMessage::Chat(text.clone()));
+    }
+    lobby.add_chat(None, text);
+}
+
+pub fn broadcast_proposal(socket: &mut Option<PeerSocket>, proposal: Proposal) {
+    if let Some(socket) = socket.as_mut() {
+        broadcast(socket, &LobbyMessage::Settings(proposal));
+    }
+}
+
+// Everyone connected, us included, in the order handles go out in. Everyone sorts the same
+// ids, so everyone comes up with the same order.
+pub fn players(socket: &PeerSocket) -> Vec<PlayerType<PeerId>> {
+    let ours = match socket.id() {
+        Some(id) => id,
+        // nobody can connect to us before the server gives us an id
+        None => return vec![PlayerType::Local],
+    };
+    room(socket)
+        .into_iter()
+        .map(|id| {
+            if id == ours {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(id)
+            }
+        })
+        .collect()
+}
+
+// The ids behind players, empty until the server gives us ours
+fn room(socket: &PeerSocket) -> Vec<PeerId> {
+    let mut ids: Vec<PeerId> = socket.connected_peers().chain(socket.id()).collect();
+    ids.sort();
+    ids
+}
+
+// Whether we'd get handle 0 and with it the say over the settings
+pub fn is_host(socket: &PeerSocket) -> bool {
+    matches!(players(socket).first(), Some(PlayerType::Local))
+}
+
+// Gives up on matchmaking and starts a local session where bots fill every other slot
+#[allow(clippy::too_many_arguments)]
+pub fn start_bot_match(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut socket: ResMut<Option<PeerSocket>>,
+    status: Res<ServerStatus>,
+    mut state: ResMut<State<GameState>>,
+    mode: Res<GameMode>,
+    lobby: Res<Lobby>,
+    options: Res<Settings>,
+    mut bots: ResMut<BotPlayers>,
+    // missing without the chat plugin
+    draft: Option<Res<ChatDraft>>,
+) {
+    // without a socket or a server to find, the match has already started, and B is just a
+    // letter while a chat line is being typed
+    let typing = draft.map_or(false, |draft| draft.typing);
+    if (socket.is_none() && !status.connecting()) || typing || !keys.just_pressed(KeyCode::B) {
+        return;
+    }
+    *socket = None;
+    start_local_match(
+        &mut commands,
+        &mut state,
+        *mode,
+        lobby.proposal,
+        &options,
+        &mut bots,
+        false,
+    );
+}
+
+// Local matches are synctest sessions that never roll back unless synctest asks them to, in
+// which case the bots get our handle too
+pub fn start_local_match(
+    commands: &mut Commands,
+    state: &mut State<GameState>,
+    mode: GameMode,
+    settings: MatchSettings,
+    options: &Settings,
+    bots: &mut BotPlayers,
+    synctest: bool,
+) {
+    let num_players = mode.num_players();
+    let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(num_players)
+        .with_fps(TICKS_PER_SECOND as usize)
+        .expect("invalid tick rate");
+    if synctest {
+        // the check has to fit inside the prediction window
+        session_builder = session_builder
+            .with_max_prediction_window(SYNCTEST_CHECK_DISTANCE + 1)
+            .with_check_distance(SYNCTEST_CHECK_DISTANCE);
+    }
+    for i in 0..num_players {
+        session_builder = session_builder
+            .add_player(PlayerType::Local, i)
+            .expect("failed to add player");
+    }
+    let session = session_builder
+        .start_synctest_session()
+        .expect("failed to start session");
+
+    // we're always handle 0, the rest belong to the bots unless they have all of them
+    let first_bot = if synctest { 0 } else { 1 };
+    bots.0 = (first_bot..num_players).collect();
+    commands.insert_resource(LocalHandle(0));
+    commands.insert_resource(Cosmetics::new(
+        mode,
+        &[Some(options.profile.clone())],
+        options,
+    ));
+    info!("Starting a local match against {} bots", bots.0.len());
+
+    commands.insert_resource(settings);
+    commands.insert_resource(session);
+    commands.insert_resource(SessionType::SyncTestSession);
+    commands.insert_resource(RecentInputs::default());
+    commands.insert_resource(Training::default());
+    commands.insert_resource(Daily::default());
+
+    state.set(GameState::InGame).unwrap();
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn wait_for_players(
+    mut commands: Commands,
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut state: ResMut<State<GameState>>,
+    args: Res<Args>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    options: Res<Settings>,
+    mut lobby: ResMut<Lobby>,
+    mut spectators: ResMut<Broadcast>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    // still looking for a server to find peers on
+    let socket = match socket.as_mut() {
+        Some(socket) => socket,
+        None => return,
+    };
+
+    // the room was already playing when we came in, or there was no cycle left for us
+    if let Some((host, watch)) = lobby.watch.take() {
+        let channel = socket
+            .take_channel(SPECTATE_CHANNEL)
+            .expect("already spectating");
+        start_spectating(&mut commands, &mut state, &options, host, &watch, channel);
+        return;
+    }
+
+    let players = players(socket);
+
+    let num_humans = queue.num_humans(*mode, &args);
+    if players.len() < num_humans {
+        return; // wait for more players
+    }
+
+    // everyone sends their proposal and profile as soon as they connect
+    if socket
+        .connected_peers()
+        .any(|peer| !lobby.proposals.contains_key(&peer) || !lobby.profiles.contains_key(&peer))
+    {
+        return;
+    }
+
+    // Everyone plays by the settings of whoever got handle 0, and has to press ready again
+    // whenever those change
+    let agreed = match &players[0] {
+        PlayerType::Remote(peer) => lobby.proposals[peer],
+        _ => lobby.our_proposal(&queue, *mode),
+    };
+    let room = room(socket);
+    if lobby.agreed != Some(agreed) || lobby.room != room {
+        if lobby.agreed.is_some_and(|old| old != agreed) && !args.headless {
+            lobby.ready = false;
+        }
+        lobby.agreed = Some(agreed);
+        lobby.room = room;
+        broadcast(socket, &LobbyMessage::Ready(lobby.ready_for()));
+    }
+
+    // Nobody goes in until everyone is ready for the same thing, with the same people
+    let all_ready = socket.connected_peers().all(|peer| {
+        matches!(lobby.ready_peers.get(&peer),
+            Some((proposal, room)) if *proposal == agreed && *room == lobby.room)
+    });
+    if !lobby.ready || !all_ready {
+        return;
+    }
+
+    info!("All peers are ready, going in-game with {:?}", agreed);
+    let mode = agreed.mode;
+    let num_players = mode.num_players();
+
+    // Everyone sorted the room the same way, so everyone agrees on who gets a cycle. A custom
+    // room can hold more than that, the rest watch.
+    let (seated, watchers) = players.split_at(num_humans);
+    let profiles: Vec<Option<PlayerProfile>> = seated
+        .iter()
+        .map(|player| match player {
+            PlayerType::Remote(peer) => lobby.profiles.get(peer).cloned(),
+            _ => Some(options.profile.clone()),
+        })
+        .collect();
+    // Headless peers send empty inputs for their own handle. Bots filling the empty handles
+    // run on whoever got handle 0, and everyone else just receives their (empty) inputs.
+    let bots = profiles
+        .iter()
+        .enumerate()
+        .filter(|(_, profile)| matches!(profile, Some(profile) if profile.bot))
+        .map(|(handle, _)| handle)
+        .chain(num_humans..num_players)
+        .collect();
+    let watch = Watch {
+        proposal: agreed,
+        profiles,
+        bots,
+    };
+    let bot_host = seated[0];
+    let host = match bot_host {
+        PlayerType::Remote(peer) => Some(peer),
+        _ => None,
+    };
+    // whoever has handle 0 sends us the match once it's started, like anyone who came in late
+    if !seated.contains(&PlayerType::Local) {
+        return;
+    }
+    commands.insert_resource(mode);
+    commands.insert_resource(agreed.settings);
+
+    // create a GGRS P2P session
+    let input_delay = options.input_delay.unwrap_or(tuning.input_delay);
+    let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(num_players)
+        // GGRS paces peers against each other by this, so it has to match the plugin's
+        .with_fps(TICKS_PER_SECOND as usize)
+        .expect("invalid tick rate")
+        .with_input_delay(input_delay)
+        .with_disconnect_timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS))
+        .with_disconnect_notify_delay(Duration::from_millis(DISCONNECT_NOTIFY_MILLIS));
+
+    if let Some(handle) = seated.iter().position(|p| matches!(p, PlayerType::Local)) {
+        commands.insert_resource(LocalHandle(handle));
+    }
+    commands.insert_resource(Cosmetics::new(mode, &watch.profiles, &options));
+    commands.insert_resource(ConnectionStatus {
+        handles: seated
+            .iter()
+            .enumerate()
+            .filter_map(|(handle, player)| match player {
+                PlayerType::Remote(peer) => Some((*peer, handle)),
+                _ => None,
+            })
+            .collect(),
+        ..default()
+    });
+    commands.insert_resource(BotPlayers(watch.bots.clone()));
+    for (i, player) in seated.iter().enumerate() {
+        session_builder = session_builder
+            .add_player(*player, i)
+            .expect("failed to add player");
+    }
+    for i in num_humans..num_players {
+        session_builder = session_builder
+            .add_player(bot_host, i)
+            .expect("failed to add bot");
+    }
+    // whoever has handle 0 sends the match on to everyone watching, now and later on
+    if host.is_none() {
+        spectators.watch = Some(watch);
+        spectators.delay = options.spectator_delay * TICKS_PER_SECOND;
+        for watcher in watchers {
+            if let PlayerType::Remote(peer) = watcher {
+                spectators.add_spectator(socket, *peer);
+            }
+        }
+    }
+
+    // GGRS gets the unreliable channel to itself, the socket stays ours for the chat
+    let channel = socket
+        .take_channel(GGRS_CHANNEL)
+        .expect("GGRS already has the channel");
+    let links = Arc::new(Mutex::new(Links::new(channel)));
+    let session = session_builder
+        .start_p2p_session(MatchSocket(links.clone()))
+        .expect("failed to start session");
+    // anyone whose link drops looks for the others again in a room named after handle 0
+    if let Some(id) = socket.id() {
+        let first = match players[0] {
+            PlayerType::Remote(peer) => peer,
+            _ => id,
+        };
+        *reconnect = Reconnect {
+            links: Some(links),
+            id: Some(id),
+            room: format!("resume_{}", first.0),
+            ..default()
+        };
+    }
+
+    commands.insert_resource(session);
+    commands.insert_resource(SessionType::P2PSession);
+    commands.insert_resource(RecentInputs {
+        delay: input_delay,
+        ..default()
+    });
+    commands.insert_resource(Training::default());
+    commands.insert_resource(Daily::default());
+
+    state.set(GameState::InGame).unwrap();
+}
+
+// Watches whoever has handle 0 play, over the channel they're sending the match on. Without a
+// cycle of our own the camera is free, like it is for anyone who's died.
+fn start_spectating(
+    commands: &mut Commands,
+    state: &mut State<GameState>,
+    options: &Settings,
+    host: PeerId,
+    watch: &Watch,
+    channel: WebRtcChannel,
+) {
+    info!("Spectating {:?} from {}", watch.proposal, host.0);
+    let mode = watch.proposal.mode;
+    let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(mode.num_players())
+        .with_fps(TICKS_PER_SECOND as usize)
+        .expect("invalid tick rate")
+        .with_max_frames_behind(SPECTATOR_MAX_FRAMES_BEHIND)
+        .expect("invalid frames behind")
+        .with_catchup_speed(SPECTATOR_CATCHUP_SPEED)
+        .expect("invalid catchup speed")
+        .with_disconnect_timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS))
+        .with_disconnect_notify_delay(Duration::from_millis(DISCONNECT_NOTIFY_MILLIS))
+        .start_spectator_session(host, channel);
+
+    commands.insert_resource(mode);
+    commands.insert_resource(watch.proposal.settings);
+    commands.insert_resource(LocalHandle::SPECTATOR);
+    commands.insert_resource(Cosmetics::new(mode, &watch.profiles, options));
+    commands.insert_resource(ConnectionStatus {
+        handles: HashMap::from([(host, 0)]),
+        watching: Some(host),
+        ..default()
+    });
+    commands.insert_resource(BotPlayers(watch.bots.clone()));
+    commands.insert_resource(session);
+    commands.insert_resource(SessionType::SpectatorSession);
+    commands.insert_resource(RecentInputs::default());
+    commands.insert_resource(Training::default());
+    commands.insert_resource(Daily::default());
+
+    state.set(GameState::InGame).unwrap();
+}
+
+fn watch_connection(
+    time: Res<Time>,
+    session: Option<ResMut<P2PSession<GgrsConfig>>>,
+    spectator: Option<ResMut<SpectatorSession<GgrsConfig>>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut skip: ResMut<FrameSkip>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    // a spectator only ever hears from whoever has handle 0
+    let events: Vec<_> = match (session, spectator) {
+        (Some(mut session), _) => session.events().collect(),
+        (None, Some(mut spectator)) => spectator.events().collect(),
+        (None, None) => return,
+    };
+    let now = time.seconds_since_startup();
+    for event in events {
+        match event {
+            GGRSEvent::Synchronizing { addr, total, count } => {
+                status.synchronizing.insert(addr, (count, total));
+            }
+            GGRSEvent::Synchronized { addr } => {
+                info!("Synchronized with {}", addr.0);
+                status.synchronizing.remove(&addr);
+            }
+            GGRSEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => {
+                warn!("Connection to {} interrupted", addr.0);
+                let deadline = now + disconnect_timeout as f64 / 1000.;
+                status.interrupted.insert(addr, deadline);
+                reconnect.interrupted(addr);
+            }
+            GGRSEvent::NetworkResumed { addr } => {
+                info!("Connection to {} resumed", addr.0);
+                status.interrupted.remove(&addr);
+            }
+            GGRSEvent::Disconnected { addr } => {
+                warn!("Lost connection to {}", addr.0);
+                status.interrupted.remove(&addr);
+                status.synchronizing.remove(&addr);
+                status.disconnected.push((addr, now));
+            }
+            GGRSEvent::WaitRecommendation { skip_frames } => {
+                debug!("GGRS recommends waiting {} frames", skip_frames);
+                status.last_wait = Some(now);
+                let until = now + skip_frames as f64 / TICKS_PER_SECOND as f64;
+                skip.until = skip.until.max(until);
+            }
+            // only sent with desync detection turned on, which it isn't
+            GGRSEvent::DesyncDetected { frame, addr, .. } => {
+                error!("Desynced from {} at frame {}", addr.0, frame);
+            }
+        }
+    }
+}
+
+// Anyone without a cycle gets the match from whoever has handle 0, over a session of their own
+// where every handle is ours and they're the spectator. GGRS only takes spectators when a
+// session is built, and the match's own was built before any late joiners came. It would also
+// send them every input the moment it's confirmed, where a feed can be held back.
+#[derive(Default)]
+pub struct Broadcast {
+    // what a spectator has to know to watch, None unless we have handle 0 of a match
+    pub watch: Option<Watch>,
+    // frames the feeds stay behind the match, so nobody watching can tell us where the others
+    // are
+    pub delay: u32,
+    feeds: HashMap<PeerId, P2PSession<GgrsConfig>>,
+    // taken from the socket for the first late joiner, every feed sends over it
+    channel: Option<SharedChannel>,
+}
+
+impl Broadcast {
+    fn add_spectator(&mut self, socket: &mut PeerSocket, peer: PeerId) {
+        let watch = match &self.watch {
+            Some(watch) => watch,
+            None => return,
+        };
+        let channel = self
+            .channel
+            .get_or_insert_with(|| {
+                SharedChannel::new(
+                    socket
+                        .take_channel(SPECTATE_CHANNEL)
+                        .expect("spectators already have the channel"),
+                )
+            })
+            .clone();
+        let num_players = watch.proposal.mode.num_players();
+        let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
+            .with_num_players(num_players)
+            .with_fps(TICKS_PER_SECOND as usize)
+            .expect("invalid tick rate")
+            .with_disconnect_timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS))
+            .with_disconnect_notify_delay(Duration::from_millis(DISCONNECT_NOTIFY_MILLIS));
+        // the inputs all come out of the replay, already confirmed
+        for handle in 0..num_players {
+            session_builder = session_builder
+                .add_player(PlayerType::Local, handle)
+                .expect("failed to add player");
+        }
+        let session = session_builder
+            .add_player(PlayerType::Spectator(peer), num_players)
+            .expect("failed to add spectator")
+            .start_p2p_session(FeedSocket { channel, peer })
+            .expect("failed to start session");
+        send_lobby_message(socket, peer, &LobbyMessage::Spectate(watch.clone()));
+        info!("sending {} the match", peer.0);
+        self.feeds.insert(peer, session);
+    }
+
+    fn remove_spectator(&mut self, peer: &PeerId) {
+        self.feeds.remove(peer);
+        if let Some(channel) = &self.channel {
+            channel.0.lock().unwrap().mail.remove(peer);
+        }
+    }
+}
+
+// The spectators' channel, with whatever came in sorted by who it's from so each feed only
+// reads its own
+#[derive(Clone)]
+struct SharedChannel(Arc<Mutex<Mailbox>>);
+
+struct Mailbox {
+    channel: WebRtcChannel,
+    mail: HashMap<PeerId, Vec<Message>>,
+}
+
+impl SharedChannel {
+    fn new(channel: WebRtcChannel) -> Self {
+        Self(Arc::new(Mutex::new(Mailbox {
+            channel,
+            mail: HashMap::new(),
+        })))
+    }
+}
+
+// One spectator's share of the channel
+struct FeedSocket {
+    channel: SharedChannel,
+    peer: PeerId,
+}
+
+impl NonBlockingSocket<PeerId> for FeedSocket {
+    fn send_to(&mut self, message: &Message, addr: &PeerId) {
+        self.channel
+            .0
+            .lock()
+            .unwrap()
+            .channel
+            .send_to(message, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut guard = self.channel.0.lock().unwrap();
+        let mailbox = &mut *guard;
+        for (from, message) in mailbox.channel.receive_all_messages() {
+            mailbox.mail.entry(from).or_default().push(message);
+        }
+        let peer = self.peer;
+        mailbox
+            .mail
+            .remove(&peer)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|message| (peer, message))
+            .collect()
+    }
+}
+
+// Sends each spectator the confirmed frames as of the delay, no further ahead of them than their
+// session can hold on to
+fn feed_spectators(replay: Res<Replay>, lobby: Res<Lobby>, mut broadcast: ResMut<Broadcast>) {
+    let confirmed = replay.confirmed.saturating_sub(broadcast.delay) as usize;
+    let mut gone = Vec::new();
+    for (peer, feed) in broadcast.feeds.iter_mut() {
+        feed.poll_remote_clients();
+        if feed
+            .events()
+            .any(|event| matches!(event, GGRSEvent::Disconnected { .. }))
+        {
+            gone.push(*peer);
+            continue;
+        }
+        if feed.current_state() != SessionState::Running {
+            continue;
+        }
+        let watched = lobby.watched.get(peer).copied().unwrap_or(0);
+        let until = confirmed.min((watched + SPECTATOR_LEAD_FRAMES) as usize);
+        while (feed.current_frame() as usize) < until {
+            for (handle, input) in replay.inputs[feed.current_frame() as usize]
+                .iter()
+                .enumerate()
+            {
+                feed.add_local_input(handle, *input)
+                    .expect("every handle is ours");
+            }
+            match feed.advance_frame() {
+                // spectators only get inputs, there's no state to keep for them
+                Ok(requests) => {
+                    for request in requests {
+                        if let GGRSRequest::SaveGameState { cell, frame } = request {
+                            cell.save(frame, None, None);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("couldn't send {} the next frame: {}", peer.0, e);
+                    break;
+                }
+            }
+        }
+    }
+    for peer in gone {
+        info!("{} stopped watching", peer.0);
+        broadcast.remove_spectator(&peer);
+    }
+}
+
+// Tells whoever's sending us the match how far we've got, so they can send more
+fn report_watched(
+    clock: Res<SimulationClock>,
+    status: Res<ConnectionStatus>,
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut reported: Local<u32>,
+) {
+    let (host, socket) = match (status.watching, socket.as_mut()) {
+        (Some(host), Some(socket)) => (host, socket),
+        _ => return,
+    };
+    // the clock starts over with every match we watch
+    if (*reported..*reported + SPECTATOR_REPORT_FRAMES).contains(&clock.frame) {
+        return;
+    }
+    *reported = clock.frame;
+    send_lobby_message(socket, host, &LobbyMessage::Watched(clock.frame));
+}
+
+// The match's links to each peer. Whoever drops out and comes back does it on a socket of their
+// own, under a new id, and gets mapped back to the one GGRS has known them by all along.
+struct Links {
+    channel: WebRtcChannel,
+    // peers whose link over it has closed, matchbox panics at anything more sent their way
+    closed: HashSet<PeerId>,
+    // the reconnect socket's GGRS channel, once there is one
+    resumed: Option<WebRtcChannel>,
+    // id over it -> id at the start of the match, for everyone who's come back
+    ids: HashMap<PeerId, PeerId>,
+}
+
+impl Links {
+    fn new(channel: WebRtcChannel) -> Self {
+        Self {
+            channel,
+            closed: HashSet::new(),
+            resumed: None,
+            ids: HashMap::new(),
+        }
+    }
+}
+
+// What the match session sends and receives through. GGRS carries on resending whatever hasn't
+// been acknowledged, so once a peer is back it picks up from the last frame both sides
+// confirmed, same as after any other gap in the packets.
+struct MatchSocket(Arc<Mutex<Links>>);
+
+impl NonBlockingSocket<PeerId> for MatchSocket {
+    fn send_to(&mut self, message: &Message, addr: &PeerId) {
+        let mut guard = self.0.lock().unwrap();
+        let links = &mut *guard;
+        let resumed = links
+            .ids
+            .iter()
+            .find(|(_, old)| *old == addr)
+            .map(|(new, _)| *new);
+        match (resumed, links.resumed.as_mut()) {
+            (Some(new), Some(channel)) => channel.send_to(message, &new),
+            _ if links.closed.contains(addr) => {}
+            _ => links.channel.send_to(message, addr),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut guard = self.0.lock().unwrap();
+        let links = &mut *guard;
+        let mut messages = links.channel.receive_all_messages();
+        if let Some(channel) = links.resumed.as_mut() {
+            // anyone who hasn't said who they were yet is left to GGRS's resends
+            messages.extend(
+                channel
+                    .receive_all_messages()
+                    .into_iter()
+                    .filter_map(|(new, message)| Some((*links.ids.get(&new)?, message))),
+            );
+        }
+        messages
+    }
+}
+
+// A second socket, for everyone in the match to find each other on again when a link drops. It
+// joins a room named after whoever has handle 0, so it only ever meets the same match.
+#[derive(Default)]
+pub struct Reconnect {
+    // shared with the match's session, None unless we're playing in one
+    links: Option<Arc<Mutex<Links>>>,
+    // ours at the start of the match, which is who everyone else knows us as
+    id: Option<PeerId>,
+    room: String,
+    socket: Option<PeerSocket>,
+    // a link dropped that the socket we have can't help with, so we want a fresh one
+    reopen: bool,
+}
+
+impl Reconnect {
+    // Both ends see the interruption, so both go looking. A link that already came back once
+    // and dropped again gets a new socket, since the old one's peers are stuck on it.
+    fn interrupted(&mut self, peer: PeerId) {
+        let links = match &self.links {
+            Some(links) => links,
+            None => return,
+        };
+        let resumed = links.lock().unwrap().ids.values().any(|old| *old == peer);
+        if self.socket.is_none() || resumed {
+            self.reopen = true;
+        }
+    }
+
+    fn link_closed(&mut self, peer: PeerId) {
+        if let Some(links) = &self.links {
+            links.lock().unwrap().closed.insert(peer);
+        }
+    }
+}
+
+// Opens the reconnect socket when it's needed, and tells whoever turns up on it who we were
+fn poll_reconnect(
+    server: Res<ServerStatus>,
+    connection: Res<ConnectionStatus>,
+    options: Res<Settings>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    let (links, id) = match (&reconnect.links, reconnect.id) {
+        (Some(links), Some(id)) => (links.clone(), id),
+        _ => return,
+    };
+    if reconnect.reopen {
+        reconnect.reopen = false;
+        let room_url = format!("{}/{}", server.server(), reconnect.room);
+        let mut socket = open_socket(room_url, &options.ice_server);
+        let channel = socket
+            .take_channel(GGRS_CHANNEL)
+            .expect("GGRS already has the channel");
+        let mut links = links.lock().unwrap();
+        links.resumed = Some(channel);
+        links.ids.clear();
+        reconnect.socket = Some(socket);
+    }
+    let socket = match reconnect.socket.as_mut() {
+        Some(socket) => socket,
+        None => return,
+    };
+    for (peer, state) in socket.update_peers() {
+        match state {
+            PeerState::Connected => send_lobby_message(socket, peer, &LobbyMessage::Resume(id)),
+            PeerState::Disconnected => {
+                links.lock().unwrap().ids.remove(&peer);
+            }
+        }
+    }
+    for (peer, packet) in socket.channel(LOBBY_CHANNEL).receive() {
+        match bincode::deserialize(&packet) {
+            Ok(LobbyMessage::Resume(old)) if connection.handle(&old).is_some() => {
+                info!("{} is back as {}", old.0, peer.0);
+                links.lock().unwrap().ids.insert(peer, old);
+            }
+            // nothing else is said over it, the chat stays on the match's socket
+            Ok(_) => {}
+            Err(e) => warn!("ignoring malformed message from {}: {}", peer.0, e),
+        }
+    }
+}
+
+// bevy_ggrs steps to the wall clock and would only catch up on frames we held its stage back
+// from, so while skipping it's shown a spectator session that never starts instead. The stage
+// still uses up the time that passed, which is what makes the frames skipped rather than late.
+fn park_session(world: &mut World) {
+    let now = world.resource::<Time>().seconds_since_startup();
+    if world.resource::<FrameSkip>().until <= now {
+        return;
+    }
+    let session = match world.remove_resource::<P2PSession<GgrsConfig>>() {
+        Some(session) => session,
+        None => return,
+    };
+    let stand_in = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(session.num_players())
+        .start_spectator_session(PeerId(Uuid::nil()), NoSocket);
+    world.insert_resource(stand_in);
+    world.insert_resource(SessionType::SpectatorSession);
+    world.resource_mut::<FrameSkip>().session = Some(session);
+}
+
+// Before anything in Update can notice the session was gone
+fn unpark_session(world: &mut World) {
+    let session = match world.resource_mut::<FrameSkip>().session.take() {
+        Some(session) => session,
+        None => return,
+    };
+    world.remove_resource::<SpectatorSession<GgrsConfig>>();
+    world.insert_resource(session);
+    world.insert_resource(SessionType::P2PSession);
+}
+
+fn send_proposal(
+    socket: &mut PeerSocket,
+    peer: PeerId,
+    proposal: Proposal,
+    lobby: &Lobby,
+    options: &Settings,
+) {
+    send_lobby_message(socket, peer, &LobbyMessage::Settings(proposal));
+    send_lobby_message(
+        socket,
+        peer,
+        &LobbyMessage::Profile(options.profile.clone()),
+    );
+    send_lobby_message(socket, peer, &LobbyMessage::Ready(lobby.ready_for()));
+}
+
+// Keeps up with who's connected and what they're saying, before the match and after it
+#[allow(clippy::too_many_arguments)]
+fn poll_socket(
+    time: Res<Time>,
+    state: Res<State<GameState>>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    options: Res<Settings>,
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut lobby: ResMut<Lobby>,
+    mut spectators: ResMut<Broadcast>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    let socket = match socket.as_mut() {
+        Some(socket) => socket,
+        None => return,
+    };
+    // peer ids are random, which makes them a decent seed for our proposal
+    if lobby.proposal.seed == 0 {
+        if let Some(id) = socket.id() {
+            lobby.proposal.seed = seed_from_str(&id.0.to_string());
+        }
+    }
+    // newcomers hear which settings we'd like, or that they're too late and get to watch
+    let ours = lobby.our_proposal(&queue, *mode);
+    let in_match =
+        state.current() == &GameState::InGame || state.inactives().contains(&GameState::InGame);
+    for (peer, peer_state) in socket.update_peers() {
+        match peer_state {
+            PeerState::Connected if in_match => {
+                let profile = LobbyMessage::Profile(options.profile.clone());
+                send_lobby_message(socket, peer, &profile);
+                spectators.add_spectator(socket, peer);
+            }
+            PeerState::Connected => send_proposal(socket, peer, ours, &lobby, &options),
+            PeerState::Disconnected => {
+                lobby.forget_peer(&peer);
+                spectators.remove_spectator(&peer);
+                reconnect.link_closed(peer);
+            }
+        }
+    }
+    receive_lobby_messages(socket, &mut lobby, time.seconds_since_startup());
+}
+
+fn broadcast(socket: &mut PeerSocket, message: &LobbyMessage) {
+    let peers: Vec<PeerId> = socket.connected_peers().collect();
+    for peer in peers {
+        send_lobby_message(socket, peer, message);
+    }
+}
+
+fn send_lobby_message(socket: &mut PeerSocket, peer: PeerId, message: &LobbyMessage) {
+    let packet = bincode::serialize(message).expect("failed to serialize lobby message");
+    socket
+        .channel(LOBBY_CHANNEL)
+        .send(packet.into_boxed_slice(), peer);
+}
+
+fn ping_peers(time: Res<Time>, mut socket: ResMut<Option<PeerSocket>>, mut frames: Local<u32>) {
+    let socket = match socket.as_mut() {
+        Some(socket) => socket,
+        None => return,
+    };
+    *frames += 1;
+    if *frames % PING_INTERVAL_FRAMES != 0 {
+        return;
+    }
+    broadcast(socket, &LobbyMessage::Ping(time.seconds_since_startup()));
+}
+
+fn receive_lobby_messages(socket: &mut PeerSocket, lobby: &mut Lobby, now: f64) {
+    for (peer, packet) in socket.channel(LOBBY_CHANNEL).receive() {
+        match bincode::deserialize(&packet) {
+            // anything the lobby buttons couldn't have picked is a broken or meddled with
+            // client, and a zero or NaN in there would wreck the match for everyone
+            Ok(LobbyMessage::Settings(settings)) if !settings.settings.in_range() => {
+                warn!(
+                    "ignoring out of range settings from {}: {:?}",
+                    peer.0, settings
+                );
+                lobby.rejected.insert(peer);
+            }
+            Ok(LobbyMessage::Settings(settings)) => {
+                lobby.rejected.remove(&peer);
+                lobby.proposals.insert(peer, settings);
+            }
+            Ok(LobbyMessage::Profile(profile)) => {
+                lobby.profiles.insert(peer, profile);
+            }
+            Ok(LobbyMessage::Ready(Some(ready))) => {
+                lobby.ready_peers.insert(peer, ready);
+            }
+            Ok(LobbyMessage::Ready(None)) => {
+                lobby.ready_peers.remove(&peer);
+            }
+            Ok(LobbyMessage::Chat(text)) => lobby.add_chat(Some(peer), text),
+            Ok(LobbyMessage::Ping(sent)) => {
+                send_lobby_message(socket, peer, &LobbyMessage::Pong(sent));
+            }
+            Ok(LobbyMessage::Pong(sent)) => {
+                let millis = (now - sent) * 1000.;
+                let ping = lobby.pings.entry(peer).or_insert(millis);
+                *ping = *ping * PING_SMOOTHING + millis * (1. - PING_SMOOTHING);
+            }
+            Ok(LobbyMessage::Spectate(watch)) if !watch.proposal.settings.in_range() => {
+                warn!(
+                    "not watching {}, the settings are out of range: {:?}",
+                    peer.0, watch.proposal
+                );
+            }
+            Ok(LobbyMessage::Spectate(watch)) => lobby.watch = Some((peer, watch)),
+            Ok(LobbyMessage::Watched(frame)) => {
+                lobby.watched.insert(peer, frame);
+            }
+            // only ever sent over a reconnect socket
+            Ok(LobbyMessage::Resume(_)) => {}
+            Err(e) => warn!("ignoring malformed lobby message from {}: {}", peer.0, e),
+        }
+    }
+}
+
+// Checks that a matchbox server answers before any room gets joined on it
+pub struct ServerProbe {
+    // seconds since startup when it went out
+    started: f64,
+    #[cfg(not(target_arch = "wasm32"))]
+    result: std::sync::Arc<std::sync::Mutex<Option<Result<(), String>>>>,
+    #[cfg(target_arch = "wasm32")]
+    socket: Option<web_sys::WebSocket>,
+}
+
+impl ServerProbe {
+    fn poll(&self, now: f64) -> Option<Result<(), String>> {
+        if let Some(result) = self.check() {
+            return Some(result);
+        }
+        (now - self.started > PROBE_TIMEOUT_SECS).then(|| Err("timed out".to_string()))
+    }
+
+    // A plain TCP connection is enough to tell whether anything is listening, without joining
+    // a room
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start(server: &str, now: f64) -> Self {
+        let result: std::sync::Arc<std::sync::Mutex<_>> = std::sync::Arc::default();
+        let slot = std::sync::Arc::clone(&result);
+        let server = server.to_string();
+        // blocks until the connection is up or refused, so it stays off the IO pool
+        bevy::tasks::AsyncComputeTaskPool::get()
+            .spawn(async move {
+                *slot.lock().unwrap() = Some(connect_to(&server));
+            })
+            .detach();
+        Self {
+            started: now,
+            result,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check(&self) -> Option<Result<(), String>> {
+        self.result.lock().unwrap().take()
+    }
+
+    // Browsers can only open websockets, so this opens one to a room nobody plays in
+    #[cfg(target_arch = "wasm32")]
+    fn start(server: &str, now: f64) -> Self {
+        let url = format!("{}/{}", server.trim_end_matches('/'), PROBE_ROOM);
+        Self {
+            started: now,
+            socket: web_sys::WebSocket::new(&url).ok(),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn check(&self) -> Option<Result<(), String>> {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return Some(Err("invalid server URL".to_string())),
+        };
+        match socket.ready_state() {
+            web_sys::WebSocket::OPEN => Some(Ok(())),
+            web_sys::WebSocket::CLOSING | web_sys::WebSocket::CLOSED => {
+                Some(Err("connection refused".to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+// the probe is only ever a way to find out, so it never stays open
+#[cfg(target_arch = "wasm32")]
+impl Drop for ServerProbe {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.socket {
+            let _ = socket.close();
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn connect_to(server: &str) -> Result<(), String> {
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    let (rest, port) = if let Some(rest) = server.strip_prefix("ws://") {
+        (rest, 80)
+    } else if let Some(rest) = server.strip_prefix("wss://") {
+        (rest, 443)
+    } else {
+        return Err(format!("{} isn't a ws:// or wss:// URL", server));
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let timeout = std::time::Duration::from_secs_f64(PROBE_TIMEOUT_SECS);
+    let mut error = format!("couldn't resolve {}", host);
+    for address in address.to_socket_addrs().map_err(|e| e.to_string())? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => error = e.to_string(),
+        }
+    }
+    Err(error)
+}
+
+const PROBE_TIMEOUT_SECS: f64 = 5.;
+
+#[cfg(target_arch = "wasm32")]
+const PROBE_ROOM: &str = "tron_probe";
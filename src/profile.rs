@@ -0,0 +1,177 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gameplay::{BotPlayers, GameMode, MatchStats, Scoreboard},
+    networking::LocalHandle,
+    rendering::Cosmetics,
+    settings::Settings,
+    GameState,
+};
+
+// Keeps who we are between sessions. The profile, key bindings and lifetime stats all live in
+// Settings, which goes to settings.json or the browser's local storage whenever it changes.
+// Headless clients leave it alone, so a bot's matches and --name never end up in there.
+pub struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_in_stack_update(GameState::InGame).with_system(record_lifetime_stats),
+        )
+        .add_system_to_stage(CoreStage::Last, save_settings);
+    }
+}
+
+// Totals over every match we've finished on this machine
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifetimeStats {
+    pub matches: u32,
+    // outright, a shared lead doesn't count
+    pub wins: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub distance: f32,
+    pub near_misses: u32,
+}
+
+impl LifetimeStats {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} matches, {} wins, {} kills, {} deaths, {:.0} distance",
+            self.matches, self.wins, self.kills, self.deaths, self.distance
+        )
+    }
+}
+
+// Elo against everyone we've played, by the name they went by. Names aren't accounts, so it's
+// only a rough guide for us, and nobody else ever sees it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ratings {
+    pub own: f32,
+    pub opponents: BTreeMap<String, f32>,
+    // from the last match we finished, None if it was against bots only
+    pub last_change: Option<f32>,
+}
+
+impl Default for Ratings {
+    fn default() -> Self {
+        Self {
+            own: STARTING_RATING,
+            opponents: BTreeMap::new(),
+            last_change: None,
+        }
+    }
+}
+
+impl Ratings {
+    pub fn summary(&self) -> String {
+        format!(
+            "Rating {:.0} against {} opponents",
+            self.own,
+            self.opponents.len()
+        )
+    }
+
+    // Every opponent is a game of its own, 1 for finishing ahead of them and 0.5 for a tie.
+    // Ours moves by the average so a royale counts as one match, theirs by the whole step.
+    pub fn record(&mut self, results: &[(String, f32)]) {
+        if results.is_empty() {
+            self.last_change = None;
+            return;
+        }
+        let mut change = 0.;
+        for (name, result) in results {
+            let theirs = self
+                .opponents
+                .entry(name.clone())
+                .or_insert(STARTING_RATING);
+            let expected = 1. / (1. + 10f32.powf((*theirs - self.own) / 400.));
+            let step = RATING_K * (result - expected);
+            *theirs -= step;
+            change += step / results.len() as f32;
+        }
+        self.own += change;
+        self.last_change = Some(change);
+    }
+}
+
+// Counted once per match, as soon as the scoreboard says it's over. Matches our handle sat
+// out with a bot at the wheel or that we only watched don't count, and bots don't get rated.
+#[allow(clippy::too_many_arguments)]
+pub fn record_lifetime_stats(
+    mode: Res<GameMode>,
+    local: Res<LocalHandle>,
+    bots: Res<BotPlayers>,
+    cosmetics: Res<Cosmetics>,
+    scoreboard: Res<Scoreboard>,
+    match_stats: Res<MatchStats>,
+    mut options: ResMut<Settings>,
+    mut recorded: Local<bool>,
+) {
+    if !scoreboard.match_over {
+        *recorded = false;
+        return;
+    }
+    if *recorded || bots.0.contains(&local.0) || local.spectating() {
+        return;
+    }
+    *recorded = true;
+
+    let team = mode.team_of(local.0);
+    let best = scoreboard.scores.iter().max().copied().unwrap_or(0);
+    let leaders = scoreboard
+        .scores
+        .iter()
+        .filter(|score| **score == best)
+        .count();
+    let won = leaders == 1 && scoreboard.scores.get(team) == Some(&best);
+    let player = match_stats
+        .players
+        .get(local.0)
+        .copied()
+        .unwrap_or_default();
+
+    let stats = &mut options.stats;
+    stats.matches += 1;
+    stats.wins += won as u32;
+    stats.kills += player.kills;
+    stats.deaths += player.deaths;
+    stats.distance += player.distance;
+    stats.near_misses += player.near_misses;
+
+    let ours = scoreboard.scores.get(team).copied().unwrap_or(0);
+    let results: Vec<(String, f32)> = (0..mode.num_players())
+        .filter(|handle| mode.team_of(*handle) != team && !bots.0.contains(handle))
+        .map(|handle| {
+            let theirs = scoreboard
+                .scores
+                .get(mode.team_of(handle))
+                .copied()
+                .unwrap_or(0);
+            let result = match ours.cmp(&theirs) {
+                Ordering::Greater => 1.,
+                Ordering::Equal => 0.5,
+                Ordering::Less => 0.,
+            };
+            (cosmetics.player_name(handle).to_string(), result)
+        })
+        .collect();
+    options.ratings.record(&results);
+}
+
+// The copy the game started with was just loaded, so only later changes need writing out
+fn save_settings(options: Res<Settings>) {
+    if options.is_changed() && !options.is_added() {
+        options.save();
+    }
+}
+
+const STARTING_RATING: f32 = 1000.;
+
+// the most a single match can move a rating
+const RATING_K: f32 = 32.;
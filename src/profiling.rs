@@ -0,0 +1,54 @@
+use bevy::{
+    prelude::*,
+    utils::{Duration, Instant},
+};
+
+use crate::gameplay::FramesSimulated;
+
+// Times the rollback schedule from either side of the GGRS stage, for the diagnostics overlay
+// and --bench. Added after gameplay, which is where that stage comes from.
+pub struct ProfilingPlugin;
+
+impl Plugin for ProfilingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RollbackProfile::default())
+            .add_stage_before(
+                bevy_ggrs::GGRS_UPDATE,
+                PROFILE_START_STAGE,
+                SystemStage::single_threaded().with_system(start_rollback_profile),
+            )
+            .add_stage_after(
+                bevy_ggrs::GGRS_UPDATE,
+                PROFILE_END_STAGE,
+                SystemStage::single_threaded().with_system(end_rollback_profile),
+            );
+    }
+}
+
+// Time spent in the rollback schedule since someone last took it, summed over every frame
+// simulated (or re-simulated) in between
+#[derive(Default)]
+pub struct RollbackProfile {
+    pub started: Option<(Instant, u32)>,
+    pub elapsed: Duration,
+    pub frames: u32,
+}
+
+fn start_rollback_profile(mut profile: ResMut<RollbackProfile>, simulated: Res<FramesSimulated>) {
+    profile.started = Some((Instant::now(), simulated.0));
+}
+
+// Updates that didn't get to simulate anything aren't counted, they'd only water the
+// average down
+fn end_rollback_profile(mut profile: ResMut<RollbackProfile>, simulated: Res<FramesSimulated>) {
+    if let Some((started, frames_before)) = profile.started.take() {
+        let frames = simulated.0.wrapping_sub(frames_before);
+        if frames > 0 {
+            profile.elapsed += started.elapsed();
+            profile.frames += frames;
+        }
+    }
+}
+
+const PROFILE_START_STAGE: &str = "profile_start";
+const PROFILE_END_STAGE: &str = "profile_end";
@@ -0,0 +1,1702 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
+use bevy::{
+    input::mouse::MouseWheel,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::ScalingMode,
+        mesh::{Indices, PrimitiveTopology},
+        render_resource::{AsBindGroup, ShaderRef, ShaderType},
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle},
+};
+use ggrs::P2PSession;
+
+use crate::{
+    gameplay::{
+        emit_death_events, emit_round_events, pseudo_random, Arena, BoostZone, Cue, CueLog,
+        DashEnergy, DeathEvent, GameMode, Ghost, GhostCycle, Heading, Hill, KillcamFrame,
+        KillcamLog, MatchSettings, Pellet, Player, Position, PowerUp, PowerUpKind, RoundEndEvent,
+        RoundState, Scoreboard, Shield, SimulationClock, Slowed, Trail, TrailPoint,
+        COUNTDOWN_FRAMES, HILL_RADIUS, PELLET_SIZE, POWER_UP_SIZE, ROYALE_PLAYERS,
+        TICKS_PER_SECOND,
+    },
+    input::EMOTE_WHEEL_KEY,
+    networking::{GgrsConfig, LocalHandle},
+    settings::{CameraMode, DisplaySettings, PlayerProfile, Settings, Skin, Theme, SKINS},
+    tuning::Tuning,
+    GameState,
+};
+
+// Everything drawn in the arena, and the camera looking at it
+pub struct RenderingPlugin;
+
+impl Plugin for RenderingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Cosmetics::default())
+            .insert_resource(Spectator::default())
+            .insert_resource(CameraShake::default())
+            .insert_resource(Killcam::default())
+            .insert_resource(ClearColor(Theme::default().background()))
+            .add_plugin(Material2dPlugin::<GridMaterial>::default())
+            .add_startup_system(setup)
+            .add_system(animate_grid)
+            .add_system(apply_theme)
+            .add_system(apply_display)
+            .add_system(resize_arena_floor)
+            .add_system(add_board_meshes)
+            .add_system(add_cycle_sprites)
+            .add_system(add_pickup_meshes)
+            .add_system(add_ghost_mesh)
+            .add_system_set(
+                SystemSet::on_enter(GameState::InGame)
+                    .with_system(spawn_trail_ribbons)
+                    .with_system(spawn_killcam),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(control_spectator)
+                    .with_system(shake_camera)
+                    .with_system(start_killcam.after(emit_round_events))
+                    .with_system(play_killcam.after(start_killcam))
+                    .with_system(
+                        move_camera
+                            .after(control_spectator)
+                            .after(shake_camera)
+                            .after(play_killcam),
+                    )
+                    .with_system(spawn_death_particles.after(emit_death_events))
+                    .with_system(spawn_dash_streaks)
+                    .with_system(animate_particles)
+                    .with_system(update_arena)
+                    .with_system(update_trail_ribbons)
+                    .with_system(show_cycle_glow)
+                    .with_system(update_shield_rings)
+                    .with_system(animate_shield_pops)
+                    .with_system(tint_players)
+                    .with_system(update_ghost_visibility),
+            );
+        // browsers pace frames themselves
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system_to_stage(CoreStage::Last, limit_frame_rate);
+    }
+}
+
+// The arena floor, see assets/shaders/grid.wgsl
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "770872ce-dddb-43e9-8c0b-1605be28e1c0"]
+pub struct GridMaterial {
+    #[uniform(0)]
+    pub uniform: GridUniform,
+}
+
+#[derive(ShaderType, Clone)]
+pub struct GridUniform {
+    pub floor_color: Vec4,
+    pub line_color: Vec4,
+    // seconds, drives the scrolling and the pulse
+    pub time: f32,
+    // world units between lines
+    pub spacing: f32,
+}
+
+impl Material2d for GridMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/grid.wgsl".into()
+    }
+}
+
+// Child of every player, only visible while they carry a shield
+#[derive(Component)]
+pub struct ShieldRing;
+
+#[derive(Component)]
+pub struct ShieldPop {
+    pub timer: Timer,
+}
+
+// A bit of a wrecked cycle, purely cosmetic so it lives outside the rollback world
+#[derive(Component)]
+pub struct Particle {
+    pub velocity: Vec2,
+    pub timer: Timer,
+}
+
+// Made once at startup and shared, the colors that differ come from vertices or per-effect
+// materials that have to fade on their own
+pub struct TrailAssets {
+    // plain white, ribbons carry their colors in the vertices
+    pub ribbon_material: Handle<ColorMaterial>,
+    pub particle_mesh: Mesh2dHandle,
+    pub streak_mesh: Mesh2dHandle,
+}
+
+#[derive(Default)]
+pub struct CameraShake {
+    // 0 to 1, the shake grows with its square
+    pub trauma: f32,
+    // this frame's offset in view heights, move_camera turns it into world units
+    pub jitter: Vec2,
+    // what move_camera added last frame, taken back out before following the target
+    pub offset: Vec2,
+}
+
+// Draws every trail segment of one handle as a single mesh, or the glow around them
+#[derive(Component)]
+pub struct TrailRibbon {
+    pub handle: usize,
+    pub glow: bool,
+}
+
+// The halo under a cycle, hidden with the glow setting off
+#[derive(Component)]
+pub struct CycleGlow;
+
+// Free camera for while we're dead, it stays out of the way until it's used
+#[derive(Default)]
+pub struct Spectator {
+    pub active: bool,
+    pub position: Vec2,
+    pub view_size: f32,
+    // snapped to a living player with the number keys
+    pub following: Option<usize>,
+}
+
+// Slow motion of how a round ended, drawn from the killcam log once nothing can roll the end
+// back. It's only a picture of what was recorded, the next countdown carries on underneath.
+#[derive(Default)]
+pub struct Killcam {
+    // clock frame the round was seen to be over by, until that's confirmed
+    pub pending: Option<u32>,
+    // oldest first, ending on the last death
+    pub clip: Vec<KillcamFrame>,
+    // as they stood at the last death, so any that had faded by then are missing
+    pub trails: Vec<(usize, Vec<TrailPoint>)>,
+    // where the last death happened
+    pub focus: Vec2,
+    // seconds since the clip started playing
+    pub elapsed: f32,
+}
+
+impl Killcam {
+    pub fn playing(&self) -> bool {
+        !self.clip.is_empty()
+    }
+}
+
+#[derive(Component)]
+pub struct KillcamCycle {
+    pub handle: usize,
+}
+
+#[derive(Component)]
+pub struct KillcamRibbon {
+    pub handle: usize,
+}
+
+// Dims the live arena while the killcam plays over it
+#[derive(Component)]
+pub struct KillcamBackdrop;
+
+const SHIELD_POP_SECONDS: f32 = 0.3;
+
+const DEATH_PARTICLES: u32 = 24;
+
+const PARTICLE_SECONDS: f32 = 0.8;
+
+const PARTICLE_SIZE: f32 = 0.12;
+
+// units per second, particles slow down with PARTICLE_DRAG per second
+const PARTICLE_SPEED: f32 = 6.;
+
+const PARTICLE_DRAG: f32 = 0.05;
+
+const DASH_STREAK_INTERVAL: f32 = 0.03;
+
+const DASH_STREAK_SECONDS: f32 = 0.25;
+
+// how far towards white trails laid while dashing are drawn
+const DASH_TRAIL_WHITEN: f32 = 0.4;
+
+// the view grows by this much while we dash
+const DASH_ZOOM: f32 = 1.06;
+
+// the most the killcam shows, the next round's countdown usually only leaves room for half
+const KILLCAM_FRAMES: u32 = TICKS_PER_SECOND * 3;
+
+// times slower than the match played
+const KILLCAM_SLOWDOWN: u32 = 2;
+
+// well past the edges of any arena
+const KILLCAM_BACKDROP_SIZE: f32 = 100.;
+
+const KILLCAM_BACKDROP_ALPHA: f32 = 0.8;
+
+// the tail of a trail fades out and thins over its last few frames
+const TRAIL_FADE_FRAMES: u32 = 20;
+
+// how far the glow spreads, in trail widths
+const GLOW_WIDTH: f32 = 4.;
+
+const GLOW_ALPHA: f32 = 0.35;
+
+// Bevy has no bloom yet, so the trails fake it with a whiter core and a soft halo. Each
+// profile is (offset across the trail in widths, alpha, how far towards white)
+const RIBBON_PROFILE: [(f32, f32, f32); 2] = [(0.5, 1., 0.), (-0.5, 1., 0.)];
+
+const HOT_RIBBON_PROFILE: [(f32, f32, f32); 3] = [(0.5, 1., 0.), (0., 1., 0.6), (-0.5, 1., 0.)];
+
+const GLOW_PROFILE: [(f32, f32, f32); 3] = [
+    (GLOW_WIDTH / 2., 0., 0.),
+    (0., GLOW_ALPHA, 0.),
+    (-GLOW_WIDTH / 2., 0., 0.),
+];
+
+// for the arena floor, nothing to do with Steering::Grid
+const GRID_LINE_SPACING: f32 = 0.5;
+
+// length of each half of a striped trail
+const TRAIL_STRIPE_FRAMES: u32 = 8;
+
+// in characters
+const MAX_NAME_LENGTH: usize = 16;
+
+// height of the world shown by the follow camera
+const FOLLOW_VIEW_SIZE: f32 = 5.;
+
+// how far ahead of the cycle the follow camera looks
+const CAMERA_LEAD: f32 = 1.;
+
+// higher catches up faster
+const CAMERA_DAMPING: f32 = 4.;
+
+// room left around the outermost players by the fit camera
+const CAMERA_MARGIN: f32 = 1.5;
+
+// the border warning starts this far inside it
+const EDGE_WARNING_DISTANCE: f32 = 1.5;
+
+const EDGE_WARNING_FLASHES: f32 = 3.;
+
+// shakes add this much trauma, your own death adds all of it
+const DEATH_SHAKE: f32 = 0.5;
+
+const NEAR_MISS_SHAKE: f32 = 0.35;
+
+// trauma lost per second
+const SHAKE_DECAY: f32 = 1.5;
+
+// in view heights at full trauma
+const MAX_SHAKE: f32 = 0.03;
+
+const SHAKE_FREQUENCY: f32 = 25.;
+
+// in view heights per second
+const SPECTATOR_PAN_SPEED: f32 = 0.75;
+
+// fraction of the view size per scroll line
+const SPECTATOR_ZOOM_STEP: f32 = 0.1;
+
+const SPECTATOR_KEYS: [KeyCode; ROYALE_PLAYERS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+];
+
+// Which team colors and skins are in play, decided when the session starts. This is purely
+// cosmetic, so it's fine if peers end up disagreeing about it.
+pub struct Cosmetics {
+    // by team
+    pub colors: Vec<usize>,
+    // by handle
+    pub skins: Vec<usize>,
+    // by handle
+    pub names: Vec<String>,
+    pub colorblind: bool,
+    pub theme: Theme,
+}
+
+impl Default for Cosmetics {
+    fn default() -> Self {
+        Self {
+            colors: (0..ROYALE_PLAYERS).collect(),
+            skins: vec![0; ROYALE_PLAYERS],
+            names: (0..ROYALE_PLAYERS).map(default_name).collect(),
+            colorblind: false,
+            theme: Theme::default(),
+        }
+    }
+}
+
+impl Cosmetics {
+    // profiles are by handle, and each team wears the color of its lowest handle. Color
+    // clashes go to the lower handle, everyone else gets the first free color.
+    pub fn new(mode: GameMode, profiles: &[Option<PlayerProfile>], options: &Settings) -> Self {
+        let mut colors: Vec<usize> = Vec::new();
+        for team in 0..mode.num_teams() {
+            let choice = profiles
+                .get(team)
+                .and_then(Option::as_ref)
+                .map(|profile| profile.color)
+                .filter(|color| *color < ROYALE_PLAYERS && !colors.contains(color));
+            let color = choice
+                .or_else(|| (0..ROYALE_PLAYERS).find(|color| !colors.contains(color)))
+                .expect("more teams than colors");
+            colors.push(color);
+        }
+        let skins = (0..mode.num_players())
+            .map(
+                |handle| match profiles.get(handle).and_then(Option::as_ref) {
+                    Some(profile) if profile.skin < SKINS.len() => profile.skin,
+                    _ => 0,
+                },
+            )
+            .collect();
+        // names come from other peers too, so keep them short enough for the HUD
+        let names = (0..mode.num_players())
+            .map(|handle| {
+                match profiles
+                    .get(handle)
+                    .and_then(Option::as_ref)
+                    .and_then(|profile| profile.name.as_deref())
+                {
+                    Some(name) if !name.trim().is_empty() => {
+                        name.trim().chars().take(MAX_NAME_LENGTH).collect()
+                    }
+                    _ => default_name(handle),
+                }
+            })
+            .collect();
+        Self {
+            colors,
+            skins,
+            names,
+            colorblind: options.colorblind,
+            theme: options.theme,
+        }
+    }
+
+    pub fn skin(&self, handle: usize) -> &'static Skin {
+        &SKINS[self.skins[handle]]
+    }
+
+    pub fn team_colors(&self, team: usize) -> (Color, Color) {
+        self.theme
+            .team_colors(team_colors(self.colors[team], self.colorblind))
+    }
+
+    // 0 to 1, how strong the glow around cycles and trails is
+    pub fn glow(&self, options: &Settings) -> f32 {
+        if options.glow {
+            self.theme.effects()
+        } else {
+            0.
+        }
+    }
+
+    pub fn player_name(&self, handle: usize) -> &str {
+        &self.names[handle]
+    }
+
+    pub fn team_name(&self, team: usize) -> &'static str {
+        team_name(self.colors[team], self.colorblind)
+    }
+
+    // In colorblind mode every other color gets striped trails, so teams can be told apart
+    // by pattern as well as by color
+    pub fn trail_color(&self, team: usize, frame: u32) -> Color {
+        let (_, color) = self.team_colors(team);
+        let striped = self.colorblind && self.colors[team] % 2 == 1;
+        if !striped || (frame / TRAIL_STRIPE_FRAMES) % 2 == 0 {
+            return color;
+        }
+        // the lighter half of the stripe
+        Color::rgba(
+            (color.r() + 1.) / 2.,
+            (color.g() + 1.) / 2.,
+            (color.b() + 1.) / 2.,
+            color.a(),
+        )
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut grid_materials: ResMut<Assets<GridMaterial>>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    options: Res<Settings>,
+) {
+    commands.insert_resource(TrailAssets {
+        ribbon_material: materials.add(ColorMaterial::from(Color::WHITE)),
+        particle_mesh: meshes
+            .add(shape::Quad::new(Vec2::splat(PARTICLE_SIZE)).into())
+            .into(),
+        streak_mesh: meshes
+            .add(shape::Quad::new(Vec2::new(tuning.player_size, 0.04)).into())
+            .into(),
+    });
+
+    let mut camera_bundle = Camera2dBundle::default();
+    camera_bundle.projection.scaling_mode =
+        ScalingMode::FixedVertical(mode.board_size(&tuning) + 1.);
+    commands.spawn_bundle(camera_bundle);
+
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(shape::Circle::new(mode.board_size(&tuning) / 2.).into())
+                .into(),
+            material: grid_materials.add(GridMaterial {
+                uniform: GridUniform {
+                    floor_color: options.theme.floor().0.as_linear_rgba_f32().into(),
+                    line_color: options.theme.floor().1.as_linear_rgba_f32().into(),
+                    time: 0.,
+                    spacing: GRID_LINE_SPACING,
+                },
+            }),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
+            ..default()
+        })
+        .insert(Arena);
+}
+
+pub fn sector_mesh(start_angle: f32, end_angle: f32, inner_radius: f32, outer_radius: f32) -> Mesh {
+    let segments = 32;
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut indices = Vec::new();
+    for i in 0..=segments {
+        let t = i as f32 / segments as f32;
+        let angle = start_angle + (end_angle - start_angle) * t;
+        let direction = Vec2::new(angle.cos(), angle.sin());
+        for (radius, v) in [(inner_radius, 0.), (outer_radius, 1.)] {
+            let point = direction * radius;
+            positions.push([point.x, point.y, 0.]);
+            normals.push([0., 0., 1.]);
+            uvs.push([t, v]);
+        }
+        if i > 0 {
+            let base = (i - 1) * 2;
+            indices.extend([base, base + 2, base + 1, base + 1, base + 2, base + 3]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+// A disc fading out from GLOW_ALPHA times the strength in the middle to nothing at the edge
+pub fn glow_mesh(radius: f32, color: Color, strength: f32) -> Mesh {
+    let edge = color.as_linear_rgba_f32();
+    let mut center = edge;
+    center[3] *= GLOW_ALPHA * strength;
+    let mut positions = vec![[0., 0., 0.]];
+    let mut normals = vec![[0., 0., 1.]];
+    let mut uvs = vec![[0.5, 0.5]];
+    let mut colors = vec![center];
+    let mut indices = Vec::new();
+    let sides = 24;
+    for i in 0..sides {
+        let angle = TAU * i as f32 / sides as f32;
+        let (sin, cos) = angle.sin_cos();
+        positions.push([cos * radius, sin * radius, 0.]);
+        normals.push([0., 0., 1.]);
+        uvs.push([0.5 + cos / 2., 0.5 - sin / 2.]);
+        colors.push([edge[0], edge[1], edge[2], 0.]);
+        let corner = i + 1;
+        let next = (i + 1) % sides + 1;
+        indices.extend([0, corner, next]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+pub fn shield_ring_mesh(player_size: f32) -> Mesh {
+    sector_mesh(0., TAU, player_size / 2. + 0.05, player_size / 2. + 0.12)
+}
+
+pub fn team_colors(color: usize, colorblind: bool) -> (Color, Color) {
+    if colorblind {
+        return colorblind_team_colors(color);
+    }
+    // (cycle color, trail color)
+    match color {
+        0 => (Color::RED, Color::ORANGE_RED),
+        1 => (Color::BLUE, Color::ALICE_BLUE),
+        2 => (Color::YELLOW, Color::GOLD),
+        3 => (Color::PURPLE, Color::VIOLET),
+        4 => (Color::TEAL, Color::TURQUOISE),
+        5 => (Color::FUCHSIA, Color::PINK),
+        6 => (Color::ORANGE, Color::BISQUE),
+        7 => (Color::BLACK, Color::SILVER),
+        _ => panic!("invalid team"),
+    }
+}
+
+fn default_name(handle: usize) -> String {
+    format!("Player {}", handle + 1)
+}
+
+fn team_name(color: usize, colorblind: bool) -> &'static str {
+    match (color, colorblind) {
+        (0, false) => "Red",
+        (0, true) => "Orange",
+        (1, _) => "Blue",
+        (2, _) => "Yellow",
+        (3, false) => "Purple",
+        (3, true) => "Pink",
+        (4, false) => "Teal",
+        (4, true) => "Green",
+        (5, false) => "Fuchsia",
+        (5, true) => "Sky Blue",
+        (6, false) => "Orange",
+        (6, true) => "Vermilion",
+        (7, _) => "Black",
+        _ => panic!("invalid team"),
+    }
+}
+
+// Okabe-Ito, which stays distinguishable for the common kinds of color blindness
+fn colorblind_team_colors(color: usize) -> (Color, Color) {
+    let cycle_color = match color {
+        0 => Color::rgb_u8(230, 159, 0),
+        1 => Color::rgb_u8(0, 114, 178),
+        2 => Color::rgb_u8(240, 228, 66),
+        3 => Color::rgb_u8(204, 121, 167),
+        4 => Color::rgb_u8(0, 158, 115),
+        5 => Color::rgb_u8(86, 180, 233),
+        6 => Color::rgb_u8(213, 94, 0),
+        7 => Color::BLACK,
+        _ => panic!("invalid team"),
+    };
+    // lighter trails, so they still read as belonging to the cycle
+    let trail_color = Color::rgb(
+        (cycle_color.r() + 1.) / 2.,
+        (cycle_color.g() + 1.) / 2.,
+        (cycle_color.b() + 1.) / 2.,
+    );
+    (cycle_color, trail_color)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn control_spectator(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    local: Res<LocalHandle>,
+    player_query: Query<(&Transform, &Player), Without<Camera>>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut spectator: ResMut<Spectator>,
+) {
+    let scroll: f32 = wheel_events.iter().map(|event| event.y).sum();
+    if player_query.iter().any(|(_, p)| p.handle == local.0) {
+        spectator.active = false;
+        return;
+    }
+    let player_position = |handle| {
+        player_query
+            .iter()
+            .find(|(_, p)| p.handle == handle)
+            .map(|(transform, _)| transform.translation.truncate())
+    };
+
+    // take over from wherever the camera is when we start spectating
+    let start = |spectator: &mut Spectator| {
+        if spectator.active {
+            return;
+        }
+        spectator.active = true;
+        spectator.following = None;
+        if let Ok((transform, projection)) = camera_query.get_single() {
+            spectator.position = transform.translation.truncate();
+            spectator.view_size = match projection.scaling_mode {
+                ScalingMode::FixedVertical(size) => size,
+                _ => mode.board_size(&tuning) + 1.,
+            };
+        }
+    };
+
+    for (handle, key) in SPECTATOR_KEYS.iter().enumerate() {
+        // the number keys pick emotes while the wheel is open
+        if keys.pressed(EMOTE_WHEEL_KEY) {
+            break;
+        }
+        if keys.just_pressed(*key) && player_position(handle).is_some() {
+            start(&mut spectator);
+            spectator.following = Some(handle);
+        }
+    }
+
+    let mut pan = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::Left, KeyCode::A]) {
+        pan.x -= 1.;
+    }
+    if keys.any_pressed([KeyCode::Right, KeyCode::D]) {
+        pan.x += 1.;
+    }
+    if keys.any_pressed([KeyCode::Up, KeyCode::W]) {
+        pan.y += 1.;
+    }
+    if keys.any_pressed([KeyCode::Down, KeyCode::S]) {
+        pan.y -= 1.;
+    }
+    if pan != Vec2::ZERO {
+        start(&mut spectator);
+        // let go of whoever we were following, from where they are now
+        let following = spectator.following.take();
+        if let Some(position) = following.and_then(player_position) {
+            spectator.position = position;
+        }
+        let view_size = spectator.view_size;
+        spectator.position += pan * SPECTATOR_PAN_SPEED * view_size * time.delta_seconds();
+    }
+
+    let mut zoom = scroll;
+    if keys.just_pressed(KeyCode::Equals) {
+        zoom += 1.;
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        zoom -= 1.;
+    }
+    if zoom != 0. {
+        start(&mut spectator);
+        spectator.view_size = (spectator.view_size * (1. - zoom * SPECTATOR_ZOOM_STEP))
+            .clamp(FOLLOW_VIEW_SIZE / 2., mode.board_size(&tuning) * 2.);
+    }
+}
+
+// Runs on the rendered state rather than in the rollback, and eases towards its target, so
+// a rollback nudging our cycle doesn't make the view jump
+#[allow(clippy::too_many_arguments)]
+pub fn move_camera(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    options: Res<Settings>,
+    local: Res<LocalHandle>,
+    round: Res<RoundState>,
+    spectator: Res<Spectator>,
+    killcam: Res<Killcam>,
+    mut shake: ResMut<CameraShake>,
+    player_query: Query<(&Transform, &Player), Without<Camera>>,
+    dash_query: Query<(&Player, &DashEnergy)>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+) {
+    let followed = spectator.following.and_then(|handle| {
+        player_query
+            .iter()
+            .find(|(_, p)| p.handle == handle)
+            .map(|(transform, _)| transform.translation.truncate())
+    });
+    let camera_mode = if spectator.active {
+        None
+    } else {
+        Some(options.camera_mode)
+    };
+    let (target, view_size) = match camera_mode {
+        _ if killcam.playing() => (killcam.focus, FOLLOW_VIEW_SIZE),
+        None => (followed.unwrap_or(spectator.position), spectator.view_size),
+        Some(CameraMode::Board) => (Vec2::ZERO, mode.board_size(&tuning) + 1.),
+        Some(CameraMode::Follow) => {
+            let player = player_query.iter().find(|(_, p)| p.handle == local.0);
+            match player {
+                Some((transform, _)) => (
+                    transform.translation.truncate() + transform.local_x().truncate() * CAMERA_LEAD,
+                    FOLLOW_VIEW_SIZE,
+                ),
+                // watch the whole arena while we're dead
+                None => (Vec2::ZERO, mode.board_size(&tuning) + 1.),
+            }
+        }
+        Some(CameraMode::FitPlayers) => {
+            let aspect = match windows.get_primary() {
+                Some(window) => window.width() / window.height(),
+                None => 1.,
+            };
+            let positions = player_query
+                .iter()
+                .map(|(transform, _)| transform.translation.truncate());
+            let (min, max) = positions.fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), position| (min.min(position), max.max(position)),
+            );
+            if min.x > max.x {
+                // nobody alive
+                (Vec2::ZERO, mode.board_size(&tuning) + 1.)
+            } else {
+                let extent = max - min + Vec2::splat(CAMERA_MARGIN * 2.);
+                // the scaling mode fixes the height, so a wide box needs a taller view
+                let view_size = extent.y.max(extent.x / aspect);
+                (
+                    (min + max) / 2.,
+                    view_size.clamp(FOLLOW_VIEW_SIZE, mode.board_size(&tuning) + 1.),
+                )
+            }
+        }
+    };
+    // swoop in from the whole arena over the countdown, so every round visibly starts afresh.
+    // It's the same for everyone and nobody can move yet, so there's nothing to steer.
+    let flying_in = round.in_countdown() && camera_mode.is_some() && !killcam.playing();
+    let (target, view_size) = if flying_in {
+        let overview = mode.board_size(&tuning) + 1.;
+        let t = round.frame as f32 / COUNTDOWN_FRAMES as f32;
+        let t = t * t * (3. - 2. * t);
+        (target * t, overview + (view_size - overview) * t)
+    } else {
+        (target, view_size)
+    };
+    // pull back a little while we dash, it sells the speed
+    let dashing = dash_query
+        .iter()
+        .any(|(player, dash)| player.handle == local.0 && dash.dashing);
+    let view_size = if dashing {
+        view_size * DASH_ZOOM
+    } else {
+        view_size
+    };
+
+    let blend = 1. - (-CAMERA_DAMPING * time.delta_seconds()).exp();
+    for (mut transform, mut projection) in camera_query.iter_mut() {
+        let current = match projection.scaling_mode {
+            ScalingMode::FixedVertical(size) => size,
+            _ => view_size,
+        };
+        // the shake sits on top, so it never drags the smoothed position around
+        let position = (transform.translation.truncate() - shake.offset).lerp(target, blend);
+        shake.offset = shake.jitter * current;
+        transform.translation = (position + shake.offset).extend(transform.translation.z);
+        projection.scaling_mode =
+            ScalingMode::FixedVertical(current + (view_size - current) * blend);
+    }
+}
+
+// Unlike sounds, shakes wait until their frame is confirmed, so a death that a rollback takes
+// back never shakes the screen
+fn shake_camera(
+    time: Res<Time>,
+    options: Res<Settings>,
+    local: Res<LocalHandle>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    cues: Res<CueLog>,
+    // frames up to here have been shaken for already
+    mut released: Local<u32>,
+    mut shake: ResMut<CameraShake>,
+) {
+    // clock frames run one ahead of GGRS frames, this errs on the side of waiting a frame
+    let confirmed = match &session {
+        Some(session) => session.confirmed_frame().max(0) as u32,
+        // nothing else can change what a local match simulated
+        None => cues.frame,
+    };
+    let kicks = cues
+        .cues
+        .iter()
+        .filter(|(frame, _)| *frame > *released && *frame <= confirmed);
+    for (_, cue) in kicks {
+        shake.trauma += match cue {
+            Cue::Death { death, .. } if death.victim == local.0 => 1.,
+            Cue::Death { .. } => DEATH_SHAKE,
+            Cue::NearMiss(handle) if *handle == local.0 => NEAR_MISS_SHAKE,
+            _ => 0.,
+        };
+    }
+    *released = (*released).max(confirmed);
+    shake.trauma = (shake.trauma - SHAKE_DECAY * time.delta_seconds()).clamp(0., 1.);
+
+    // a couple of out of step wobbles look random enough
+    let t = time.seconds_since_startup() as f32 * SHAKE_FREQUENCY;
+    let wobble = Vec2::new(
+        (t * 1.3).sin() + (t * 2.9).sin(),
+        (t * 1.7).cos() + (t * 3.1).sin(),
+    ) / 2.;
+    shake.jitter = wobble * shake.trauma.powi(2) * MAX_SHAKE * options.screen_shake;
+}
+
+fn spawn_trail_ribbons(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    trail_assets: Res<TrailAssets>,
+    mode: Res<GameMode>,
+) {
+    for handle in 0..mode.num_players() {
+        // the trail just under the cycles, its glow under every trail
+        for (glow, z) in [(false, 0.4), (true, 0.3)] {
+            commands
+                .spawn_bundle(MaterialMesh2dBundle {
+                    mesh: meshes.add(ribbon_mesh(&[], &RIBBON_PROFILE)).into(),
+                    material: trail_assets.ribbon_material.clone(),
+                    transform: Transform::from_translation(Vec3::new(0., 0., z)),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(TrailRibbon { handle, glow });
+        }
+    }
+}
+
+// Drawn over everything else in the arena, and hidden until there's a clip to play
+#[allow(clippy::too_many_arguments)]
+fn spawn_killcam(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    trail_assets: Res<TrailAssets>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    cosmetics: Res<Cosmetics>,
+) {
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(shape::Quad::new(Vec2::splat(KILLCAM_BACKDROP_SIZE)).into())
+                .into(),
+            material: materials.add(ColorMaterial::from(Color::rgba(
+                0.,
+                0.,
+                0.,
+                KILLCAM_BACKDROP_ALPHA,
+            ))),
+            transform: Transform::from_translation(Vec3::new(0., 0., 5.)),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(KillcamBackdrop);
+    for handle in 0..mode.num_players() {
+        commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: meshes.add(ribbon_mesh(&[], &RIBBON_PROFILE)).into(),
+                material: trail_assets.ribbon_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 5.1)),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(KillcamRibbon { handle });
+        let (cycle_color, _) = cosmetics.team_colors(mode.team_of(handle));
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: cycle_color,
+                    // same as the real thing
+                    custom_size: Some(Vec2::new(
+                        tuning.player_size * 1.3,
+                        tuning.player_size * 0.65,
+                    )),
+                    ..default()
+                },
+                texture: asset_server.load(cosmetics.skin(handle).sprite),
+                transform: Transform::from_translation(Vec3::new(0., 0., 5.2)),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(KillcamCycle { handle });
+    }
+}
+
+// Waits for the end of the round to be confirmed, then cuts a clip ending on the last death
+// that fits in what's left of the countdown, or all of KILLCAM_FRAMES once the match is over
+#[allow(clippy::too_many_arguments)]
+pub fn start_killcam(
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    clock: Res<SimulationClock>,
+    round: Res<RoundState>,
+    scoreboard: Res<Scoreboard>,
+    log: Res<KillcamLog>,
+    mut round_events: EventReader<RoundEndEvent>,
+    mut killcam: ResMut<Killcam>,
+) {
+    if round_events.iter().count() > 0 {
+        killcam.pending = Some(clock.frame);
+    }
+    let end = match killcam.pending {
+        Some(end) => end,
+        None => return,
+    };
+    // same as shake_camera
+    let confirmed = match &session {
+        Some(session) => session.confirmed_frame().max(0) as u32,
+        None => clock.frame,
+    };
+    if confirmed < end {
+        return;
+    }
+    killcam.pending = None;
+
+    let death = log
+        .frames
+        .iter()
+        .rposition(|frame| frame.frame <= end && frame.trails.is_some());
+    let death = match death {
+        Some(death) => death,
+        None => return,
+    };
+    let length = if scoreboard.match_over {
+        KILLCAM_FRAMES
+    } else {
+        COUNTDOWN_FRAMES.saturating_sub(round.frame) / KILLCAM_SLOWDOWN
+    };
+    let start = (death + 1).saturating_sub(length as usize);
+    let clip: Vec<KillcamFrame> = log.frames.range(start..=death).cloned().collect();
+    // whoever was there the frame before and isn't anymore
+    let last = &clip[clip.len() - 1];
+    let focus = clip
+        .iter()
+        .rev()
+        .nth(1)
+        .and_then(|before| {
+            before
+                .cycles
+                .iter()
+                .find(|(handle, ..)| !last.cycles.iter().any(|(alive, ..)| alive == handle))
+        })
+        .map_or(Vec2::ZERO, |(_, position, _)| *position);
+    *killcam = Killcam {
+        trails: log.frames[death].trails.clone().unwrap_or_default(),
+        clip,
+        focus,
+        ..default()
+    };
+}
+
+// Blends between the recorded frames, with trails growing back in as far as they'd been laid
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn play_killcam(
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    cosmetics: Res<Cosmetics>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut killcam: ResMut<Killcam>,
+    mut backdrop_query: Query<
+        &mut Visibility,
+        (
+            With<KillcamBackdrop>,
+            Without<KillcamCycle>,
+            Without<KillcamRibbon>,
+        ),
+    >,
+    mut cycle_query: Query<
+        (&KillcamCycle, &mut Transform, &mut Visibility),
+        Without<KillcamRibbon>,
+    >,
+    mut ribbon_query: Query<(&KillcamRibbon, &Mesh2dHandle, &mut Visibility)>,
+) {
+    if !killcam.playing() {
+        return;
+    }
+    killcam.elapsed += time.delta_seconds();
+    let progress = killcam.elapsed * (TICKS_PER_SECOND / KILLCAM_SLOWDOWN) as f32;
+    let index = progress as usize;
+    let finished = index + 1 >= killcam.clip.len();
+    for mut visibility in backdrop_query.iter_mut() {
+        visibility.is_visible = !finished;
+    }
+    if finished {
+        killcam.clip.clear();
+        for (_, _, mut visibility) in cycle_query.iter_mut() {
+            visibility.is_visible = false;
+        }
+        for (_, _, mut visibility) in ribbon_query.iter_mut() {
+            visibility.is_visible = false;
+        }
+        return;
+    }
+
+    let (from, to) = (&killcam.clip[index], &killcam.clip[index + 1]);
+    let blend = progress.fract();
+    for (cycle, mut transform, mut visibility) in cycle_query.iter_mut() {
+        let pose = |frame: &KillcamFrame| {
+            frame
+                .cycles
+                .iter()
+                .find(|(handle, ..)| *handle == cycle.handle)
+                .map(|(_, position, heading)| (*position, Quat::from_rotation_z(*heading)))
+        };
+        // anyone missing from the next frame died on it, and stays where they crashed
+        let pose = match (pose(from), pose(to)) {
+            (Some(from), Some(to)) => Some((from.0.lerp(to.0, blend), from.1.slerp(to.1, blend))),
+            (from, _) => from,
+        };
+        visibility.is_visible = pose.is_some();
+        if let Some((position, rotation)) = pose {
+            transform.translation = position.extend(transform.translation.z);
+            transform.rotation = rotation;
+        }
+    }
+
+    for (ribbon, mesh, mut visibility) in ribbon_query.iter_mut() {
+        let points: Vec<RibbonPoint> = killcam
+            .trails
+            .iter()
+            .filter(|(handle, _)| *handle == ribbon.handle)
+            .flat_map(|(_, points)| points)
+            .filter(|point| point.frame <= from.frame)
+            .map(|point| RibbonPoint {
+                connected: point.connected,
+                position: point.position,
+                heading: point.heading,
+                width: point.size,
+                color: cosmetics.trail_color(mode.team_of(ribbon.handle), point.frame),
+            })
+            .collect();
+        visibility.is_visible = !points.is_empty();
+        if let Some(mesh) = meshes.get_mut(&mesh.0) {
+            *mesh = ribbon_mesh(&points, &RIBBON_PROFILE);
+        }
+    }
+}
+
+// Rebuilds each ribbon from whatever trail segments the simulation has right now, so
+// rollbacks fix the picture up for free
+#[allow(clippy::too_many_arguments)]
+fn update_trail_ribbons(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mode: Res<GameMode>,
+    settings: Res<MatchSettings>,
+    round: Res<RoundState>,
+    options: Res<Settings>,
+    cosmetics: Res<Cosmetics>,
+    trail_query: Query<&Trail>,
+    mut ribbon_query: Query<(&TrailRibbon, &Mesh2dHandle, &mut Visibility)>,
+) {
+    // same as kill_trail, trails that never die never start fading either
+    let fading = settings.trail_length != 0 && !round.sudden_death();
+    let mut segments: Vec<Vec<RibbonPoint>> = vec![Vec::new(); mode.num_players()];
+    for trail in trail_query.iter() {
+        let handle = trail.player_handle;
+        // skins only change how big the trail looks, collisions still use the real size
+        let (scale, alpha) = cosmetics.skin(handle).trail_look();
+        for point in trail.points.iter() {
+            let life = if fading {
+                (point.frames_left as f32 / TRAIL_FADE_FRAMES as f32).min(1.)
+            } else {
+                1.
+            };
+            let mut color = cosmetics.trail_color(mode.team_of(handle), point.frame);
+            if point.dashed {
+                color = Color::rgb(
+                    color.r() + (1. - color.r()) * DASH_TRAIL_WHITEN,
+                    color.g() + (1. - color.g()) * DASH_TRAIL_WHITEN,
+                    color.b() + (1. - color.b()) * DASH_TRAIL_WHITEN,
+                );
+            }
+            color.set_a(alpha * life);
+            segments[handle].push(RibbonPoint {
+                connected: point.connected,
+                position: point.position,
+                heading: point.heading,
+                // only down to half, it still kills until it's gone
+                width: point.size * scale * (0.5 + life / 2.),
+                color,
+            });
+        }
+    }
+
+    let glow = cosmetics.glow(&options);
+    let glow_profile = GLOW_PROFILE.map(|(offset, alpha, white)| (offset, alpha * glow, white));
+    for (ribbon, mesh, mut visibility) in ribbon_query.iter_mut() {
+        let points = &segments[ribbon.handle];
+        visibility.is_visible = !points.is_empty() && (glow > 0. || !ribbon.glow);
+        if !visibility.is_visible {
+            continue;
+        }
+        let profile: &[(f32, f32, f32)] = match (ribbon.glow, glow > 0.) {
+            (true, _) => &glow_profile,
+            (false, true) => &HOT_RIBBON_PROFILE,
+            (false, false) => &RIBBON_PROFILE,
+        };
+        if let Some(mesh) = meshes.get_mut(&mesh.0) {
+            *mesh = ribbon_mesh(points, profile);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct RibbonPoint {
+    pub connected: bool,
+    pub position: Vec2,
+    pub heading: Vec2,
+    pub width: f32,
+    pub color: Color,
+}
+
+// A strip along each unbroken run of points, squared off as far past either end as the
+// profile is wide
+fn ribbon_mesh(points: &[RibbonPoint], profile: &[(f32, f32, f32)]) -> Mesh {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+
+    let mut start = 0;
+    while start < points.len() {
+        let mut end = start + 1;
+        while end < points.len() && points[end].connected {
+            end += 1;
+        }
+        let run = &points[start..end];
+        let first = run[0];
+        let last = run[run.len() - 1];
+        let reach = profile[0].0;
+        let caps = [
+            RibbonPoint {
+                position: first.position - first.heading * first.width * reach,
+                ..first
+            },
+            RibbonPoint {
+                position: last.position + last.heading * last.width * reach,
+                ..last
+            },
+        ];
+        let run_points = std::iter::once(caps[0])
+            .chain(run.iter().copied())
+            .chain(std::iter::once(caps[1]));
+        for (i, point) in run_points.enumerate() {
+            let side = point.heading.perp() * point.width;
+            let [r, g, b, a] = point.color.as_linear_rgba_f32();
+            for (j, &(offset, alpha, white)) in profile.iter().enumerate() {
+                let corner = point.position + side * offset;
+                positions.push([corner.x, corner.y, 0.]);
+                normals.push([0., 0., 1.]);
+                uvs.push([0., j as f32 / (profile.len() - 1) as f32]);
+                colors.push([
+                    r + (1. - r) * white,
+                    g + (1. - g) * white,
+                    b + (1. - b) * white,
+                    a * alpha,
+                ]);
+            }
+            if i > 0 {
+                let n = profile.len() as u32;
+                let current = positions.len() as u32 - n;
+                let previous = current - n;
+                for j in 0..n - 1 {
+                    let (a, b) = (previous + j, current + j);
+                    indices.extend([a, b, a + 1, a + 1, b, b + 1]);
+                }
+            }
+        }
+        start = end;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+// The simulation spawns its pieces bare, these give them something to look at. Anything a
+// rollback brings back gets dressed again the same way.
+fn add_board_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    zone_query: Query<(Entity, &BoostZone), Added<BoostZone>>,
+    hill_query: Query<Entity, Added<Hill>>,
+) {
+    for (entity, zone) in zone_query.iter() {
+        commands.entity(entity).insert_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(sector_mesh(
+                    zone.start_angle,
+                    zone.end_angle,
+                    zone.inner_radius,
+                    zone.outer_radius,
+                ))
+                .into(),
+            material: materials.add(ColorMaterial::from(Color::rgb(0.6, 1.0, 0.75))),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.1)),
+            ..default()
+        });
+    }
+    for entity in hill_query.iter() {
+        commands.entity(entity).insert_bundle(MaterialMesh2dBundle {
+            mesh: meshes.add(shape::Circle::new(HILL_RADIUS).into()).into(),
+            material: materials.add(ColorMaterial::from(Color::rgb(0.9, 0.85, 0.4))),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.1)),
+            ..default()
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_cycle_sprites(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    cosmetics: Res<Cosmetics>,
+    player_query: Query<(Entity, &Position, &Heading, &Player), Added<Player>>,
+) {
+    for (entity, position, heading, player) in player_query.iter() {
+        let (cycle_color, trail_color) = cosmetics.team_colors(mode.team_of(player.handle));
+        let skin = cosmetics.skin(player.handle);
+        commands
+            .entity(entity)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: cycle_color,
+                    // longer than it is wide, the hitbox is still the player_size circle
+                    custom_size: Some(Vec2::new(
+                        tuning.player_size * 1.3,
+                        tuning.player_size * 0.65,
+                    )),
+                    ..default()
+                },
+                texture: asset_server.load(skin.sprite),
+                transform: Transform {
+                    translation: position.0.extend(0.5),
+                    rotation: Quat::from_rotation_z(heading.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(MaterialMesh2dBundle {
+                        mesh: meshes.add(shield_ring_mesh(tuning.player_size)).into(),
+                        material: materials.add(ColorMaterial::from(PowerUpKind::Shield.color())),
+                        transform: Transform::from_translation(Vec3::new(0., 0., 2.)),
+                        visibility: Visibility { is_visible: false },
+                        ..default()
+                    })
+                    .insert(ShieldRing);
+                parent
+                    .spawn_bundle(MaterialMesh2dBundle {
+                        mesh: meshes
+                            .add(glow_mesh(
+                                tuning.player_size * 1.5,
+                                trail_color,
+                                cosmetics.theme.effects(),
+                            ))
+                            .into(),
+                        material: materials.add(ColorMaterial::from(Color::WHITE)),
+                        // under the trails as well as the cycle
+                        transform: Transform::from_translation(Vec3::new(0., 0., -0.2)),
+                        ..default()
+                    })
+                    .insert(CycleGlow);
+            });
+    }
+}
+
+fn add_pickup_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    power_up_query: Query<(Entity, &Position, &PowerUp), Added<PowerUp>>,
+    pellet_query: Query<(Entity, &Position), Added<Pellet>>,
+) {
+    for (entity, position, power_up) in power_up_query.iter() {
+        commands.entity(entity).insert_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(shape::Circle::new(POWER_UP_SIZE / 2.).into())
+                .into(),
+            material: materials.add(ColorMaterial::from(power_up.kind.color())),
+            transform: Transform::from_translation(position.0.extend(0.2)),
+            ..default()
+        });
+    }
+    if pellet_query.is_empty() {
+        return;
+    }
+    let mesh: Mesh2dHandle = meshes
+        .add(shape::Circle::new(PELLET_SIZE / 2.).into())
+        .into();
+    let material = materials.add(ColorMaterial::from(Color::WHITE));
+    for (entity, position) in pellet_query.iter() {
+        commands.entity(entity).insert_bundle(MaterialMesh2dBundle {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            transform: Transform::from_translation(position.0.extend(0.2)),
+            ..default()
+        });
+    }
+}
+
+fn add_ghost_mesh(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    tuning: Res<Tuning>,
+    ghost_query: Query<Entity, Added<GhostCycle>>,
+) {
+    for entity in ghost_query.iter() {
+        commands.entity(entity).insert_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(shape::Circle::new(tuning.player_size / 2.).into())
+                .into(),
+            material: materials.add(ColorMaterial::from(Color::rgba(1., 1., 1., 0.35))),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.4)),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        });
+    }
+}
+
+fn update_ghost_visibility(
+    round: Res<RoundState>,
+    ghost: Res<Ghost>,
+    mut ghost_query: Query<&mut Visibility, With<GhostCycle>>,
+) {
+    let visible = match &ghost.replay {
+        Some(replay) => round.frame > 0 && (round.frame as usize) <= replay.inputs.len(),
+        None => false,
+    };
+    for mut visibility in ghost_query.iter_mut() {
+        visibility.is_visible = visible;
+    }
+}
+
+fn show_cycle_glow(
+    options: Res<Settings>,
+    cosmetics: Res<Cosmetics>,
+    mut glow_query: Query<&mut Visibility, With<CycleGlow>>,
+) {
+    for mut visibility in glow_query.iter_mut() {
+        visibility.is_visible = cosmetics.glow(&options) > 0.;
+    }
+}
+
+// The arena and background follow the settings right away, cycles and trails pick the
+// theme up from Cosmetics when the next match starts
+fn apply_theme(
+    options: Res<Settings>,
+    mut clear_color: ResMut<ClearColor>,
+    mut grid_materials: ResMut<Assets<GridMaterial>>,
+) {
+    if !options.is_changed() {
+        return;
+    }
+    clear_color.0 = options.theme.background();
+    let (floor_color, line_color) = options.theme.floor();
+    for (_, material) in grid_materials.iter_mut() {
+        material.uniform.floor_color = floor_color.as_linear_rgba_f32().into();
+        material.uniform.line_color = line_color.as_linear_rgba_f32().into();
+    }
+}
+
+// Only touches the window when the display settings themselves change, so a window resized
+// by hand stays that way until a different resolution is picked
+fn apply_display(
+    options: Res<Settings>,
+    mut windows: ResMut<Windows>,
+    mut applied: Local<Option<DisplaySettings>>,
+) {
+    if *applied == Some(options.display) {
+        return;
+    }
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let display = options.display;
+    if cfg!(not(target_arch = "wasm32")) {
+        window.set_mode(display.window_mode());
+        if applied.map(|applied| applied.resolution) != Some(display.resolution) {
+            window.set_resolution(display.resolution.0, display.resolution.1);
+        }
+    }
+    window.set_present_mode(display.present_mode());
+    *applied = Some(display);
+}
+
+// Sleeps off whatever's left of this frame's share of a second. The simulation keeps its
+// own time, so this only changes how often it gets drawn.
+#[cfg(not(target_arch = "wasm32"))]
+fn limit_frame_rate(options: Res<Settings>, mut last_frame: Local<Option<std::time::Instant>>) {
+    if let (Some(limit), Some(last_frame)) = (options.display.frame_limit, *last_frame) {
+        let frame_time = std::time::Duration::from_secs_f64(1. / limit as f64);
+        if let Some(left) = frame_time.checked_sub(last_frame.elapsed()) {
+            std::thread::sleep(left);
+        }
+    }
+    *last_frame = Some(std::time::Instant::now());
+}
+
+fn animate_grid(time: Res<Time>, mut grid_materials: ResMut<Assets<GridMaterial>>) {
+    for (_, material) in grid_materials.iter_mut() {
+        material.uniform.time = time.seconds_since_startup() as f32;
+    }
+}
+
+fn update_arena(
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    round: Res<RoundState>,
+    mut arena_query: Query<&mut Transform, With<Arena>>,
+) {
+    let scale = mode.arena_radius(&round, &tuning) / (mode.board_size(&tuning) / 2.);
+    for mut transform in arena_query.iter_mut() {
+        transform.scale = Vec3::new(scale, scale, 1.);
+    }
+}
+
+// the lobby's arena size only lands in Tuning once the match's settings are in
+fn resize_arena_floor(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    mut arena_query: Query<&mut Mesh2dHandle, With<Arena>>,
+) {
+    if !mode.is_changed() && !tuning.is_changed() {
+        return;
+    }
+    for mut mesh in arena_query.iter_mut() {
+        *mesh = meshes
+            .add(shape::Circle::new(mode.board_size(&tuning) / 2.).into())
+            .into();
+    }
+}
+
+fn update_shield_rings(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    tuning: Res<Tuning>,
+    shield_query: Query<(&GlobalTransform, Option<&Shield>), With<Player>>,
+    mut ring_query: Query<(&Parent, &mut Visibility), With<ShieldRing>>,
+) {
+    for (parent, mut visibility) in ring_query.iter_mut() {
+        let (player_transform, shield) = match shield_query.get(parent.get()) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let shielded = shield.is_some();
+        if visibility.is_visible && !shielded {
+            commands
+                .spawn_bundle(MaterialMesh2dBundle {
+                    mesh: meshes.add(shield_ring_mesh(tuning.player_size)).into(),
+                    material: materials.add(ColorMaterial::from(PowerUpKind::Shield.color())),
+                    transform: Transform::from_translation(
+                        player_transform.translation().truncate().extend(2.),
+                    ),
+                    ..default()
+                })
+                .insert(ShieldPop {
+                    timer: Timer::from_seconds(SHIELD_POP_SECONDS, false),
+                });
+        }
+        visibility.is_visible = shielded;
+    }
+}
+
+fn animate_shield_pops(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut pop_query: Query<(
+        Entity,
+        &mut ShieldPop,
+        &mut Transform,
+        &Handle<ColorMaterial>,
+    )>,
+) {
+    for (entity, mut pop, mut transform, material) in pop_query.iter_mut() {
+        if pop.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let progress = pop.timer.percent();
+        transform.scale = Vec3::splat(1. + progress);
+        if let Some(material) = materials.get_mut(material) {
+            material.color.set_a(1. - progress);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_death_particles(
+    mut commands: Commands,
+    trail_assets: Res<TrailAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mode: Res<GameMode>,
+    cosmetics: Res<Cosmetics>,
+    mut death_events: EventReader<DeathEvent>,
+    // where each cycle was last drawn, it's usually gone by the time we hear it died
+    mut positions: Local<HashMap<usize, Vec2>>,
+    player_query: Query<(&Transform, &Player)>,
+) {
+    for death in death_events.iter() {
+        let position = match positions.get(&death.victim) {
+            Some(position) => *position,
+            None => continue,
+        };
+        let (color, _) = cosmetics.team_colors(mode.team_of(death.victim));
+        // one material for the whole burst, every particle fades together
+        let material = materials.add(ColorMaterial::from(color));
+        let seed = death
+            .frame
+            .wrapping_mul(31)
+            .wrapping_add(death.victim as u32);
+        // themes without effects still get half a burst, it's what tells you someone died
+        let count = (DEATH_PARTICLES as f32 * (1. + cosmetics.theme.effects()) / 2.) as u32;
+        for i in 0..count {
+            let roll = pseudo_random(seed.wrapping_add(i));
+            let angle = TAU * (i as f32 + (roll % 100) as f32 / 100.) / count as f32;
+            let speed = PARTICLE_SPEED * (0.3 + (roll / 100 % 70) as f32 / 100.);
+            commands
+                .spawn_bundle(MaterialMesh2dBundle {
+                    mesh: trail_assets.particle_mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(position.extend(3.))
+                        .with_rotation(Quat::from_rotation_z(angle)),
+                    ..default()
+                })
+                .insert(Particle {
+                    velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+                    timer: Timer::from_seconds(PARTICLE_SECONDS, false),
+                });
+        }
+    }
+
+    positions.clear();
+    for (transform, player) in player_query.iter() {
+        positions.insert(player.handle, transform.translation.truncate());
+    }
+}
+
+// Streaks left behind dashing cycles, they reuse the particle fade-out
+#[allow(clippy::too_many_arguments)]
+fn spawn_dash_streaks(
+    mut commands: Commands,
+    trail_assets: Res<TrailAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    cosmetics: Res<Cosmetics>,
+    // (seconds until the next streak, streaks so far for the randomness)
+    mut streaks: Local<(f32, u32)>,
+    player_query: Query<(&Transform, &Player, &DashEnergy)>,
+) {
+    streaks.0 -= time.delta_seconds();
+    if streaks.0 > 0. {
+        return;
+    }
+    streaks.0 = DASH_STREAK_INTERVAL;
+    for (transform, player, dash) in player_query.iter() {
+        if !dash.dashing {
+            continue;
+        }
+        streaks.1 = streaks.1.wrapping_add(1);
+        let roll = pseudo_random(streaks.1);
+        let side = ((roll % 100) as f32 / 100. - 0.5) * tuning.player_size;
+        let heading = transform.local_x().truncate();
+        let position = transform.translation.truncate() + heading.perp() * side
+            - heading * tuning.player_size / 2.;
+        let (_, color) = cosmetics.team_colors(mode.team_of(player.handle));
+        commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: trail_assets.streak_mesh.clone(),
+                material: materials.add(ColorMaterial::from(color)),
+                transform: Transform::from_translation(position.extend(3.))
+                    .with_rotation(transform.rotation),
+                ..default()
+            })
+            .insert(Particle {
+                velocity: -heading * PARTICLE_SPEED / 2.,
+                timer: Timer::from_seconds(DASH_STREAK_SECONDS, false),
+            });
+    }
+}
+
+fn animate_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut particle_query: Query<(
+        Entity,
+        &mut Particle,
+        &mut Transform,
+        &Handle<ColorMaterial>,
+    )>,
+) {
+    let drag = PARTICLE_DRAG.powf(time.delta_seconds());
+    for (entity, mut particle, mut transform, material) in particle_query.iter_mut() {
+        if particle.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        particle.velocity *= drag;
+        transform.translation += (particle.velocity * time.delta_seconds()).extend(0.);
+        let progress = particle.timer.percent();
+        transform.scale = Vec3::splat(1. - progress);
+        if let Some(material) = materials.get_mut(material) {
+            material.color.set_a(1. - progress);
+        }
+    }
+}
+
+// 0 while comfortably inside the arena, up to 1 right on the border
+pub fn edge_danger(mode: &GameMode, round: &RoundState, tuning: &Tuning, position: Vec2) -> f32 {
+    let room = mode.arena_radius(round, tuning) - position.length();
+    (1. - room / EDGE_WARNING_DISTANCE).clamp(0., 1.)
+}
+
+// flashes faster than the countdown so it can't be mistaken for anything else
+pub fn edge_warning_pulse(time: &Time) -> f32 {
+    0.5 + 0.5 * (time.seconds_since_startup() as f32 * EDGE_WARNING_FLASHES * TAU).sin()
+}
+
+fn tint_players(
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    cosmetics: Res<Cosmetics>,
+    round: Res<RoundState>,
+    mut player_query: Query<(&Transform, &Player, &mut Sprite, Option<&Slowed>)>,
+) {
+    let pulse = edge_warning_pulse(&time);
+    for (transform, player, mut sprite, slowed) in player_query.iter_mut() {
+        let (base_color, _) = cosmetics.team_colors(mode.team_of(player.handle));
+        let color = match slowed {
+            // washed out towards grey while crawling
+            Some(_) => Color::rgb(
+                (base_color.r() + 0.5) / 3.,
+                (base_color.g() + 0.5) / 3.,
+                (base_color.b() + 0.5) / 3.,
+            ),
+            None => base_color,
+        };
+        // flashing red as it nears the border
+        let danger = edge_danger(&mode, &round, &tuning, transform.translation.truncate()) * pulse;
+        let color = Color::rgb(
+            color.r() + (1. - color.r()) * danger,
+            color.g() * (1. - danger),
+            color.b() * (1. - danger),
+        );
+        // only touch the sprite when it changes, so change detection stays quiet
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
@@ -0,0 +1,771 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+};
+
+use bevy::{app::AppExit, prelude::*};
+use ggrs::{InputStatus, P2PSession, SpectatorSession};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::Args,
+    gameplay::{
+        DashEnergy, GameMode, Heading, MatchSettings, Pellet, Player, Position, PowerUp,
+        RoundState, Scoreboard, SimulationClock, Steering, Trail, ROYALE_PLAYERS,
+    },
+    networking::GgrsConfig,
+    rendering::Cosmetics,
+    tuning::Tuning,
+    GameState,
+};
+
+// Catching desyncs before players do. --record keeps every frame's inputs along with a
+// checksum of where they led, and --replay feeds the same inputs back in and fails as soon as
+// a checksum comes out different. Online, every peer logs a checksum now and then so
+// desynced players can compare notes. Short sessions can also be shared as a link, with the
+// whole log packed into the URL's fragment.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let args = app.world.resource::<Args>();
+        let recording_to = args.record.clone();
+        let log = args.replay.as_deref().and_then(InputLog::load);
+
+        // a replay has to be played by the rules it was recorded under
+        if let Some(log) = &log {
+            app.insert_resource(log.mode)
+                .insert_resource(log.steering)
+                .insert_resource(log.tuning.clone());
+        }
+        app.insert_resource(Replay {
+            log,
+            recording_to,
+            ..default()
+        })
+        .insert_resource(ConfirmedChecksums::default())
+        .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(forget_drills))
+        .add_system_set(
+            // the session keeps going under the victory ceremony and the settings menu
+            SystemSet::on_in_stack_update(GameState::InGame)
+                .with_system(stop_after_frames)
+                .with_system(check_replay)
+                .with_system(log_confirmed_checksums)
+                .with_system(name_replayed_players),
+        )
+        .add_system_to_stage(CoreStage::Last, save_recording);
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system_to_stage(CoreStage::Last, exit_on_desync.after(save_recording));
+    }
+}
+
+// What --record and the post-game screen write and --replay reads. On disk it's REPLAY_MAGIC
+// and the format version as a little-endian u32, then all of this in bincode. Everything
+// before the inputs is the header, enough to set the match up exactly as it was.
+#[derive(Serialize, Deserialize)]
+pub struct InputLog {
+    pub mode: GameMode,
+    pub steering: Steering,
+    pub tuning: Tuning,
+    // the seed's in here too
+    pub settings: MatchSettings,
+    // by handle, as they were shown
+    pub names: Vec<String>,
+    // by frame and then handle, with whatever the bots decided filled in
+    pub inputs: Vec<Vec<u8>>,
+    // of the state each frame ended in, only kept with --record
+    pub checksums: Vec<u64>,
+}
+
+impl InputLog {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("failed to serialize input log");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let body = bytes.strip_prefix(REPLAY_MAGIC).ok_or("not a replay")?;
+        let (version, body) = body.split_at(body.len().min(4));
+        match version.try_into().map(u32::from_le_bytes) {
+            Ok(REPLAY_VERSION) => bincode::deserialize(body).map_err(|e| e.to_string()),
+            Ok(version) => Err(format!(
+                "format version {}, this build plays version {}",
+                version, REPLAY_VERSION
+            )),
+            Err(_) => Err("cut off before the version".to_string()),
+        }
+    }
+
+    // Short enough for a link: the header as usual, then each handle's inputs as (run length,
+    // input) pairs, since inputs tend to stay the same for a while. Checksums are left out.
+    pub fn to_share_code(&self) -> String {
+        let mut bytes = REPLAY_VERSION.to_le_bytes().to_vec();
+        let header = (
+            self.mode,
+            self.steering,
+            &self.tuning,
+            self.settings,
+            &self.names,
+        );
+        bincode::serialize_into(&mut bytes, &header).expect("failed to serialize input log");
+
+        let players = self.inputs.first().map_or(0, Vec::len);
+        write_varint(&mut bytes, self.inputs.len());
+        write_varint(&mut bytes, players);
+        for handle in 0..players {
+            let mut frame = 0;
+            while frame < self.inputs.len() {
+                let input = self.inputs[frame][handle];
+                let run = self.inputs[frame..]
+                    .iter()
+                    .take_while(|inputs| inputs[handle] == input)
+                    .count();
+                write_varint(&mut bytes, run);
+                bytes.push(input);
+                frame += run;
+            }
+        }
+        base64_encode(&bytes)
+    }
+
+    pub fn from_share_code(code: &str) -> Result<Self, String> {
+        let bytes = base64_decode(code)?;
+        let (version, mut body) = bytes.split_at(bytes.len().min(4));
+        match version.try_into().map(u32::from_le_bytes) {
+            Ok(REPLAY_VERSION) => {}
+            Ok(version) => {
+                return Err(format!(
+                    "format version {}, this build plays version {}",
+                    version, REPLAY_VERSION
+                ))
+            }
+            Err(_) => return Err("cut off before the version".to_string()),
+        }
+        let (mode, steering, tuning, settings, names) =
+            bincode::deserialize_from(&mut body).map_err(|e| e.to_string())?;
+
+        let frames = read_varint(&mut body)?;
+        let players = read_varint(&mut body)?;
+        if frames > MAX_SHARED_FRAMES || players > ROYALE_PLAYERS {
+            return Err("too many frames or players".to_string());
+        }
+        let mut inputs = vec![vec![0; players]; frames];
+        for handle in 0..players {
+            let mut frame = 0;
+            while frame < frames {
+                let run = read_varint(&mut body)?;
+                let (&input, rest) = body.split_first().ok_or("cut off in the inputs")?;
+                body = rest;
+                if run == 0 || frame + run > frames {
+                    return Err("inputs don't add up".to_string());
+                }
+                for frame_inputs in &mut inputs[frame..frame + run] {
+                    frame_inputs[handle] = input;
+                }
+                frame += run;
+            }
+        }
+        Ok(Self {
+            mode,
+            steering,
+            tuning,
+            settings,
+            names,
+            inputs,
+            checksums: Vec::new(),
+        })
+    }
+
+    // from a file, or from a link with the log in its fragment
+    fn load(source: &str) -> Option<Self> {
+        let log = match source.split_once(SHARE_PREFIX) {
+            Some((_, code)) => Self::from_share_code(code),
+            None => std::fs::read(source)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| Self::from_bytes(&bytes)),
+        };
+        match log {
+            Ok(log) => Some(log),
+            Err(e) => {
+                error!("failed to load input log {}: {}", source, e);
+                // a browser can still show the menu, but there's nothing to do on the command
+                // line without it
+                if cfg!(not(target_arch = "wasm32")) {
+                    std::process::exit(2);
+                }
+                None
+            }
+        }
+    }
+
+    fn save(&self, path: &str) {
+        match std::fs::write(path, self.to_bytes()) {
+            Ok(()) => info!("wrote {} frames to {}", self.inputs.len(), path),
+            Err(e) => warn!("failed to save input log: {}", e),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Replay {
+    // the log being played back
+    pub log: Option<InputLog>,
+    // first frame whose checksum didn't match the log
+    pub desync: Option<u32>,
+    pub recording_to: Option<String>,
+    // by frame, re-simulated frames just overwrite themselves. Inputs are always kept so the
+    // post-game screen can export them, checksums only with --record.
+    pub inputs: Vec<Vec<u8>>,
+    pub checksums: Vec<u64>,
+    // Frames nothing can roll back any more, as of the last one GGRS had us simulate. It
+    // polls its peers between steps too, so its own confirmed frame can be past one that's
+    // still waiting to be rolled back.
+    pub confirmed: u32,
+    // whether a drill put a bot at its start this session, which isn't in the inputs
+    pub drilled: bool,
+}
+
+impl Replay {
+    // everything needed to watch this session again, which isn't there once a drill moved a
+    // bot around
+    pub fn to_log(
+        &self,
+        mode: GameMode,
+        steering: Steering,
+        tuning: &Tuning,
+        settings: MatchSettings,
+        cosmetics: &Cosmetics,
+    ) -> Option<InputLog> {
+        if self.drilled {
+            return None;
+        }
+        Some(InputLog {
+            mode,
+            steering,
+            tuning: tuning.clone(),
+            settings,
+            names: cosmetics.names.clone(),
+            inputs: self.inputs.clone(),
+            checksums: self.checksums.clone(),
+        })
+    }
+}
+
+fn forget_drills(mut replay: ResMut<Replay>) {
+    replay.drilled = false;
+}
+
+// Checksums of every CHECKSUM_LOG_FRAMES-th frame, logged once nothing can roll them back
+#[derive(Default)]
+pub struct ConfirmedChecksums {
+    // by SimulationClock::frame
+    pub pending: BTreeMap<u32, u64>,
+}
+
+const CHECKSUM_LOG_FRAMES: u32 = 600;
+
+// Swaps in the logged inputs for every handle, bots included, so nobody has to decide anything
+pub fn replay_inputs(
+    clock: Res<SimulationClock>,
+    replay: Res<Replay>,
+    mut inputs: ResMut<Vec<(u8, InputStatus)>>,
+) {
+    let logged = match &replay.log {
+        Some(log) => log.inputs.get(clock.frame as usize - 1),
+        None => return,
+    };
+    if let Some(logged) = logged {
+        for ((input, _), logged) in inputs.iter_mut().zip(logged) {
+            *input = *logged;
+        }
+    }
+}
+
+// Runs at the very end of each simulated frame
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn log_frame(
+    clock: Res<SimulationClock>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    inputs: Res<Vec<(u8, InputStatus)>>,
+    round: Res<RoundState>,
+    scoreboard: Res<Scoreboard>,
+    mut replay: ResMut<Replay>,
+    mut confirmed: ResMut<ConfirmedChecksums>,
+    player_query: Query<(&Position, &Heading, &Player, &DashEnergy)>,
+    trail_query: Query<&Trail>,
+    item_query: Query<&Position, Or<(With<PowerUp>, With<Pellet>)>>,
+) {
+    let index = clock.frame as usize - 1;
+    replay.inputs.truncate(index);
+    replay
+        .inputs
+        .push(inputs.iter().map(|(input, _)| *input).collect());
+    // GGRS counts from 0, the clock has already ticked once by the end of that frame
+    replay.confirmed = session.map_or(clock.frame, |session| {
+        (session.confirmed_frame() + 1).clamp(0, clock.frame as i32) as u32
+    });
+
+    let logged = clock.frame % CHECKSUM_LOG_FRAMES == 0;
+    if !logged && replay.log.is_none() && replay.recording_to.is_none() {
+        return;
+    }
+    let checksum = checksum(
+        &round,
+        &scoreboard,
+        &player_query,
+        &trail_query,
+        &item_query,
+    );
+    if logged {
+        confirmed.pending.insert(clock.frame, checksum);
+    }
+
+    let expected = replay
+        .log
+        .as_ref()
+        .and_then(|log| log.checksums.get(index).copied());
+    if matches!(expected, Some(expected) if expected != checksum) && replay.desync.is_none() {
+        replay.desync = Some(clock.frame);
+    }
+
+    if replay.recording_to.is_some() {
+        replay.checksums.truncate(index);
+        replay.checksums.push(checksum);
+    }
+}
+
+// Everything that decides how the match plays out from here. Floats go in by their bits,
+// since being off by the last one is exactly what we're looking for.
+#[allow(clippy::type_complexity)]
+fn checksum(
+    round: &RoundState,
+    scoreboard: &Scoreboard,
+    player_query: &Query<(&Position, &Heading, &Player, &DashEnergy)>,
+    trail_query: &Query<&Trail>,
+    item_query: &Query<&Position, Or<(With<PowerUp>, With<Pellet>)>>,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    round.hash(&mut hasher);
+    scoreboard.hash(&mut hasher);
+
+    let mut players: Vec<_> = player_query.iter().collect();
+    players.sort_by_key(|(_, _, player, _)| player.handle);
+    for (position, heading, player, dash) in players {
+        player.handle.hash(&mut hasher);
+        position.0.to_array().map(f32::to_bits).hash(&mut hasher);
+        heading.0.to_bits().hash(&mut hasher);
+        dash.hash(&mut hasher);
+    }
+
+    let mut trails: Vec<&Trail> = trail_query.iter().collect();
+    trails.sort_by_key(|trail| trail.player_handle);
+    for trail in trails {
+        trail.player_handle.hash(&mut hasher);
+        for point in &trail.points {
+            point
+                .position
+                .to_array()
+                .map(f32::to_bits)
+                .hash(&mut hasher);
+            point.size.to_bits().hash(&mut hasher);
+            point.frames_left.hash(&mut hasher);
+            point.connected.hash(&mut hasher);
+        }
+    }
+
+    // power-ups and pellets can come back in a different order after a rollback
+    let mut items: Vec<[u32; 2]> = item_query
+        .iter()
+        .map(|position| position.0.to_array().map(f32::to_bits))
+        .collect();
+    items.sort_unstable();
+    items.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+// Every peer prints the same lines until they desync, so the first line that differs between
+// two logs is where it went wrong
+fn log_confirmed_checksums(
+    replay: Res<Replay>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    spectator: Option<Res<SpectatorSession<GgrsConfig>>>,
+    mut checksums: ResMut<ConfirmedChecksums>,
+) {
+    // spectators only ever simulate what's confirmed
+    if session.is_none() && spectator.is_none() {
+        return;
+    }
+    let confirmed = replay.confirmed;
+    let unconfirmed = checksums.pending.split_off(&(confirmed + 1));
+    for (frame, checksum) in std::mem::replace(&mut checksums.pending, unconfirmed) {
+        info!("checksum at frame {}: {:016x}", frame, checksum);
+    }
+}
+
+fn stop_after_frames(args: Res<Args>, clock: Res<SimulationClock>, mut exit: EventWriter<AppExit>) {
+    if matches!(args.frames, Some(frames) if clock.frame >= frames) {
+        info!("simulated {} frames, quitting", clock.frame);
+        exit.send(AppExit);
+    }
+}
+
+// Headless replays quit once the log runs out, anyone watching one gets to look at how it
+// ended instead
+fn check_replay(
+    args: Res<Args>,
+    replay: Res<Replay>,
+    clock: Res<SimulationClock>,
+    mut exit: EventWriter<AppExit>,
+    mut finished: Local<bool>,
+) {
+    let log = match &replay.log {
+        Some(log) => log,
+        None => return,
+    };
+    if let Some(frame) = replay.desync {
+        // exit_on_desync turns this into the exit code once the recording's saved
+        error!("desync: frame {} doesn't match the input log", frame);
+        exit.send(AppExit);
+        return;
+    }
+    if clock.frame as usize >= log.inputs.len() && !*finished {
+        info!("replayed {} frames without a desync", log.inputs.len());
+        *finished = true;
+        if args.headless {
+            exit.send(AppExit);
+        }
+    }
+}
+
+// Shows whoever was playing when it was recorded rather than our own profile
+fn name_replayed_players(replay: Res<Replay>, mut cosmetics: ResMut<Cosmetics>) {
+    if let Some(log) = &replay.log {
+        if cosmetics.names != log.names {
+            cosmetics.names = log.names.clone();
+        }
+    }
+}
+
+// Saved on the way out, however the game got told to quit
+fn save_recording(
+    mut exits: EventReader<AppExit>,
+    replay: Res<Replay>,
+    mode: Res<GameMode>,
+    steering: Res<Steering>,
+    tuning: Res<Tuning>,
+    // both come with the match, quitting before one started leaves nothing to save
+    settings: Option<Res<MatchSettings>>,
+    cosmetics: Option<Res<Cosmetics>>,
+) {
+    if exits.iter().count() == 0 {
+        return;
+    }
+    let (path, settings, cosmetics) = match (&replay.recording_to, settings, cosmetics) {
+        (Some(path), Some(settings), Some(cosmetics)) => (path, settings, cosmetics),
+        _ => return,
+    };
+    match replay.to_log(*mode, *steering, &tuning, *settings, &cosmetics) {
+        Some(log) => log.save(path),
+        None => warn!("not writing {}, a drill can't be replayed", path),
+    }
+}
+
+// The world's gone by the time App::run returns, so a desync sets the exit code from in here,
+// on the same frame the app was told to quit
+#[cfg(not(target_arch = "wasm32"))]
+fn exit_on_desync(mut exits: EventReader<AppExit>, replay: Res<Replay>) {
+    if exits.iter().count() > 0 && replay.desync.is_some() {
+        std::process::exit(1);
+    }
+}
+
+// Into the replays folder, named for when it was saved
+#[cfg(not(target_arch = "wasm32"))]
+pub fn export(log: &InputLog) {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    if let Err(e) = std::fs::create_dir_all(REPLAY_DIR) {
+        warn!("failed to create {}: {}", REPLAY_DIR, e);
+        return;
+    }
+    log.save(&format!("{}/{}.replay", REPLAY_DIR, seconds));
+}
+
+// As a download, by clicking a link to the file that never gets shown
+#[cfg(target_arch = "wasm32")]
+pub fn export(log: &InputLog) {
+    let url = format!(
+        "data:application/octet-stream;base64,{}",
+        base64_encode(&log.to_bytes())
+    );
+    if download(&url, REPLAY_DOWNLOAD_NAME).is_none() {
+        warn!("failed to download the replay");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn download(url: &str, file_name: &str) -> Option<()> {
+    let document = web_sys::window()?.document()?;
+    let body = document.body()?;
+    let link = document.create_element("a").ok()?;
+    link.set_attribute("href", url).ok()?;
+    link.set_attribute("download", file_name).ok()?;
+    body.append_child(&link).ok()?;
+    // only a MouseEvent counts as a click that follows the link
+    let click = web_sys::MouseEvent::new("click").ok()?;
+    link.dispatch_event(&click).ok()?;
+    body.remove_child(&link).ok()?;
+    Some(())
+}
+
+// Puts a link to the replay in the address bar, ready to be copied and sent. Browsers don't
+// reload for a new fragment, so it only starts playing once someone opens it.
+#[cfg(target_arch = "wasm32")]
+pub fn share(log: &InputLog) {
+    let code = match share_code(log) {
+        Some(code) => code,
+        None => return,
+    };
+    let hash = format!("{}{}", SHARE_PREFIX.trim_start_matches('#'), code);
+    let shared = web_sys::window().map(|window| window.location().set_hash(&hash));
+    if matches!(shared, Some(Ok(()))) {
+        info!("the address bar links to this replay now");
+    } else {
+        warn!("failed to put the replay link in the address bar");
+    }
+}
+
+// Without a page of our own the fragment is all there is to hand out
+#[cfg(not(target_arch = "wasm32"))]
+pub fn share(log: &InputLog) {
+    if let Some(code) = share_code(log) {
+        info!(
+            "to share this replay, add this to the game's web address, or watch it with --replay: {}{}",
+            SHARE_PREFIX, code
+        );
+    }
+}
+
+fn share_code(log: &InputLog) -> Option<String> {
+    let code = log.to_share_code();
+    if code.len() > MAX_SHARE_CODE_LENGTH {
+        warn!("the replay is too long for a link, save it instead");
+        return None;
+    }
+    Some(code)
+}
+
+// LEB128, so short runs take a single byte
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<usize, String> {
+    let mut value = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or("cut off in a number")?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("number out of range".to_string())
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let (mut group, mut bits) = (0u32, 0);
+    for c in encoded.bytes().filter(|c| *c != b'=') {
+        let value = BASE64_ALPHABET
+            .iter()
+            .position(|letter| *letter == c)
+            .ok_or("not base64")?;
+        group = (group << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+// Standard alphabet with padding, which is all fine in a URL's fragment
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | ((*byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[((group >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+const REPLAY_MAGIC: &[u8; 4] = b"TRNR";
+
+// bumped whenever InputLog or anything in it changes shape, or the simulation plays the same
+// inputs out differently
+const REPLAY_VERSION: u32 = 4;
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_DIR: &str = "replays";
+
+#[cfg(target_arch = "wasm32")]
+const REPLAY_DOWNLOAD_NAME: &str = "tron.replay";
+
+// what comes before the code in a link to a replay
+pub const SHARE_PREFIX: &str = "#replay=";
+
+// comfortably under what browsers and chat apps put up with in a link
+const MAX_SHARE_CODE_LENGTH: usize = 16 * 1024;
+
+// an hour, anything claiming more is broken and shouldn't get allocated
+const MAX_SHARED_FRAMES: usize = 60 * 60 * 60;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gameplay::{pseudo_random, BotPlayers},
+        networking::SYNCTEST_CHECK_DISTANCE,
+        stepping::{self, Stepper},
+    };
+
+    #[test]
+    fn same_seed_same_checksums() {
+        let first = record(7);
+        let second = record(7);
+        assert_eq!(first.checksums.len(), TEST_FRAMES as usize);
+        assert_eq!(first.inputs, second.inputs);
+        assert_eq!(first.checksums, second.checksums);
+    }
+
+    #[test]
+    fn replay_reaches_the_same_checksums() {
+        let log = record(11);
+        let mut app = match_app(log.mode, log.settings);
+        app.insert_resource(log.steering)
+            .insert_resource(log.tuning.clone());
+        let mut replay = app.world.resource_mut::<Replay>();
+        replay.log = Some(InputLog::from_bytes(&log.to_bytes()).unwrap());
+        // the log already has what the bots did
+        app.world.resource_mut::<BotPlayers>().0.clear();
+        step(&mut app, TEST_FRAMES);
+
+        let replay = app.world.resource::<Replay>();
+        assert_eq!(replay.desync, None);
+        assert_eq!(replay.checksums, log.checksums);
+        assert_eq!(replay.inputs, log.inputs);
+    }
+
+    #[test]
+    fn input_log_round_trips() {
+        let log = sample_log(true);
+        let bytes = log.to_bytes();
+        let loaded = InputLog::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert_eq!(loaded.inputs, log.inputs);
+        assert_eq!(loaded.checksums, log.checksums);
+
+        // share codes leave the checksums behind
+        let shared = InputLog::from_share_code(&log.to_share_code()).unwrap();
+        assert_eq!(shared.to_bytes(), sample_log(false).to_bytes());
+        assert_eq!(shared.inputs, log.inputs);
+        assert!(shared.checksums.is_empty());
+    }
+
+    #[test]
+    fn broken_logs_are_refused() {
+        let bytes = sample_log(true).to_bytes();
+        assert!(InputLog::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+        assert!(InputLog::from_bytes(b"not a replay").is_err());
+
+        let code = sample_log(false).to_share_code();
+        assert!(InputLog::from_share_code(&code[..code.len() / 2]).is_err());
+    }
+
+    // Bots on every handle of a royale for the most trails, rolled back like --synctest does,
+    // and sent through the file format like --record and --replay would
+    fn record(seed: u32) -> InputLog {
+        let settings = MatchSettings { seed, ..default() };
+        let mut app = match_app(GameMode::Royale, settings);
+        app.world.resource_mut::<BotPlayers>().0 = (0..ROYALE_PLAYERS).collect();
+        step(&mut app, TEST_FRAMES);
+        let log = app
+            .world
+            .resource::<Replay>()
+            .to_log(
+                GameMode::Royale,
+                *app.world.resource::<Steering>(),
+                app.world.resource::<Tuning>(),
+                settings,
+                &Cosmetics::default(),
+            )
+            .unwrap();
+        InputLog::from_bytes(&log.to_bytes()).unwrap()
+    }
+
+    // nothing gets written, it's only there so the checksums are kept like --record does
+    fn match_app(mode: GameMode, settings: MatchSettings) -> App {
+        let mut app = stepping::match_app(mode, settings);
+        app.world.resource_mut::<Replay>().recording_to = Some(String::new());
+        app
+    }
+
+    // At a fixed step rather than GGRS's wall clock, with every frame rolled back and
+    // simulated again like a synctest session does
+    fn step(app: &mut App, frames: u32) {
+        Stepper::new(SYNCTEST_CHECK_DISTANCE).advance_idle(&mut app.world, frames);
+    }
+
+    // runs of the same input of all sorts of lengths, like real steering
+    fn sample_log(checksums: bool) -> InputLog {
+        let frames = 1000;
+        InputLog {
+            mode: GameMode::Royale,
+            steering: Steering::Grid,
+            tuning: Tuning::default(),
+            settings: MatchSettings {
+                seed: 42,
+                ..default()
+            },
+            names: vec!["Sam".to_string(), "Bot 2".to_string()],
+            inputs: (0..frames)
+                .map(|frame| {
+                    (0..ROYALE_PLAYERS)
+                        .map(|handle| (pseudo_random(frame / (handle as u32 + 3)) % 8) as u8)
+                        .collect()
+                })
+                .collect(),
+            checksums: if checksums {
+                (0..frames)
+                    .map(|frame| pseudo_random(frame) as u64)
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    // past the countdown and long enough for trails to start fading
+    const TEST_FRAMES: u32 = 480;
+}
@@ -0,0 +1,353 @@
+use bevy::{
+    prelude::*,
+    window::{PresentMode, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    daily::DailyRecord,
+    input::KeyBindings,
+    profile::{LifetimeStats, Ratings},
+};
+
+// Colors for everything that isn't a power-up or the HUD. None of it reaches the simulation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Theme {
+    // flat green floor on grey, no glow
+    Classic,
+    #[default]
+    Neon,
+    // black and white around pure team colors, no glow
+    HighContrast,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Classic, Theme::Neon, Theme::HighContrast];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Classic => "Classic",
+            Theme::Neon => "Neon",
+            Theme::HighContrast => "High contrast",
+        }
+    }
+
+    pub fn next(&self) -> Theme {
+        let i = Theme::ALL
+            .iter()
+            .position(|theme| theme == self)
+            .unwrap_or(0);
+        Theme::ALL[(i + 1) % Theme::ALL.len()]
+    }
+
+    pub fn background(&self) -> Color {
+        match self {
+            Theme::Classic => Color::rgb(0.53, 0.53, 0.53),
+            Theme::Neon => Color::rgb(0.01, 0.01, 0.03),
+            Theme::HighContrast => Color::BLACK,
+        }
+    }
+
+    // (floor color, grid line color)
+    pub fn floor(&self) -> (Color, Color) {
+        match self {
+            Theme::Classic => (Color::SEA_GREEN, Color::SEA_GREEN),
+            Theme::Neon => (Color::rgb(0.02, 0.04, 0.08), Color::rgb(0.1, 0.75, 1.)),
+            Theme::HighContrast => (Color::BLACK, Color::rgb(0.35, 0.35, 0.35)),
+        }
+    }
+
+    // 0 to 1, scales the glow and how much a death throws around
+    pub fn effects(&self) -> f32 {
+        match self {
+            Theme::Neon => 1.,
+            Theme::Classic | Theme::HighContrast => 0.,
+        }
+    }
+
+    // adjusts a (cycle color, trail color) pair from team_colors
+    pub fn team_colors(&self, (cycle_color, trail_color): (Color, Color)) -> (Color, Color) {
+        match self {
+            Theme::Classic | Theme::Neon => (cycle_color, trail_color),
+            // trails as strong as the cycle, and nothing black on the black floor
+            Theme::HighContrast if cycle_color == Color::BLACK => (Color::WHITE, Color::WHITE),
+            Theme::HighContrast => (cycle_color, cycle_color),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum CameraMode {
+    // the whole arena, fixed in place
+    #[default]
+    Board,
+    // zoomed in on our own cycle, looking a little ahead of it
+    Follow,
+    // as close as it can get while keeping every living player in view
+    FitPlayers,
+}
+
+// How the window is shown. Browsers own the canvas and pace their own frames, so none of it
+// does anything on the web.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    // borderless, on whichever monitor the window is on
+    pub fullscreen: bool,
+    // (width, height) of the window when it isn't fullscreen, one of RESOLUTIONS
+    pub resolution: (f32, f32),
+    pub vsync: bool,
+    // frames a second to hold to, None draws as fast as it can
+    pub frame_limit: Option<u32>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            fullscreen: false,
+            resolution: RESOLUTIONS[0],
+            vsync: true,
+            frame_limit: None,
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+
+    // the Auto modes fall back to whatever's closest if the GPU can't do exactly that
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    pub fn next_resolution(&self) -> (f32, f32) {
+        let i = RESOLUTIONS
+            .iter()
+            .position(|resolution| *resolution == self.resolution)
+            .map_or(0, |i| i + 1);
+        RESOLUTIONS[i % RESOLUTIONS.len()]
+    }
+
+    pub fn next_frame_limit(&self) -> Option<u32> {
+        let i = FRAME_LIMITS
+            .iter()
+            .position(|limit| *limit == self.frame_limit)
+            .map_or(0, |i| i + 1);
+        FRAME_LIMITS[i % FRAME_LIMITS.len()]
+    }
+}
+
+pub const RESOLUTIONS: [(f32, f32); 4] =
+    [(1280., 720.), (1600., 900.), (1920., 1080.), (2560., 1440.)];
+
+const FRAME_LIMITS: [Option<u32>; 5] = [None, Some(30), Some(60), Some(120), Some(144)];
+
+// Where WebRTC looks for a way through NATs. STUN alone can't get through a symmetric NAT,
+// players behind one need a TURN server here to relay for them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IceServerSettings {
+    // stun: and turn: URLs, all for the same server
+    pub urls: Vec<String>,
+    // what TURN servers want, STUN needs neither
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+impl Default for IceServerSettings {
+    fn default() -> Self {
+        Self {
+            urls: vec![STUN_SERVER.to_string()],
+            username: None,
+            credential: None,
+        }
+    }
+}
+
+// the one matchbox uses when it isn't given any
+const STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+
+// What other players get to see of us, sent to every peer in the lobby
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerProfile {
+    // index into the team colors, other players keep theirs if we clash
+    pub color: usize,
+    // index into SKINS
+    pub skin: usize,
+    // set with --name, None goes by handle
+    pub name: Option<String>,
+    // a headless client, steered by the bot on every peer
+    pub bot: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrailStyle {
+    Solid,
+    // drawn smaller than they really are, so the wall reads as a string of beads
+    Beads,
+    Translucent,
+}
+
+// How a cycle and its trail are drawn. Only ever looked at when picking meshes and
+// materials, the simulation doesn't know skins exist.
+pub struct Skin {
+    pub name: &'static str,
+    // facing +x and drawn in greys, the team color tints it
+    pub sprite: &'static str,
+    pub trail_style: TrailStyle,
+}
+
+impl Skin {
+    // (mesh scale, alpha) for the visible part of a trail segment
+    pub fn trail_look(&self) -> (f32, f32) {
+        match self.trail_style {
+            TrailStyle::Solid => (1., 1.),
+            TrailStyle::Beads => (0.6, 1.),
+            TrailStyle::Translucent => (1., 0.5),
+        }
+    }
+}
+
+pub static SKINS: [Skin; 4] = [
+    Skin {
+        name: "Classic",
+        sprite: "sprites/classic.png",
+        trail_style: TrailStyle::Solid,
+    },
+    Skin {
+        name: "Arrow",
+        sprite: "sprites/arrow.png",
+        trail_style: TrailStyle::Beads,
+    },
+    Skin {
+        name: "Block",
+        sprite: "sprites/block.png",
+        trail_style: TrailStyle::Solid,
+    },
+    Skin {
+        name: "Phantom",
+        sprite: "sprites/phantom.png",
+        trail_style: TrailStyle::Translucent,
+    },
+];
+
+// Local preferences, unlike MatchSettings these never leave this machine
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // 0 to 1, the music and effect volumes are scaled by this
+    pub volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+    // None picks the one from tuning.json
+    pub input_delay: Option<usize>,
+    // seconds anyone watching our matches is kept behind them, so they can't call out where
+    // everyone is
+    pub spectator_delay: u32,
+    pub colorblind: bool,
+    pub theme: Theme,
+    pub key_bindings: KeyBindings,
+    // turn towards the cursor and dash with the left button
+    pub mouse_steering: bool,
+    pub camera_mode: CameraMode,
+    // 0 to 1, scales how far the camera shakes
+    pub screen_shake: f32,
+    // soft halos around cycles and trails, off is cheaper to draw
+    pub glow: bool,
+    pub display: DisplaySettings,
+    pub ice_server: IceServerSettings,
+    // seconds to wait for someone to join before offering to retry or play bots, None waits
+    // for as long as it takes
+    pub matchmaking_timeout: Option<u32>,
+    pub profile: PlayerProfile,
+    pub stats: LifetimeStats,
+    pub ratings: Ratings,
+    pub daily: DailyRecord,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            volume: 0.8,
+            music_volume: 0.6,
+            effects_volume: 1.,
+            input_delay: None,
+            spectator_delay: 3,
+            colorblind: false,
+            theme: Theme::default(),
+            key_bindings: KeyBindings::default(),
+            mouse_steering: false,
+            camera_mode: CameraMode::Board,
+            screen_shake: 1.,
+            glow: true,
+            display: DisplaySettings::default(),
+            ice_server: IceServerSettings::default(),
+            matchmaking_timeout: Some(60),
+            profile: PlayerProfile::default(),
+            stats: LifetimeStats::default(),
+            ratings: Ratings::default(),
+            daily: DailyRecord::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn music_gain(&self) -> f32 {
+        self.volume * self.music_volume
+    }
+
+    pub fn effects_gain(&self) -> f32 {
+        self.volume * self.effects_volume
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        std::fs::read_to_string(SETTINGS_FILE)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        local_storage()
+            .and_then(|storage| storage.get_item(SETTINGS_FILE).ok().flatten())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) {
+        let json = serde_json::to_string_pretty(self).expect("failed to serialize settings");
+        if let Err(e) = std::fs::write(SETTINGS_FILE, json) {
+            warn!("failed to save settings: {}", e);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) {
+        let json = serde_json::to_string(self).expect("failed to serialize settings");
+        let saved = local_storage().map(|storage| storage.set_item(SETTINGS_FILE, &json));
+        if !matches!(saved, Some(Ok(()))) {
+            warn!("failed to save settings to local storage");
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+const SETTINGS_FILE: &str = "settings.json";
@@ -0,0 +1,408 @@
+use std::{
+    collections::HashSet,
+    f32::consts::{FRAC_PI_4, SQRT_2},
+};
+
+use bevy::{audio::AudioSink, prelude::*};
+
+use crate::{
+    gameplay::{
+        emit_round_events, Cue, CueLog, DeathCause, GameMode, MatchSettings, Player, RoundEndEvent,
+        TICKS_PER_SECOND,
+    },
+    networking::LocalHandle,
+    rendering::move_camera,
+    settings::Settings,
+    GameState,
+};
+
+// Music, engines and sound effects, all played from render-side state so rollbacks can't
+// replay them
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EngineSounds::default())
+            .insert_resource(Music::default())
+            .insert_resource(SoundQueue::default())
+            .insert_resource(Listener::default())
+            .add_startup_system(load_sound_effects)
+            .add_system(play_music)
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(start_engine_sounds))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(queue_sounds)
+                    .with_system(
+                        play_sound_effects
+                            .after(queue_sounds)
+                            .after(emit_round_events)
+                            .after(update_listener),
+                    )
+                    .with_system(update_listener.after(move_camera))
+                    .with_system(update_engine_sounds.after(update_listener)),
+            );
+    }
+}
+
+pub struct SoundEffects {
+    pub trail_hit: PannedSound,
+    pub border_hit: PannedSound,
+    pub power_up: PannedSound,
+    pub countdown_beep: PannedSound,
+    pub go: PannedSound,
+    pub round_win: Handle<AudioSource>,
+}
+
+impl SoundEffects {
+    pub fn get(&self, sound: Sound) -> &PannedSound {
+        match sound {
+            Sound::TrailHit => &self.trail_hit,
+            Sound::BorderHit => &self.border_hit,
+            Sound::PowerUp => &self.power_up,
+            Sound::CountdownBeep => &self.countdown_beep,
+            Sound::Go => &self.go,
+        }
+    }
+}
+
+// bevy_audio can't pan, so positional sounds come as a pair with one side each, played
+// together at whatever volumes put them in the right place
+#[derive(Clone)]
+pub struct PannedSound {
+    pub left: Handle<AudioSource>,
+    pub right: Handle<AudioSource>,
+}
+
+impl PannedSound {
+    pub fn load(asset_server: &AssetServer, name: &str) -> Self {
+        Self {
+            left: asset_server.load(&format!("sounds/left/{}.wav", name)),
+            right: asset_server.load(&format!("sounds/right/{}.wav", name)),
+        }
+    }
+
+    pub fn play(&self, audio: &Audio, volume: f32, (left, right): (f32, f32)) {
+        for (sound, gain) in [(&self.left, left), (&self.right, right)] {
+            audio.play_with_settings(
+                sound.clone(),
+                PlaybackSettings::ONCE.with_volume(volume * gain),
+            );
+        }
+    }
+}
+
+// Where sounds are heard from: our own cycle, or the camera while we're dead
+#[derive(Default)]
+pub struct Listener(pub Vec2);
+
+impl Listener {
+    // (left, right) gains, equal power so sounds stay as loud as they move across, and
+    // both at 1 for anything dead ahead
+    pub fn pan(&self, position: Vec2) -> (f32, f32) {
+        let pan = ((position.x - self.0.x) / PAN_DISTANCE).clamp(-1., 1.);
+        let angle = (pan + 1.) * FRAC_PI_4;
+        (angle.cos() * SQRT_2, angle.sin() * SQRT_2)
+    }
+
+    pub fn falloff(&self, position: Vec2) -> f32 {
+        (1. - self.0.distance(position) / HEARING_DISTANCE).max(0.)
+    }
+}
+
+// Sounds the simulation's cues can make
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sound {
+    TrailHit,
+    BorderHit,
+    PowerUp,
+    CountdownBeep,
+    Go,
+}
+
+// Identifies a sound by when and for whom it happened, so re-simulating the frame asks for
+// the very same cue
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SoundCue {
+    pub frame: u32,
+    pub sound: Sound,
+    pub handle: usize,
+}
+
+// Sounds picked out of the CueLog. Every cue plays the first time it's simulated, and
+// rollbacks that simulate it again are ignored.
+#[derive(Default)]
+struct SoundQueue {
+    // SimulationClock::frame of the newest frame simulated
+    pub frame: u32,
+    // with where they happened, if anywhere in particular
+    pub pending: Vec<(SoundCue, Option<Vec2>)>,
+    // cues already queued, forgotten once they're too old to be re-simulated
+    pub queued: HashSet<SoundCue>,
+}
+
+impl SoundQueue {
+    pub fn play(&mut self, frame: u32, sound: Sound, handle: usize, position: Option<Vec2>) {
+        let cue = SoundCue {
+            frame,
+            sound,
+            handle,
+        };
+        if self.queued.insert(cue) {
+            self.pending.push((cue, position));
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<(SoundCue, Option<Vec2>)> {
+        let frame = self.frame;
+        self.queued
+            .retain(|cue| cue.frame + SOUND_HISTORY_FRAMES >= frame);
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Track {
+    Menu,
+    Game,
+}
+
+impl Track {
+    pub fn for_state(state: &GameState) -> Self {
+        match state {
+            GameState::InGame | GameState::Victory => Track::Game,
+            _ => Track::Menu,
+        }
+    }
+
+    pub fn path(&self) -> &'static str {
+        match self {
+            Track::Menu => "sounds/menu_music.wav",
+            Track::Game => "sounds/game_music.wav",
+        }
+    }
+}
+
+// Whatever's playing or fading out, newest last
+#[derive(Default)]
+pub struct Music {
+    // (track, sink, how faded in it is from 0 to 1)
+    pub playing: Vec<(Track, Handle<AudioSink>, f32)>,
+}
+
+// One looping engine per handle. Players come and go with rollbacks and respawns, so the
+// sounds live out here and just go quiet while their cycle is gone.
+#[derive(Default)]
+pub struct EngineSounds {
+    // (left, right)
+    pub sinks: Vec<(Handle<AudioSink>, Handle<AudioSink>)>,
+    // smoothed speed relative to MatchSettings::move_speed
+    pub speeds: Vec<f32>,
+    // where each cycle was last frame
+    pub positions: Vec<Option<Vec2>>,
+}
+
+const ENGINE_VOLUME: f32 = 0.3;
+
+const MUSIC_FADE_SECONDS: f32 = 1.5;
+
+// way past anything GGRS will roll back
+const SOUND_HISTORY_FRAMES: u32 = 120;
+
+// sounds fade out completely this far from the listener
+const HEARING_DISTANCE: f32 = 12.;
+
+// this far to the side is all the way in one ear
+const PAN_DISTANCE: f32 = 6.;
+
+// even the far side of the arena is a little audible for one-off sounds
+const MIN_EFFECT_FALLOFF: f32 = 0.3;
+
+const ENGINE_SMOOTHING: f32 = 8.;
+
+fn load_sound_effects(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SoundEffects {
+        trail_hit: PannedSound::load(&asset_server, "trail_hit"),
+        border_hit: PannedSound::load(&asset_server, "border_hit"),
+        power_up: PannedSound::load(&asset_server, "power_up"),
+        countdown_beep: PannedSound::load(&asset_server, "countdown_beep"),
+        go: PannedSound::load(&asset_server, "go"),
+        round_win: asset_server.load("sounds/round_win.wav"),
+    });
+}
+
+// Crossfades to the track for the current state whenever it changes
+fn play_music(
+    time: Res<Time>,
+    state: Res<State<GameState>>,
+    options: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    audio: Res<Audio>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut music: ResMut<Music>,
+) {
+    let track = Track::for_state(state.current());
+    if music.playing.last().map(|(playing, _, _)| *playing) != Some(track) {
+        let sink = audio_sinks.get_handle(audio.play_with_settings(
+            asset_server.load(track.path()),
+            PlaybackSettings::LOOP.with_volume(0.),
+        ));
+        music.playing.push((track, sink, 0.));
+    }
+
+    let step = time.delta_seconds() / MUSIC_FADE_SECONDS;
+    let gain = options.music_gain();
+    let newest = music.playing.len() - 1;
+    for (index, (_, sink, fade)) in music.playing.iter_mut().enumerate() {
+        let target = if index == newest { 1. } else { 0. };
+        *fade += (target - *fade).clamp(-step, step);
+        if let Some(sink) = audio_sinks.get(sink) {
+            sink.set_volume(*fade * gain);
+        }
+    }
+    // dropping the last handle to a sink stops it
+    let mut index = 0;
+    music.playing.retain(|(_, _, fade)| {
+        index += 1;
+        index - 1 == newest || *fade > 0.
+    });
+}
+
+fn start_engine_sounds(
+    asset_server: Res<AssetServer>,
+    audio: Res<Audio>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mode: Res<GameMode>,
+    mut engines: ResMut<EngineSounds>,
+) {
+    let engine = PannedSound::load(&asset_server, "engine");
+    let start = |sound: &Handle<AudioSource>| {
+        audio_sinks.get_handle(
+            audio.play_with_settings(sound.clone(), PlaybackSettings::LOOP.with_volume(0.)),
+        )
+    };
+    let num_players = mode.num_players();
+    engines.sinks = (0..num_players)
+        .map(|_| (start(&engine.left), start(&engine.right)))
+        .collect();
+    engines.speeds = vec![0.; num_players];
+    engines.positions = vec![None; num_players];
+}
+
+fn update_listener(
+    local: Res<LocalHandle>,
+    mut listener: ResMut<Listener>,
+    player_query: Query<(&Transform, &Player), Without<Camera>>,
+    camera_query: Query<&Transform, With<Camera>>,
+) {
+    let player = player_query
+        .iter()
+        .find(|(_, player)| player.handle == local.0)
+        .map(|(transform, _)| transform);
+    if let Some(transform) = player.or_else(|| camera_query.get_single().ok()) {
+        listener.0 = transform.translation.truncate();
+    }
+}
+
+// Pitch and loudness follow how fast each cycle is going, and other engines fade and pan
+// with where they are relative to the listener
+fn update_engine_sounds(
+    time: Res<Time>,
+    options: Res<Settings>,
+    settings: Res<MatchSettings>,
+    listener: Res<Listener>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut engines: ResMut<EngineSounds>,
+    player_query: Query<(&Transform, &Player)>,
+) {
+    let frames = time.delta_seconds() * TICKS_PER_SECOND as f32;
+    let blend = 1. - (-ENGINE_SMOOTHING * time.delta_seconds()).exp();
+
+    let engines = &mut *engines;
+    for (handle, (left, right)) in engines.sinks.iter().enumerate() {
+        let current = player_query
+            .iter()
+            .find(|(_, player)| player.handle == handle)
+            .map(|(transform, _)| transform.translation.truncate());
+        // respawns and rollbacks can teleport cycles, so don't read too much into big jumps
+        let speed = match (engines.positions[handle], current) {
+            (Some(last), Some(current)) if frames > 0. => {
+                (last.distance(current) / (settings.move_speed * frames)).min(2.5)
+            }
+            _ => 0.,
+        };
+        engines.positions[handle] = current;
+        engines.speeds[handle] += (speed - engines.speeds[handle]) * blend;
+
+        let speed = engines.speeds[handle];
+        let (volume, gains) = match current {
+            Some(current) => (
+                options.effects_gain()
+                    * ENGINE_VOLUME
+                    * listener.falloff(current)
+                    * (0.5 + 0.25 * speed).min(1.),
+                listener.pan(current),
+            ),
+            None => (0., (0., 0.)),
+        };
+        for (sink, gain) in [(left, gains.0), (right, gains.1)] {
+            if let Some(sink) = audio_sinks.get(sink) {
+                sink.set_volume(volume * gain);
+                sink.set_speed(0.8 + 0.3 * speed);
+            }
+        }
+    }
+}
+
+fn queue_sounds(cues: Res<CueLog>, mut queue: ResMut<SoundQueue>) {
+    queue.frame = cues.frame;
+    for &(frame, cue) in cues.cues.iter() {
+        match cue {
+            Cue::CountdownBeep => queue.play(frame, Sound::CountdownBeep, 0, None),
+            Cue::Go => queue.play(frame, Sound::Go, 0, None),
+            Cue::PowerUp { handle, position } => {
+                queue.play(frame, Sound::PowerUp, handle, Some(position))
+            }
+            Cue::Death { death, position } => {
+                let sound = match death.cause {
+                    DeathCause::Trail => Sound::TrailHit,
+                    DeathCause::Border | DeathCause::Forfeit => Sound::BorderHit,
+                };
+                queue.play(frame, sound, death.victim, Some(position));
+            }
+            Cue::NearMiss(_) => {}
+        }
+    }
+}
+
+// Plays whatever the simulation cued since last frame, plus the round win jingle
+#[allow(clippy::too_many_arguments)]
+fn play_sound_effects(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    options: Res<Settings>,
+    mode: Res<GameMode>,
+    local: Res<LocalHandle>,
+    listener: Res<Listener>,
+    mut queue: ResMut<SoundQueue>,
+    mut round_events: EventReader<RoundEndEvent>,
+) {
+    let volume = options.effects_gain();
+    for (cue, position) in queue.drain() {
+        let (falloff, gains) = match position {
+            Some(position) => (
+                listener.falloff(position).max(MIN_EFFECT_FALLOFF),
+                listener.pan(position),
+            ),
+            None => (1., (1., 1.)),
+        };
+        sounds.get(cue.sound).play(&audio, volume * falloff, gains);
+    }
+    for event in round_events.iter() {
+        if !local.spectating() && event.winner == Some(mode.team_of(local.0)) {
+            audio.play_with_settings(
+                sounds.round_win.clone(),
+                PlaybackSettings::ONCE.with_volume(volume),
+            );
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::{any::TypeId, collections::VecDeque, num::Wrapping};
+
+use bevy::{
+    ecs::reflect::{ReflectComponent, ReflectResource},
+    prelude::*,
+    reflect::{FromType, GetTypeRegistration, TypeRegistry},
+    utils::HashMap,
+};
+use bevy_ggrs::Rollback;
+use ggrs::InputStatus;
+
+use crate::{
+    args::Args,
+    gameplay::{
+        register_rollback_types, rollback_schedule, GameMode, GameplayPlugin, MatchSettings,
+        RollbackTypes,
+    },
+    headless::HeadlessPlugin,
+    input::InputPlugin,
+    networking::NetworkingPlugin,
+    replay::ReplayPlugin,
+    settings::Settings,
+    tuning::Tuning,
+    GameState,
+};
+
+// Put together like main does with --headless, with the match already started like
+// start_local_match would. Nothing ever calls update, a Stepper runs the simulation.
+pub fn match_app(mode: GameMode, settings: MatchSettings) -> App {
+    let mut options = Settings::default();
+    options.profile.bot = true;
+    let mut app = App::new();
+    app.add_state(GameState::InGame)
+        .insert_resource(Args {
+            headless: true,
+            ..default()
+        })
+        .insert_resource(options)
+        .add_plugin(HeadlessPlugin)
+        .insert_resource(Tuning::default())
+        .add_plugin(NetworkingPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(GameplayPlugin)
+        .add_plugin(ReplayPlugin)
+        .insert_resource(mode)
+        .insert_resource(settings);
+    app
+}
+
+// Runs the rollback schedule one frame per call, without GGRS or the wall clock, so tests and
+// benchmarks simulate exactly the same frames every run. Like a synctest, every frame can be
+// followed by rolling back and simulating the last few again, which has to land on the same
+// state or it panics.
+pub struct Stepper {
+    schedule: Schedule,
+    types: TypeRegistry,
+    // how many frames get simulated again after each new one, 0 for none
+    check_distance: usize,
+    // (state it started from, its inputs) for the last check_distance frames, oldest first
+    history: VecDeque<(Snapshot, Vec<(u8, InputStatus)>)>,
+}
+
+impl Stepper {
+    pub fn new(check_distance: usize) -> Self {
+        Self {
+            schedule: rollback_schedule(),
+            types: register_rollback_types(TypeRegistry::default()),
+            check_distance,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn advance(&mut self, world: &mut World, inputs: Vec<(u8, InputStatus)>) {
+        if self.check_distance == 0 {
+            self.run(world, inputs);
+            return;
+        }
+        self.history
+            .push_back((Snapshot::take(world, &self.types), inputs.clone()));
+        if self.history.len() > self.check_distance {
+            self.history.pop_front();
+        }
+        self.run(world, inputs);
+
+        let expected = Snapshot::take(world, &self.types).checksum;
+        self.history[0].0.restore(world, &self.types);
+        for i in 0..self.history.len() {
+            let inputs = self.history[i].1.clone();
+            self.run(world, inputs);
+        }
+        let checksum = Snapshot::take(world, &self.types).checksum;
+        assert_eq!(
+            checksum,
+            expected,
+            "simulating the last {} frames again ended somewhere else",
+            self.history.len()
+        );
+    }
+
+    // with nobody pressing anything, so only bots and replays steer
+    pub fn advance_idle(&mut self, world: &mut World, frames: u32) {
+        let players = world.resource::<GameMode>().num_players();
+        for _ in 0..frames {
+            self.advance(world, vec![(0, InputStatus::Confirmed); players]);
+        }
+    }
+
+    // the same sum GGRS compares in a synctest
+    pub fn checksum(&self, world: &World) -> u64 {
+        Snapshot::take(world, &self.types).checksum
+    }
+
+    pub fn save(&self, world: &World) -> Snapshot {
+        Snapshot::take(world, &self.types)
+    }
+
+    // Frames simulated before this are forgotten, they'd be from some other timeline
+    pub fn load(&mut self, world: &mut World, snapshot: &Snapshot) {
+        snapshot.restore(world, &self.types);
+        self.history.clear();
+    }
+
+    fn run(&mut self, world: &mut World, inputs: Vec<(u8, InputStatus)>) {
+        world.insert_resource(inputs);
+        self.schedule.run_once(world);
+        world.remove_resource::<Vec<(u8, InputStatus)>>();
+    }
+}
+
+impl RollbackTypes for TypeRegistry {
+    fn register<T: GetTypeRegistration + Reflect + Default + Component>(self) -> Self {
+        let mut registry = self.write();
+        registry.register::<T>();
+        let registration = registry.get_mut(TypeId::of::<T>()).unwrap();
+        registration.insert(<ReflectComponent as FromType<T>>::from_type());
+        registration.insert(<ReflectResource as FromType<T>>::from_type());
+        drop(registry);
+        self
+    }
+}
+
+// The registered components of every Rollback entity and the registered resources, saved the
+// way bevy_ggrs saves them
+pub struct Snapshot {
+    // by rollback id
+    entities: Vec<(u32, Vec<Box<dyn Reflect>>)>,
+    resources: Vec<Box<dyn Reflect>>,
+    // summed so the order things were found in doesn't matter
+    checksum: u64,
+}
+
+impl Snapshot {
+    fn take(world: &World, types: &TypeRegistry) -> Self {
+        let types = types.read();
+        let mut checksum = Wrapping(0);
+        let mut save = |value: &dyn Reflect| {
+            if let Some(hash) = value.reflect_hash() {
+                checksum += Wrapping(hash);
+            }
+            value.clone_value()
+        };
+
+        let mut entities = Vec::new();
+        for archetype in world.archetypes().iter() {
+            for &entity in archetype.entities() {
+                let id = match world.get::<Rollback>(entity) {
+                    Some(rollback) => rollback.id(),
+                    None => continue,
+                };
+                let components = types
+                    .iter()
+                    .filter_map(|registration| registration.data::<ReflectComponent>())
+                    .filter_map(|component| component.reflect(world, entity))
+                    .map(&mut save)
+                    .collect();
+                entities.push((id, components));
+            }
+        }
+        let resources = types
+            .iter()
+            .filter_map(|registration| registration.data::<ReflectResource>())
+            .filter_map(|resource| resource.reflect(world))
+            .map(&mut save)
+            .collect();
+        Self {
+            entities,
+            resources,
+            checksum: checksum.0,
+        }
+    }
+
+    fn restore(&self, world: &mut World, types: &TypeRegistry) {
+        let types = types.read();
+        let mut query = world.query::<(Entity, &Rollback)>();
+        let mut existing: HashMap<u32, Entity> = query
+            .iter(world)
+            .map(|(entity, rollback)| (rollback.id(), entity))
+            .collect();
+
+        for (id, components) in &self.entities {
+            let entity = existing
+                .remove(id)
+                .unwrap_or_else(|| world.spawn().insert(Rollback::new(*id)).id());
+            for registration in types.iter() {
+                let reflect_component = match registration.data::<ReflectComponent>() {
+                    Some(reflect_component) => reflect_component,
+                    None => continue,
+                };
+                let saved = components
+                    .iter()
+                    .find(|component| component.type_name() == registration.type_name());
+                match saved {
+                    Some(saved) => reflect_component.apply_or_insert(world, entity, &**saved),
+                    None => reflect_component.remove(world, entity),
+                }
+            }
+        }
+        // spawned since
+        let mut spawned: Vec<_> = existing.into_iter().collect();
+        spawned.sort_unstable_by_key(|(id, _)| *id);
+        for (_, entity) in spawned {
+            world.despawn(entity);
+        }
+
+        for registration in types.iter() {
+            let reflect_resource = match registration.data::<ReflectResource>() {
+                Some(reflect_resource) => reflect_resource,
+                None => continue,
+            };
+            let saved = self
+                .resources
+                .iter()
+                .find(|resource| resource.type_name() == registration.type_name());
+            match saved {
+                Some(saved) => reflect_resource.apply_or_insert(world, &**saved),
+                None => reflect_resource.remove(world),
+            }
+        }
+    }
+}
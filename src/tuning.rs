@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::gameplay::WIDE_TRAIL_SIZE;
+
+// Numbers for balancing the game without a rebuild, read from tuning.json at startup. Sizes,
+// speeds and trail length only seed the match settings the host proposes, and the sizes get
+// overwritten with the agreed ones once a match starts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    pub player_size: f32,
+    // across the arena in the modes that don't bring their own size
+    pub board_size: f32,
+    pub trail_length: u32,
+    pub move_speed: f32,
+    pub turn_speed: f32,
+    // what an input delay setting of auto picks
+    pub input_delay: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            player_size: PLAYER_SIZE,
+            board_size: BOARD_SIZE,
+            trail_length: TRAIL_LENGTH,
+            move_speed: MOVE_SPEED,
+            turn_speed: TURN_SPEED,
+            input_delay: INPUT_DELAY,
+        }
+    }
+}
+
+impl Tuning {
+    // how close a trail can be to a cycle and still touch it
+    pub fn trail_reach(&self) -> f32 {
+        (self.player_size + WIDE_TRAIL_SIZE) / 2.
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        match std::fs::read_to_string(TUNING_FILE) {
+            Ok(json) => Self::parse(&json),
+            Err(_) => default(),
+        }
+    }
+
+    // nothing to read from in a browser, so the file gets baked in at build time
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        Self::parse(include_str!("../tuning.json"))
+    }
+
+    fn parse(json: &str) -> Self {
+        serde_json::from_str(json).unwrap_or_else(|e| {
+            warn!("ignoring {}: {}", TUNING_FILE, e);
+            default()
+        })
+    }
+}
+
+const TUNING_FILE: &str = "tuning.json";
+
+// used for anything the file leaves out
+const PLAYER_SIZE: f32 = 0.75;
+
+const BOARD_SIZE: f32 = 9.0;
+
+const TRAIL_LENGTH: u32 = 80;
+
+const MOVE_SPEED: f32 = 0.03;
+
+const TURN_SPEED: f32 = 0.13;
+
+const INPUT_DELAY: usize = 2;
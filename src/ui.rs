@@ -0,0 +1,3103 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use ggrs::P2PSession;
+use matchbox_socket::PeerId;
+
+use crate::{
+    args::Args,
+    daily::{daily_settings, today, Daily, DAILY_MODE},
+    gameplay::{
+        emit_death_events, emit_round_events, BotDifficulty, BotPlayers, DashEnergy, DeathCause,
+        DeathEvent, Drill, Emote, Emotes, GameMode, Ghost, Handicap, Heading, MatchSettings,
+        MatchStats, Player, Position, RoundEndEvent, RoundState, Scoreboard, SimulationClock,
+        Steering, Trail, Training, COUNTDOWN_FRAMES, DASH_DRAIN, ROYALE_PLAYERS,
+        SURVIVAL_MATCH_FRAMES, TICKS_PER_SECOND,
+    },
+    input::{
+        InputAction, KeyBindings, PauseMenu, PendingEmote, PendingForfeit, RecentInputs,
+        RematchVote, TouchZone, EMOTE_WHEEL_KEY, INPUT_DASH, INPUT_LEFT, INPUT_RIGHT,
+    },
+    leaderboard::Leaderboard,
+    networking::{
+        broadcast_profile, broadcast_proposal, broadcast_ready, custom_room_code, is_host, players,
+        retry_servers, start_bot_match, start_local_match, start_probing, wait_for_players,
+        ConnectionStatus, GgrsConfig, Lobby, LocalHandle, PeerSocket, Proposal, Queue, ServerProbe,
+        ServerStatus,
+    },
+    profile::record_lifetime_stats,
+    profiling::RollbackProfile,
+    rendering::{edge_danger, edge_warning_pulse, move_camera, team_colors, Cosmetics},
+    replay::{export, share, Replay},
+    settings::{CameraMode, PlayerProfile, Settings, SKINS},
+    tuning::Tuning,
+    victory::Ceremony,
+    GameState,
+};
+
+// Menus, the matchmaking screen and the in-game HUD
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Rebinding::default())
+            .add_plugin(FrameTimeDiagnosticsPlugin)
+            .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(setup_main_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::MainMenu)
+                    .with_system(press_menu_buttons)
+                    .with_system(update_leaderboard_text),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::MainMenu).with_system(cleanup_main_menu))
+            .add_system_set(
+                SystemSet::on_enter(GameState::SettingsMenu).with_system(setup_settings_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::SettingsMenu)
+                    .with_system(press_settings_buttons)
+                    .with_system(capture_rebinding)
+                    .with_system(
+                        update_settings_labels
+                            .after(press_settings_buttons)
+                            .after(capture_rebinding),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::SettingsMenu).with_system(cleanup_settings_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::Matchmaking)
+                    .with_system(setup_matchmaking_screen.after(start_probing)),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Matchmaking)
+                    .with_system(update_matchmaking_status)
+                    .with_system(press_search_failed_buttons)
+                    .with_system(press_color_swatches.before(wait_for_players))
+                    .with_system(press_skin_button.before(wait_for_players))
+                    .with_system(press_ready_button.before(wait_for_players))
+                    .with_system(press_lobby_settings_buttons.before(wait_for_players))
+                    .with_system(update_lobby_settings.after(wait_for_players))
+                    .with_system(
+                        press_cancel_button
+                            .after(wait_for_players)
+                            .after(start_bot_match),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Matchmaking).with_system(cleanup_matchmaking_screen),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup_hud))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(update_hud)
+                    .with_system(update_match_timer)
+                    .with_system(update_boost_meter)
+                    .with_system(update_diagnostics)
+                    .with_system(update_feed.after(emit_death_events))
+                    .with_system(
+                        show_banners
+                            .after(emit_death_events)
+                            .after(emit_round_events),
+                    )
+                    .with_system(update_countdown)
+                    .with_system(show_match_stats.after(record_lifetime_stats))
+                    .with_system(press_rematch_button)
+                    .with_system(press_replay_buttons)
+                    .with_system(update_rematch_status)
+                    .with_system(open_emote_wheel)
+                    .with_system(toggle_pause_menu)
+                    .with_system(update_connection_text)
+                    .with_system(update_connection_icon)
+                    .with_system(control_drill)
+                    .with_system(update_drill_text.after(control_drill))
+                    .with_system(update_daily_text)
+                    .with_system(control_training)
+                    .with_system(update_training_text.after(control_training))
+                    .with_system(update_emote_bubbles.after(move_camera))
+                    .with_system(show_edge_warning),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct ScoreText;
+
+#[derive(Component)]
+pub struct FeedText;
+
+#[derive(Component)]
+pub struct CountdownText;
+
+// Frame counters and every cycle's rollback state, in training only
+#[derive(Component)]
+pub struct TrainingText;
+
+// Whether a drill is being recorded or played, local matches only
+#[derive(Component)]
+pub struct DrillText;
+
+// How long we lasted at the daily challenge, only while playing it
+#[derive(Component)]
+pub struct DailyText;
+
+// Handshakes, peers that went quiet or dropped out, and a shaky connection
+#[derive(Component)]
+pub struct ConnectionText;
+
+#[derive(Component)]
+pub struct MatchmakingScreen;
+
+#[derive(Component)]
+pub struct MatchmakingStatus {
+    // seconds since startup when we started searching
+    pub started: f64,
+}
+
+#[derive(Component)]
+pub struct CancelButton;
+
+// Only shown once the lobby is full
+#[derive(Component)]
+pub struct ReadyButton;
+
+// Holds the LobbySettingsButtons, only shown to the host of a full lobby
+#[derive(Component)]
+pub struct LobbySettingsRow;
+
+// What the host picked, for everyone else
+#[derive(Component)]
+pub struct LobbySettingsText;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LobbySettingsButton {
+    Mode,
+    TrailLength,
+    Speed,
+    Rounds,
+    Arena,
+    // by handle, these get a row of their own
+    Handicap(usize),
+}
+
+impl LobbySettingsButton {
+    pub const ALL: [LobbySettingsButton; 5] = [
+        LobbySettingsButton::Mode,
+        LobbySettingsButton::TrailLength,
+        LobbySettingsButton::Speed,
+        LobbySettingsButton::Rounds,
+        LobbySettingsButton::Arena,
+    ];
+
+    // everyone else in casual and ranked rooms queued for this very mode
+    pub fn available(&self, queue: &Queue) -> bool {
+        *self != LobbySettingsButton::Mode || matches!(queue, Queue::Custom(_))
+    }
+
+    pub fn label(&self, proposal: &Proposal, tuning: &Tuning) -> String {
+        let settings = &proposal.settings;
+        match self {
+            LobbySettingsButton::Mode => format!("Mode: {:?}", proposal.mode),
+            LobbySettingsButton::TrailLength if settings.trail_length == 0 => {
+                "Trail: endless".to_string()
+            }
+            LobbySettingsButton::TrailLength => format!(
+                "Trail: {:.1}s",
+                settings.trail_length as f32 / TICKS_PER_SECOND as f32
+            ),
+            LobbySettingsButton::Speed => {
+                format!("Speed: {:.0}%", settings.speed_scale(tuning) * 100.)
+            }
+            LobbySettingsButton::Rounds if settings.rounds == 0 => "Rounds: endless".to_string(),
+            LobbySettingsButton::Rounds => format!("Rounds: {}", settings.rounds),
+            LobbySettingsButton::Arena => format!("Arena: {}", settings.arena_name()),
+            LobbySettingsButton::Handicap(handle) => {
+                let handicap = settings.handicaps[*handle];
+                if handicap == Handicap::default() {
+                    format!("P{}: even", handle + 1)
+                } else {
+                    format!(
+                        "P{}: {:.0}% / {:.0}%",
+                        handle + 1,
+                        handicap.move_speed * 100.,
+                        handicap.trail_length * 100.
+                    )
+                }
+            }
+        }
+    }
+}
+
+// Holds the SearchFailedButtons, only shown once no server answered or nobody joined in time
+#[derive(Component)]
+pub struct SearchFailedRow;
+
+#[derive(Component, Clone, Copy)]
+pub enum SearchFailedButton {
+    Retry,
+    // against bots at whatever difficulty the lobby settings ask for
+    Bots,
+    // against easy bots
+    Practice,
+}
+
+impl SearchFailedButton {
+    pub const ALL: [SearchFailedButton; 3] = [
+        SearchFailedButton::Retry,
+        SearchFailedButton::Bots,
+        SearchFailedButton::Practice,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SearchFailedButton::Retry => "Retry",
+            SearchFailedButton::Bots => "Play vs Bots",
+            SearchFailedButton::Practice => "Practice",
+        }
+    }
+}
+
+// Picks our color in the lobby
+#[derive(Component)]
+pub struct ColorSwatch(pub usize);
+
+// Cycles through skins in the lobby
+#[derive(Component)]
+pub struct SkinButton;
+
+#[derive(Component)]
+pub struct TimerText;
+
+#[derive(Component)]
+pub struct StatsScreen;
+
+#[derive(Component)]
+pub struct RematchText;
+
+#[derive(Component)]
+pub struct RematchButton;
+
+// On the post-game screen, for the whole session so far
+#[derive(Component, Clone, Copy)]
+pub enum ReplayButton {
+    Save,
+    Share,
+}
+
+#[derive(Component)]
+pub struct EmoteWheel;
+
+#[derive(Component)]
+pub struct PauseOverlay;
+
+#[derive(Component, Clone, Copy)]
+pub enum PauseButton {
+    Resume,
+    // the settings menu, on top of the match
+    Settings,
+    // concedes the round, or the match in modes with only one
+    Forfeit,
+}
+
+impl PauseButton {
+    pub const ALL: [PauseButton; 3] = [
+        PauseButton::Resume,
+        PauseButton::Settings,
+        PauseButton::Forfeit,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PauseButton::Resume => "Resume",
+            PauseButton::Settings => "Settings",
+            PauseButton::Forfeit => "Forfeit",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct EmoteButton(pub Emote);
+
+// by handle
+#[derive(Component)]
+pub struct EmoteBubble(pub usize);
+
+// Big announcement under the countdown, hidden again once the timer runs out
+#[derive(Component)]
+pub struct Banner {
+    pub timer: Timer,
+}
+
+#[derive(Component)]
+pub struct BoostMeterFill;
+
+// Green, yellow or red dot next to the boost meter for how the connection is doing, hidden
+// outside online matches
+#[derive(Component)]
+pub struct ConnectionIcon;
+
+// One of the red bars around the screen while our cycle is close to the border
+#[derive(Component)]
+pub struct EdgeWarning;
+
+#[derive(Component)]
+pub struct DiagnosticsText;
+
+#[derive(Component)]
+pub struct MainMenu;
+
+// The top of the online leaderboard, under the main menu buttons
+#[derive(Component)]
+pub struct LeaderboardText;
+
+#[derive(Component)]
+pub struct SettingsMenu;
+
+// The key binding waiting for its next key press, if any
+#[derive(Default)]
+pub struct Rebinding(pub Option<InputAction>);
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SettingsButton {
+    Volume,
+    MusicVolume,
+    EffectsVolume,
+    InputDelay,
+    SpectatorDelay,
+    Colorblind,
+    Theme,
+    MouseSteering,
+    Camera,
+    ScreenShake,
+    Glow,
+    Fullscreen,
+    Resolution,
+    Vsync,
+    FrameLimit,
+    Bind(InputAction),
+    ResetControls,
+    Back,
+}
+
+impl SettingsButton {
+    pub const ALL: [SettingsButton; 20] = [
+        SettingsButton::Volume,
+        SettingsButton::MusicVolume,
+        SettingsButton::EffectsVolume,
+        SettingsButton::InputDelay,
+        SettingsButton::SpectatorDelay,
+        SettingsButton::Colorblind,
+        SettingsButton::Theme,
+        SettingsButton::MouseSteering,
+        SettingsButton::Camera,
+        SettingsButton::ScreenShake,
+        SettingsButton::Glow,
+        SettingsButton::Fullscreen,
+        SettingsButton::Resolution,
+        SettingsButton::Vsync,
+        SettingsButton::FrameLimit,
+        SettingsButton::Bind(InputAction::Left),
+        SettingsButton::Bind(InputAction::Right),
+        SettingsButton::Bind(InputAction::Dash),
+        SettingsButton::ResetControls,
+        SettingsButton::Back,
+    ];
+
+    // the display settings only mean something with a window of our own
+    pub fn available(&self) -> bool {
+        let display = matches!(
+            self,
+            SettingsButton::Fullscreen
+                | SettingsButton::Resolution
+                | SettingsButton::Vsync
+                | SettingsButton::FrameLimit
+        );
+        !display || cfg!(not(target_arch = "wasm32"))
+    }
+
+    pub fn label(
+        &self,
+        options: &Settings,
+        tuning: &Tuning,
+        rebinding: &Rebinding,
+        gamepad: bool,
+    ) -> String {
+        match self {
+            SettingsButton::Volume => format!("Volume: {:.0}%", options.volume * 100.),
+            SettingsButton::MusicVolume => format!("Music: {:.0}%", options.music_volume * 100.),
+            SettingsButton::EffectsVolume => {
+                format!("Effects: {:.0}%", options.effects_volume * 100.)
+            }
+            SettingsButton::InputDelay => match options.input_delay {
+                Some(frames) => format!("Input delay: {} frames", frames),
+                None => format!("Input delay: auto ({})", tuning.input_delay),
+            },
+            SettingsButton::SpectatorDelay if options.spectator_delay == 0 => {
+                "Spectator delay: off".to_string()
+            }
+            SettingsButton::SpectatorDelay => {
+                format!("Spectator delay: {} s", options.spectator_delay)
+            }
+            SettingsButton::Colorblind if options.colorblind => "Colorblind mode: on".to_string(),
+            SettingsButton::Colorblind => "Colorblind mode: off".to_string(),
+            SettingsButton::Theme => format!("Theme: {}", options.theme.name()),
+            SettingsButton::MouseSteering if options.mouse_steering => {
+                "Mouse steering: on".to_string()
+            }
+            SettingsButton::MouseSteering => "Mouse steering: off".to_string(),
+            SettingsButton::Camera => match options.camera_mode {
+                CameraMode::Board => "Camera: whole arena".to_string(),
+                CameraMode::Follow => "Camera: follow".to_string(),
+                CameraMode::FitPlayers => "Camera: fit players".to_string(),
+            },
+            SettingsButton::ScreenShake if options.screen_shake == 0. => {
+                "Screen shake: off".to_string()
+            }
+            SettingsButton::ScreenShake => {
+                format!("Screen shake: {:.0}%", options.screen_shake * 100.)
+            }
+            SettingsButton::Glow if options.glow => "Neon glow: on".to_string(),
+            SettingsButton::Glow => "Neon glow: off".to_string(),
+            SettingsButton::Fullscreen if options.display.fullscreen => {
+                "Fullscreen: on".to_string()
+            }
+            SettingsButton::Fullscreen => "Fullscreen: off".to_string(),
+            SettingsButton::Resolution => {
+                let (width, height) = options.display.resolution;
+                format!("Window size: {}x{}", width, height)
+            }
+            SettingsButton::Vsync if options.display.vsync => "Vsync: on".to_string(),
+            SettingsButton::Vsync => "Vsync: off".to_string(),
+            SettingsButton::FrameLimit => match options.display.frame_limit {
+                Some(limit) => format!("Frame limit: {} fps", limit),
+                None => "Frame limit: off".to_string(),
+            },
+            SettingsButton::Bind(action) if rebinding.0 == Some(*action) => {
+                format!("{:?}: press a key...", action)
+            }
+            SettingsButton::Bind(action) => {
+                let keys = options.key_bindings.keys(*action);
+                let mut keys: Vec<String> = keys.iter().map(|key| format!("{:?}", key)).collect();
+                if gamepad {
+                    keys.push(action.pad_glyph().to_string());
+                }
+                format!("{:?}: {}", action, keys.join(" / "))
+            }
+            SettingsButton::ResetControls => "Reset controls".to_string(),
+            SettingsButton::Back => "Back".to_string(),
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuButton {
+    PlayOnline,
+    // only with a leaderboard to count the results
+    Ranked,
+    // a new room code to hand out to friends
+    CustomRoom,
+    // against bots at whatever difficulty the lobby settings ask for
+    Local,
+    // against easy bots
+    Practice,
+    // a local match that can be paused and stepped through
+    Training,
+    // today's seeded royale, the same for everyone
+    Daily,
+    Settings,
+}
+
+impl MenuButton {
+    pub const ALL: [MenuButton; 8] = [
+        MenuButton::PlayOnline,
+        MenuButton::Ranked,
+        MenuButton::CustomRoom,
+        MenuButton::Local,
+        MenuButton::Practice,
+        MenuButton::Training,
+        MenuButton::Daily,
+        MenuButton::Settings,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MenuButton::PlayOnline => "Play Online",
+            MenuButton::Ranked => "Ranked",
+            MenuButton::CustomRoom => "Custom Room",
+            MenuButton::Local => "Local",
+            MenuButton::Practice => "Practice",
+            MenuButton::Training => "Training",
+            MenuButton::Daily => "Daily Challenge",
+            MenuButton::Settings => "Settings",
+        }
+    }
+}
+
+// how long "GO!" stays up once the countdown is over
+const GO_FRAMES: u32 = 30;
+
+const BANNER_SECONDS: f32 = 2.;
+
+const FEED_LENGTH: usize = 4;
+
+const EMOTE_BUBBLE_WIDTH: f32 = 100.;
+
+// render frames shown in the rollback graph
+const ROLLBACK_HISTORY: usize = 60;
+
+const DRILL_RECORD_KEY: KeyCode = KeyCode::F5;
+
+const TRAINING_PAUSE_KEY: KeyCode = KeyCode::P;
+
+const TRAINING_STEP_KEY: KeyCode = KeyCode::Period;
+
+const DRILL_CLEAR_KEY: KeyCode = KeyCode::F6;
+
+// how long a disconnect or a shaky connection stays on screen
+const CONNECTION_NOTICE_SECS: f64 = 3.;
+
+// the connection icon goes yellow past either of the good limits and red past the fair ones
+const GOOD_PING_MILLIS: u128 = 80;
+
+const FAIR_PING_MILLIS: u128 = 160;
+
+const GOOD_ROLLBACK_FRAMES: u32 = 4;
+
+const FAIR_ROLLBACK_FRAMES: u32 = 8;
+
+const EDGE_WARNING_ALPHA: f32 = 0.4;
+
+// percent of the screen each bar covers
+const EDGE_WARNING_BAR_SIZE: f32 = 4.;
+
+fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // under the rest of the HUD, one bar per side of the screen
+    let bar = Val::Percent(EDGE_WARNING_BAR_SIZE);
+    let full = Val::Percent(100.);
+    for (size, position) in [
+        (
+            Size::new(full, bar),
+            UiRect {
+                top: Val::Px(0.),
+                ..default()
+            },
+        ),
+        (
+            Size::new(full, bar),
+            UiRect {
+                bottom: Val::Px(0.),
+                ..default()
+            },
+        ),
+        (
+            Size::new(bar, full),
+            UiRect {
+                left: Val::Px(0.),
+                ..default()
+            },
+        ),
+        (
+            Size::new(bar, full),
+            UiRect {
+                right: Val::Px(0.),
+                ..default()
+            },
+        ),
+    ] {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size,
+                    position_type: PositionType::Absolute,
+                    position,
+                    ..default()
+                },
+                color: Color::NONE.into(),
+                ..default()
+            })
+            .insert(EdgeWarning);
+    }
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                    font_size: 32.,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.),
+                    left: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(ScoreText);
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                    font_size: 32.,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.),
+                    right: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(TimerText);
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                    font_size: 16.,
+                    color: Color::YELLOW,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(50.),
+                    left: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(DiagnosticsText);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(20.),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 48.,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(Banner {
+                    timer: Timer::from_seconds(BANNER_SECONDS, false),
+                });
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(60.),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 24.,
+                        color: Color::YELLOW,
+                    },
+                ))
+                .insert(ConnectionText);
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(40.),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 20.,
+                        color: Color::ORANGE,
+                    },
+                ))
+                .insert(DrillText);
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(70.),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 20.,
+                        color: Color::GOLD,
+                    },
+                ))
+                .insert(DailyText);
+        });
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                    font_size: 16.,
+                    color: Color::CYAN,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(50.),
+                    right: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(TrainingText);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(200.), Val::Px(16.)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(10.),
+                    right: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            },
+            color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                        ..default()
+                    },
+                    color: Color::CYAN.into(),
+                    ..default()
+                })
+                .insert(BoostMeterFill);
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(16.), Val::Px(16.)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(10.),
+                    right: Val::Px(220.),
+                    ..default()
+                },
+                ..default()
+            },
+            color: Color::NONE.into(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(ConnectionIcon);
+
+    for action in InputAction::ALL {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                color: Color::rgba(1., 1., 1., 0.08).into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(TouchZone(action))
+            .with_children(|parent| {
+                parent.spawn_bundle(TextBundle::from_section(
+                    action.touch_label(),
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 40.,
+                        color: Color::rgba(1., 1., 1., 0.4),
+                    },
+                ));
+            });
+    }
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                    font_size: 20.,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(10.),
+                    left: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(FeedText);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 160.,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(CountdownText);
+        });
+
+    for handle in 0..ROYALE_PLAYERS {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(EMOTE_BUBBLE_WIDTH), Val::Auto),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::all(Val::Px(4.)),
+                    ..default()
+                },
+                color: Color::rgba(1., 1., 1., 0.85).into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(EmoteBubble(handle))
+            .with_children(|parent| {
+                parent.spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                        font_size: 18.,
+                        color: Color::BLACK,
+                    },
+                ));
+            });
+    }
+}
+
+fn update_hud(
+    mode: Res<GameMode>,
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    bots: Res<BotPlayers>,
+    ghost: Res<Ghost>,
+    mut text_query: Query<&mut Text, With<ScoreText>>,
+) {
+    let scores = scoreboard
+        .scores
+        .iter()
+        .map(|score| match *mode {
+            // survival scores are frames alive, hill scores are frames on the hill
+            GameMode::Survival | GameMode::Hill => {
+                format!("{:.1}s", *score as f32 / TICKS_PER_SECOND as f32)
+            }
+            _ => score.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" - ");
+    let overtime = if round.sudden_death() {
+        "    SUDDEN DEATH"
+    } else {
+        ""
+    };
+    let ghost = match (&ghost.replay, bots.0.is_empty()) {
+        (Some(replay), false) => format!("    Ghost: {:.1}s", replay.survival_seconds()),
+        _ => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!(
+            "Round {}    {}{}{}",
+            scoreboard.round, scores, overtime, ghost
+        );
+    }
+}
+
+fn update_match_timer(
+    mode: Res<GameMode>,
+    round: Res<RoundState>,
+    mut text_query: Query<&mut Text, With<TimerText>>,
+) {
+    let frames = match *mode {
+        // survival plays against the clock, so count down instead
+        GameMode::Survival => {
+            SURVIVAL_MATCH_FRAMES.saturating_sub(round.frame.max(COUNTDOWN_FRAMES))
+        }
+        _ => round.frame.saturating_sub(COUNTDOWN_FRAMES),
+    };
+    let seconds = frames / TICKS_PER_SECOND;
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("{}:{:02}", seconds / 60, seconds % 60);
+    }
+}
+
+fn update_boost_meter(
+    local: Res<LocalHandle>,
+    player_query: Query<(&Player, &DashEnergy)>,
+    mut fill_query: Query<(&mut Style, &mut UiColor), With<BoostMeterFill>>,
+) {
+    // an empty meter while we're dead
+    let dash = player_query
+        .iter()
+        .find(|(player, _)| player.handle == local.0)
+        .map(|(_, dash)| dash);
+    let fraction = dash.map(DashEnergy::fraction).unwrap_or(0.);
+    let empty = dash.map_or(true, |dash| dash.energy < DASH_DRAIN);
+    for (mut style, mut color) in fill_query.iter_mut() {
+        style.size.width = Val::Percent(fraction * 100.);
+        // red while there isn't enough left to dash
+        *color = if empty { Color::RED } else { Color::CYAN }.into();
+    }
+}
+
+// F3 toggles it
+#[allow(clippy::too_many_arguments)]
+fn update_diagnostics(
+    keys: Res<Input<KeyCode>>,
+    diagnostics: Res<Diagnostics>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    recent_inputs: Res<RecentInputs>,
+    mut profile: ResMut<RollbackProfile>,
+    mut visible: Local<bool>,
+    // (last frame we saw, re-simulated frames for the last few render frames)
+    mut rollbacks: Local<(i32, VecDeque<u32>)>,
+    entity_query: Query<Entity>,
+    trail_query: Query<&Trail>,
+    mut text_query: Query<&mut Text, With<DiagnosticsText>>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        *visible = !*visible;
+    }
+    let RollbackProfile {
+        elapsed, frames, ..
+    } = std::mem::take(&mut *profile);
+
+    // anything simulated beyond how far the session moved forward was a rollback
+    let (current_frame, confirmed_frame) = match &session {
+        Some(session) => (session.current_frame(), session.confirmed_frame()),
+        None => (0, 0),
+    };
+    let advanced = (current_frame - rollbacks.0).max(0) as u32;
+    let resimulated = frames.saturating_sub(advanced);
+    rollbacks.0 = current_frame;
+    rollbacks.1.push_back(resimulated);
+    if rollbacks.1.len() > ROLLBACK_HISTORY {
+        rollbacks.1.pop_front();
+    }
+
+    let overlay = if *visible {
+        let fps = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.average())
+            .unwrap_or(0.);
+        // measured in seconds
+        let frame_time = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+            .and_then(|frame_time| frame_time.average())
+            .unwrap_or(0.);
+        let mut overlay = format!(
+            "FPS: {:.0} ({:.1} ms)\nEntities: {}\nTrail points: {}\nRollback: {:.2} ms over {} frames",
+            fps,
+            frame_time * 1000.,
+            entity_query.iter().count(),
+            trail_query
+                .iter()
+                .map(|trail| trail.points.len())
+                .sum::<usize>(),
+            elapsed.as_secs_f64() * 1000.,
+            frames
+        );
+        if session.is_some() {
+            overlay += &format!(
+                "\nFrame: {} (confirmed {}, {} behind)\nResimulated: {}\n{}",
+                current_frame,
+                confirmed_frame,
+                current_frame - confirmed_frame,
+                resimulated,
+                rollback_graph(&rollbacks.1)
+            );
+        }
+        // when each change to our input was read and the frame it gets simulated on
+        overlay += &format!("\nInput delay: {} frames", recent_inputs.delay);
+        for (frame, input) in recent_inputs.changes.iter().rev() {
+            overlay += &format!(
+                "\n  {} -> {}: {}",
+                frame,
+                *frame as usize + recent_inputs.delay,
+                describe_input(*input)
+            );
+        }
+        overlay
+    } else {
+        String::new()
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != overlay {
+            text.sections[0].value = overlay.clone();
+        }
+    }
+}
+
+// One bar per render frame, taller for deeper rollbacks
+fn rollback_graph(history: &VecDeque<u32>) -> String {
+    const BARS: [char; 8] = [
+        '\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}',
+        '\u{2588}',
+    ];
+    history
+        .iter()
+        .map(|frames| match *frames {
+            0 => ' ',
+            frames => BARS[(frames as usize - 1).min(BARS.len() - 1)],
+        })
+        .collect()
+}
+
+fn describe_input(input: u8) -> String {
+    let held: Vec<&str> = [
+        (INPUT_LEFT, "left"),
+        (INPUT_RIGHT, "right"),
+        (INPUT_DASH, "dash"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| input & bit != 0)
+    .map(|(_, name)| name)
+    .collect();
+    if held.is_empty() {
+        "released".to_string()
+    } else {
+        held.join(" + ")
+    }
+}
+
+fn update_countdown(
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    mut text_query: Query<&mut Text, With<CountdownText>>,
+) {
+    let countdown = if scoreboard.match_over {
+        match scoreboard.leader() {
+            Some(team) => format!("Player {} wins!", team + 1),
+            None => String::new(),
+        }
+    } else if round.frame == 0 {
+        // the round hasn't been set up yet
+        String::new()
+    } else if round.in_countdown() {
+        ((COUNTDOWN_FRAMES - round.frame) / TICKS_PER_SECOND + 1).to_string()
+    } else if round.frame <= COUNTDOWN_FRAMES + GO_FRAMES {
+        "GO!".to_string()
+    } else {
+        String::new()
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = countdown.clone();
+    }
+}
+
+// shorter version for the stats screen, where it's clear who it's about
+fn death_summary(death: &DeathEvent, cosmetics: &Cosmetics) -> String {
+    match death.cause {
+        DeathCause::Border => "Hit the wall".to_string(),
+        DeathCause::Trail if death.is_suicide() => "Own trail".to_string(),
+        DeathCause::Trail => format!("Cut off by {}", cosmetics.player_name(death.killer)),
+        DeathCause::Forfeit => "Forfeited".to_string(),
+    }
+}
+
+// Table of everyone's stats once the match is over, under the winner announcement
+#[allow(clippy::too_many_arguments)]
+fn show_match_stats(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mode: Res<GameMode>,
+    cosmetics: Res<Cosmetics>,
+    scoreboard: Res<Scoreboard>,
+    stats: Res<MatchStats>,
+    options: Res<Settings>,
+    ceremony: Res<Ceremony>,
+    screen_query: Query<Entity, With<StatsScreen>>,
+) {
+    if !scoreboard.match_over {
+        for entity in screen_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    // the final round's killcam and the victory ceremony go first
+    if !screen_query.is_empty() || !ceremony.finished {
+        return;
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    let mut rows = vec![(
+        Color::WHITE,
+        [
+            String::new(),
+            "Distance".to_string(),
+            "Dashing".to_string(),
+            "Trails".to_string(),
+            "Near misses".to_string(),
+            "Kills".to_string(),
+            "Cause of death".to_string(),
+        ],
+    )];
+    for handle in 0..mode.num_players() {
+        let player = stats.players.get(handle).copied().unwrap_or_default();
+        let (color, _) = cosmetics.team_colors(mode.team_of(handle));
+        rows.push((
+            color,
+            [
+                cosmetics.player_name(handle).to_string(),
+                format!("{:.1}", player.distance),
+                format!(
+                    "{:.1}s",
+                    player.dash_frames as f32 / TICKS_PER_SECOND as f32
+                ),
+                player.trails_laid.to_string(),
+                player.near_misses.to_string(),
+                player.kills.to_string(),
+                match player.deaths {
+                    0 => "Survived".to_string(),
+                    _ => death_summary(&player.last_death, &cosmetics),
+                },
+            ],
+        ));
+    }
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(62.),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(StatsScreen)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::ColumnReverse,
+                        padding: UiRect::all(Val::Px(12.)),
+                        ..default()
+                    },
+                    color: Color::rgba(0., 0., 0., 0.6).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (color, cells) in rows {
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                color: Color::NONE.into(),
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                for (column, cell) in cells.into_iter().enumerate() {
+                                    // the cause of death needs room for a name
+                                    let width = if column == 6 { 220. } else { 110. };
+                                    parent.spawn_bundle(
+                                        TextBundle::from_section(
+                                            cell,
+                                            TextStyle {
+                                                font: font.clone(),
+                                                font_size: 18.,
+                                                color,
+                                            },
+                                        )
+                                        .with_style(
+                                            Style {
+                                                size: Size::new(Val::Px(width), Val::Auto),
+                                                margin: UiRect::all(Val::Px(2.)),
+                                                ..default()
+                                            },
+                                        ),
+                                    );
+                                }
+                            });
+                    }
+                });
+            // against bots only leaves it as it was
+            if let Some(change) = options.ratings.last_change {
+                parent.spawn_bundle(
+                    TextBundle::from_section(
+                        format!("Rating {:.0} ({:+.0})", options.ratings.own, change),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.,
+                            color: Color::GRAY,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::all(Val::Px(8.)),
+                        ..default()
+                    }),
+                );
+            }
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::all(Val::Px(8.)),
+                        ..default()
+                    }),
+                )
+                .insert(RematchText);
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(200.), Val::Px(40.)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .insert(RematchButton)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        "Rematch",
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.,
+                            color: Color::WHITE,
+                        },
+                    ));
+                });
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        margin: UiRect {
+                            top: Val::Px(8.),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (button, label) in [
+                        (ReplayButton::Save, "Save replay"),
+                        (ReplayButton::Share, "Share link"),
+                    ] {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(160.), Val::Px(36.)),
+                                    margin: UiRect::all(Val::Px(4.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                color: Color::rgb(0.15, 0.15, 0.15).into(),
+                                ..default()
+                            })
+                            .insert(button)
+                            .with_children(|parent| {
+                                parent.spawn_bundle(TextBundle::from_section(
+                                    label,
+                                    TextStyle {
+                                        font: font.clone(),
+                                        font_size: 18.,
+                                        color: Color::WHITE,
+                                    },
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+fn press_replay_buttons(
+    replay: Res<Replay>,
+    mode: Res<GameMode>,
+    steering: Res<Steering>,
+    tuning: Res<Tuning>,
+    settings: Res<MatchSettings>,
+    cosmetics: Res<Cosmetics>,
+    button_query: Query<(&Interaction, &ReplayButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let log = match replay.to_log(*mode, *steering, &tuning, *settings, &cosmetics) {
+            Some(log) => log,
+            None => {
+                warn!("matches with a drill can't be replayed");
+                continue;
+            }
+        };
+        match button {
+            ReplayButton::Save => export(&log),
+            ReplayButton::Share => share(&log),
+        }
+    }
+}
+
+// Enter or the rematch button votes for a rematch, the vote is dropped once the next match
+// starts
+fn press_rematch_button(
+    keys: Res<Input<KeyCode>>,
+    scoreboard: Res<Scoreboard>,
+    mut vote: ResMut<RematchVote>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<RematchButton>)>,
+) {
+    if !scoreboard.match_over {
+        vote.0 = false;
+        return;
+    }
+    if keys.just_pressed(KeyCode::Return)
+        || button_query
+            .iter()
+            .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        vote.0 = true;
+    }
+}
+
+fn update_rematch_status(
+    local: Res<LocalHandle>,
+    bots: Res<BotPlayers>,
+    cosmetics: Res<Cosmetics>,
+    scoreboard: Res<Scoreboard>,
+    round: Res<RoundState>,
+    mut text_query: Query<&mut Text, With<RematchText>>,
+) {
+    let mut status: Vec<String> = scoreboard
+        .rematch_votes
+        .iter()
+        .enumerate()
+        .map(|(handle, voted)| {
+            let name = if handle == local.0 && !bots.0.contains(&handle) {
+                "You"
+            } else {
+                cosmetics.player_name(handle)
+            };
+            let vote = if *voted { "ready" } else { "waiting\u{2026}" };
+            format!("{}: {}", name, vote)
+        })
+        .collect();
+    if scoreboard.rematch_deadline != 0 {
+        let seconds = scoreboard.rematch_deadline.saturating_sub(round.frame) / TICKS_PER_SECOND;
+        status.push(format!("Rematch in {}s", seconds));
+    }
+    let status = status.join("    ");
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+        }
+    }
+}
+
+// Hold the wheel key, then click an emote or press its number
+fn open_emote_wheel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keys: Res<Input<KeyCode>>,
+    mut pending: ResMut<PendingEmote>,
+    wheel_query: Query<Entity, With<EmoteWheel>>,
+    button_query: Query<(&Interaction, &EmoteButton), Changed<Interaction>>,
+) {
+    if !keys.pressed(EMOTE_WHEEL_KEY) {
+        for entity in wheel_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    if wheel_query.is_empty() {
+        spawn_emote_wheel(&mut commands, &asset_server);
+        return;
+    }
+
+    let clicked = button_query
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Clicked)
+        .map(|(_, button)| button.0);
+    let pressed = Emote::ALL
+        .into_iter()
+        .find(|emote| keys.just_pressed(emote.key()));
+    if let Some(emote) = clicked.or(pressed) {
+        pending.0 = Some(emote);
+    }
+}
+
+fn spawn_emote_wheel(commands: &mut Commands, asset_server: &AssetServer) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(EmoteWheel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(300.), Val::Px(180.)),
+                        ..default()
+                    },
+                    color: Color::rgba(0., 0., 0., 0.5).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    // (left, bottom) of each slot, clockwise from the top
+                    let slots = [(100., 130.), (200., 70.), (100., 10.), (0., 70.)];
+                    for (emote, (left, bottom)) in Emote::ALL.into_iter().zip(slots) {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(100.), Val::Px(40.)),
+                                    position_type: PositionType::Absolute,
+                                    position: UiRect {
+                                        left: Val::Px(left),
+                                        bottom: Val::Px(bottom),
+                                        ..default()
+                                    },
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                color: Color::rgb(0.15, 0.15, 0.15).into(),
+                                ..default()
+                            })
+                            .insert(EmoteButton(emote))
+                            .with_children(|parent| {
+                                parent.spawn_bundle(TextBundle::from_section(
+                                    format!("{} {}", emote as u8 + 1, emote.text()),
+                                    TextStyle {
+                                        font: font.clone(),
+                                        font_size: 18.,
+                                        color: Color::WHITE,
+                                    },
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+fn control_training(keys: Res<Input<KeyCode>>, mut training: ResMut<Training>) {
+    if !training.enabled {
+        return;
+    }
+    if keys.just_pressed(TRAINING_PAUSE_KEY) {
+        training.paused = !training.paused;
+        training.steps = 0;
+    }
+    if training.paused && keys.just_pressed(TRAINING_STEP_KEY) {
+        training.steps += 1;
+    }
+}
+
+fn update_training_text(
+    training: Res<Training>,
+    settings: Res<MatchSettings>,
+    steering: Res<Steering>,
+    clock: Res<SimulationClock>,
+    round: Res<RoundState>,
+    player_query: Query<(&Position, &Heading, &Player, &DashEnergy)>,
+    mut text_query: Query<&mut Text, With<TrainingText>>,
+) {
+    let status = if training.enabled {
+        let mut status = format!(
+            "Training: {}, P to {}, . to step\nFrame {}, round frame {}",
+            if training.paused { "paused" } else { "running" },
+            if training.paused { "resume" } else { "pause" },
+            clock.frame,
+            round.frame
+        );
+        if round.overtime_frame != 0 {
+            status += &format!(", overtime at {}", round.overtime_frame);
+        }
+        // distance covered per radian of turning, grid steering turns on the spot
+        if *steering == Steering::Analog {
+            status += &format!(
+                "\nTurn radius: {:.3}",
+                settings.move_speed / settings.turn_speed
+            );
+        }
+        let mut players: Vec<_> = player_query.iter().collect();
+        players.sort_by_key(|(_, _, player, _)| player.handle);
+        for (position, heading, player, dash) in players {
+            status += &format!(
+                "\nP{}: ({:.3}, {:.3}) heading {:.1}\u{b0} dash {}",
+                player.handle + 1,
+                position.0.x,
+                position.0.y,
+                heading.0.to_degrees(),
+                dash.energy
+            );
+        }
+        status
+    } else {
+        String::new()
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+        }
+    }
+}
+
+// Drills are recorded from the simulation, which only local matches can be trusted to
+// leave alone
+fn control_drill(
+    keys: Res<Input<KeyCode>>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    mut drill: ResMut<Drill>,
+) {
+    if session.is_some() {
+        return;
+    }
+    if keys.just_pressed(DRILL_RECORD_KEY) {
+        if drill.recording.is_some() {
+            drill.stop_recording();
+        } else {
+            drill.recording = Some(default());
+        }
+    }
+    if keys.just_pressed(DRILL_CLEAR_KEY) {
+        *drill = default();
+    }
+}
+
+fn update_drill_text(drill: Res<Drill>, mut text_query: Query<&mut Text, With<DrillText>>) {
+    if !drill.is_changed() {
+        return;
+    }
+    let status = match (&drill.recording, &drill.snippet) {
+        (Some(recording), _) => format!(
+            "Recording drill\u{2026} {:.1}s, F5 to stop",
+            recording.seconds()
+        ),
+        (None, Some(snippet)) => format!(
+            "Drill: {:.1}s, F5 to record another, F6 to clear",
+            snippet.seconds()
+        ),
+        (None, None) => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = status.clone();
+    }
+}
+
+fn update_daily_text(
+    daily: Res<Daily>,
+    options: Res<Settings>,
+    mut text_query: Query<&mut Text, With<DailyText>>,
+) {
+    if !daily.is_changed() && !options.is_changed() {
+        return;
+    }
+    let status = match (daily.day, daily.survived) {
+        (None, _) => String::new(),
+        (Some(day), None) => match options.daily.best_on(day) {
+            Some(best) => format!("Daily challenge #{}, best today {:.1}s", day, best),
+            None => format!("Daily challenge #{}, last as long as you can", day),
+        },
+        (Some(day), Some(survived)) => format!(
+            "Daily challenge #{}: survived {:.1}s, best today {:.1}s",
+            day,
+            survived,
+            options.daily.best_on(day).unwrap_or(survived)
+        ),
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = status.clone();
+    }
+}
+
+// The worst ping to anyone else and how far behind the confirmed frame we're predicting,
+// whichever is worse picks the color
+fn update_connection_icon(
+    mode: Res<GameMode>,
+    local: Res<LocalHandle>,
+    session: Option<Res<P2PSession<GgrsConfig>>>,
+    mut icon_query: Query<(&mut UiColor, &mut Visibility), With<ConnectionIcon>>,
+) {
+    let session = match session {
+        Some(session) => session,
+        None => {
+            for (_, mut visibility) in icon_query.iter_mut() {
+                visibility.is_visible = false;
+            }
+            return;
+        }
+    };
+    let ping = (0..mode.num_players())
+        .filter(|handle| *handle != local.0)
+        .filter_map(|handle| session.network_stats(handle).ok())
+        .map(|stats| stats.ping)
+        .max()
+        .unwrap_or(0);
+    let depth = (session.current_frame() - session.confirmed_frame()).max(0) as u32;
+    let color = if ping <= GOOD_PING_MILLIS && depth <= GOOD_ROLLBACK_FRAMES {
+        Color::GREEN
+    } else if ping <= FAIR_PING_MILLIS && depth <= FAIR_ROLLBACK_FRAMES {
+        Color::YELLOW
+    } else {
+        Color::RED
+    };
+    for (mut ui_color, mut visibility) in icon_query.iter_mut() {
+        visibility.is_visible = true;
+        ui_color.0 = color;
+    }
+}
+
+fn update_connection_text(
+    time: Res<Time>,
+    connection: Res<ConnectionStatus>,
+    cosmetics: Res<Cosmetics>,
+    mut text_query: Query<&mut Text, With<ConnectionText>>,
+) {
+    let name = |peer: &PeerId| {
+        connection
+            .handle(peer)
+            .map_or("A player", |handle| cosmetics.player_name(handle))
+    };
+    let now = time.seconds_since_startup();
+    let spectating = connection.watching.map(|host| {
+        if connection
+            .disconnected
+            .iter()
+            .any(|(peer, _)| *peer == host)
+        {
+            format!("{} left, there's nothing more to watch", name(&host))
+        } else {
+            "Spectating until the next match".to_string()
+        }
+    });
+    let synchronizing = connection
+        .synchronizing
+        .iter()
+        .map(|(peer, (count, total))| {
+            format!(
+                "Synchronizing with {}\u{2026} {}/{}",
+                name(peer),
+                count,
+                total
+            )
+        });
+    let interrupted = connection.interrupted.iter().map(|(peer, deadline)| {
+        format!(
+            "Waiting for {} to reconnect\u{2026} {:.0}s",
+            name(peer),
+            (deadline - now).max(0.)
+        )
+    });
+    // their cycles carry on straight ahead, which is worth knowing about
+    let disconnected = connection
+        .disconnected
+        .iter()
+        .filter(|(_, at)| now - at < CONNECTION_NOTICE_SECS)
+        .map(|(peer, _)| format!("{} disconnected", name(peer)));
+    let unstable = connection
+        .last_wait
+        .filter(|at| now - at < CONNECTION_NOTICE_SECS)
+        .map(|_| "Connection unstable".to_string());
+    let status = spectating
+        .into_iter()
+        .chain(synchronizing)
+        .chain(interrupted)
+        .chain(disconnected)
+        .chain(unstable)
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn toggle_pause_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keys: Res<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut pause: ResMut<PauseMenu>,
+    mut forfeit: ResMut<PendingForfeit>,
+    overlay_query: Query<Entity, With<PauseOverlay>>,
+    button_query: Query<(&Interaction, &PauseButton), Changed<Interaction>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        pause.0 = !pause.0;
+    }
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            PauseButton::Resume => pause.0 = false,
+            PauseButton::Forfeit => {
+                forfeit.0 = true;
+                pause.0 = false;
+            }
+            PauseButton::Settings => {
+                // the victory ceremony can get in first on the frame the match is won, and
+                // that's no time for settings
+                if state.push(GameState::SettingsMenu).is_err() {
+                    continue;
+                }
+                // stays paused, so the overlay comes back once the settings are closed
+                for entity in overlay_query.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                return;
+            }
+        }
+    }
+
+    if !pause.0 {
+        for entity in overlay_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    } else if overlay_query.is_empty() {
+        spawn_pause_menu(&mut commands, &asset_server);
+    }
+}
+
+fn spawn_pause_menu(commands: &mut Commands, asset_server: &AssetServer) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..default()
+        })
+        .insert(PauseOverlay)
+        .with_children(|parent| {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Paused",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 48.,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..default()
+                }),
+            );
+            for button in PauseButton::ALL {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(240.), Val::Px(40.)),
+                            margin: UiRect::all(Val::Px(4.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        color: Color::rgb(0.15, 0.15, 0.15).into(),
+                        ..default()
+                    })
+                    .insert(button)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            button.label(),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 24.,
+                                color: Color::WHITE,
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+// Keeps each emote bubble floating just above its cycle
+fn update_emote_bubbles(
+    tuning: Res<Tuning>,
+    emotes: Res<Emotes>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    player_query: Query<(&Transform, &Player)>,
+    mut bubble_query: Query<(&EmoteBubble, &mut Style, &mut Visibility, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    let (camera, camera_transform) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    for (bubble, mut style, mut visibility, children) in bubble_query.iter_mut() {
+        let emote = emotes
+            .shown
+            .get(bubble.0)
+            .filter(|shown| shown.frames_left > 0)
+            .and_then(|shown| Emote::from_input(shown.emote));
+        let screen_position = player_query
+            .iter()
+            .find(|(_, player)| player.handle == bubble.0)
+            .and_then(|(transform, _)| {
+                let above = transform.translation + Vec3::Y * tuning.player_size;
+                camera.world_to_viewport(camera_transform, above)
+            });
+        let text = match (emote, screen_position) {
+            (Some(emote), Some(position)) => {
+                style.position = UiRect {
+                    left: Val::Px(position.x - EMOTE_BUBBLE_WIDTH / 2.),
+                    bottom: Val::Px(position.y),
+                    ..default()
+                };
+                emote.text()
+            }
+            _ => "",
+        };
+        visibility.is_visible = !text.is_empty();
+        for child in children.iter() {
+            if let Ok(mut child_text) = text_query.get_mut(*child) {
+                if child_text.sections[0].value != text {
+                    child_text.sections[0].value = text.to_string();
+                }
+            }
+        }
+    }
+}
+
+fn describe_death(death: &DeathEvent, cosmetics: &Cosmetics) -> String {
+    let victim = cosmetics.player_name(death.victim);
+    match death.cause {
+        DeathCause::Border => format!("{} hit the wall", victim),
+        DeathCause::Trail if death.is_suicide() => {
+            format!("{} crashed into their own trail", victim)
+        }
+        DeathCause::Trail => format!(
+            "{} was cut off by {}",
+            victim,
+            cosmetics.player_name(death.killer)
+        ),
+        DeathCause::Forfeit => format!("{} forfeited", victim),
+    }
+}
+
+fn update_feed(
+    mut death_events: EventReader<DeathEvent>,
+    cosmetics: Res<Cosmetics>,
+    mut feed: Local<Vec<String>>,
+    mut text_query: Query<&mut Text, With<FeedText>>,
+) {
+    let mut changed = false;
+    for death in death_events.iter() {
+        feed.push(describe_death(death, &cosmetics));
+        changed = true;
+    }
+    if !changed {
+        return;
+    }
+
+    let skip = feed.len().saturating_sub(FEED_LENGTH);
+    feed.drain(..skip);
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = feed.join("\n");
+    }
+}
+
+fn show_banners(
+    mode: Res<GameMode>,
+    cosmetics: Res<Cosmetics>,
+    time: Res<Time>,
+    mut death_events: EventReader<DeathEvent>,
+    mut round_events: EventReader<RoundEndEvent>,
+    mut banner_query: Query<(&mut Text, &mut Banner)>,
+) {
+    let mut announcement = None;
+    for death in death_events.iter() {
+        let team = mode.team_of(death.victim);
+        announcement = Some(format!("{} eliminated!", cosmetics.team_name(team)));
+    }
+
+    for event in round_events.iter() {
+        announcement = Some(match event.winner {
+            Some(winner) => format!("{} wins the round!", cosmetics.team_name(winner)),
+            None => "Draw!".to_string(),
+        });
+    }
+
+    for (mut text, mut banner) in banner_query.iter_mut() {
+        if let Some(announcement) = &announcement {
+            text.sections[0].value = announcement.clone();
+            banner.timer.reset();
+        } else if banner.timer.tick(time.delta()).just_finished() {
+            text.sections[0].value.clear();
+        }
+    }
+}
+
+fn show_edge_warning(
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    round: Res<RoundState>,
+    local: Res<LocalHandle>,
+    player_query: Query<(&Transform, &Player)>,
+    mut warning_query: Query<&mut UiColor, With<EdgeWarning>>,
+) {
+    let danger = player_query
+        .iter()
+        .find(|(_, player)| player.handle == local.0)
+        .map_or(0., |(transform, _)| {
+            edge_danger(&mode, &round, &tuning, transform.translation.truncate())
+        });
+    let alpha = danger * (0.5 + 0.5 * edge_warning_pulse(&time)) * EDGE_WARNING_ALPHA;
+    for mut color in warning_query.iter_mut() {
+        *color = Color::rgba(1., 0., 0., alpha).into();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup_matchmaking_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+    args: Res<Args>,
+    mode: Res<GameMode>,
+    queue: Res<Queue>,
+    lobby: Res<Lobby>,
+    options: Res<Settings>,
+) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    let text_style = |font_size| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..default()
+        })
+        .insert(MatchmakingScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                "Searching for opponent...",
+                text_style(40.),
+            ));
+            parent.spawn_bundle(TextBundle::from_section(queue.label(), text_style(24.)));
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section("", text_style(24.)).with_style(Style {
+                        margin: UiRect::all(Val::Px(12.)),
+                        ..default()
+                    }),
+                )
+                .insert(MatchmakingStatus {
+                    started: time.seconds_since_startup(),
+                });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(200.), Val::Px(48.)),
+                        margin: UiRect::all(Val::Px(8.)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        display: Display::None,
+                        ..default()
+                    },
+                    color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .insert(ReadyButton)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        ready_label(lobby.ready),
+                        text_style(24.),
+                    ));
+                });
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .insert(LobbySettingsRow)
+                .with_children(|parent| {
+                    let buttons = LobbySettingsButton::ALL
+                        .into_iter()
+                        .filter(|button| button.available(&queue));
+                    for button in buttons {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(180.), Val::Px(36.)),
+                                    margin: UiRect::all(Val::Px(4.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                color: Color::rgb(0.15, 0.15, 0.15).into(),
+                                ..default()
+                            })
+                            .insert(button)
+                            .with_children(|parent| {
+                                parent.spawn_bundle(TextBundle::from_section("", text_style(18.)));
+                            });
+                    }
+                });
+            // speed / trail multipliers, one button per player
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .insert(LobbySettingsRow)
+                .with_children(|parent| {
+                    for handle in 0..queue.num_humans(*mode, &args) {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(150.), Val::Px(30.)),
+                                    margin: UiRect::all(Val::Px(4.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                color: Color::rgb(0.15, 0.15, 0.15).into(),
+                                ..default()
+                            })
+                            .insert(LobbySettingsButton::Handicap(handle))
+                            .with_children(|parent| {
+                                parent.spawn_bundle(TextBundle::from_section("", text_style(16.)));
+                            });
+                    }
+                });
+            parent
+                .spawn_bundle(TextBundle::from_section("", text_style(18.)))
+                .insert(LobbySettingsText);
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .insert(SearchFailedRow)
+                .with_children(|parent| {
+                    for button in SearchFailedButton::ALL {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(200.), Val::Px(48.)),
+                                    margin: UiRect::all(Val::Px(8.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                color: Color::rgb(0.15, 0.15, 0.15).into(),
+                                ..default()
+                            })
+                            .insert(button)
+                            .with_children(|parent| {
+                                parent.spawn_bundle(TextBundle::from_section(
+                                    button.label(),
+                                    text_style(24.),
+                                ));
+                            });
+                    }
+                });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(200.), Val::Px(48.)),
+                        margin: UiRect::all(Val::Px(8.)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .insert(CancelButton)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section("Cancel", text_style(24.)));
+                });
+            parent.spawn_bundle(TextBundle::from_section(
+                "or press B to play against bots instead",
+                text_style(18.),
+            ));
+            parent.spawn_bundle(
+                TextBundle::from_section("Your color", text_style(18.)).with_style(Style {
+                    margin: UiRect {
+                        top: Val::Px(24.),
+                        ..default()
+                    },
+                    ..default()
+                }),
+            );
+            parent
+                .spawn_bundle(NodeBundle {
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for color in 0..ROYALE_PLAYERS {
+                        let (cycle_color, _) = options
+                            .theme
+                            .team_colors(team_colors(color, options.colorblind));
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(32.), Val::Px(32.)),
+                                    margin: UiRect::all(Val::Px(4.)),
+                                    ..default()
+                                },
+                                color: cycle_color.into(),
+                                ..default()
+                            })
+                            .insert(ColorSwatch(color));
+                    }
+                });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(240.), Val::Px(40.)),
+                        margin: UiRect::all(Val::Px(8.)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .insert(SkinButton)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        skin_label(&options.profile),
+                        text_style(20.),
+                    ));
+                });
+        });
+}
+
+fn skin_label(profile: &PlayerProfile) -> String {
+    let skin = SKINS.get(profile.skin).unwrap_or(&SKINS[0]);
+    format!("Skin: {}", skin.name)
+}
+
+#[allow(clippy::type_complexity)]
+fn press_skin_button(
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut options: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<SkinButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    for (interaction, children) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        options.profile.skin = (options.profile.skin + 1) % SKINS.len();
+        broadcast_profile(&mut socket, &options.profile);
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                text.sections[0].value = skin_label(&options.profile);
+            }
+        }
+    }
+}
+
+fn press_color_swatches(
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut options: ResMut<Settings>,
+    mut swatch_query: Query<(&Interaction, &ColorSwatch, &mut Style)>,
+) {
+    for (interaction, swatch, mut style) in swatch_query.iter_mut() {
+        if *interaction == Interaction::Clicked && options.profile.color != swatch.0 {
+            options.profile.color = swatch.0;
+            broadcast_profile(&mut socket, &options.profile);
+        }
+        // the picked one stands out by being bigger
+        let size = if options.profile.color == swatch.0 {
+            40.
+        } else {
+            32.
+        };
+        let size = Size::new(Val::Px(size), Val::Px(size));
+        // only touch the style when it changes, so the ui isn't laid out again every frame
+        if style.size != size {
+            style.size = size;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_matchmaking_status(
+    time: Res<Time>,
+    args: Res<Args>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    socket: Res<Option<PeerSocket>>,
+    server: Res<ServerStatus>,
+    lobby: Res<Lobby>,
+    mut status_query: Query<(&mut Text, &MatchmakingStatus)>,
+    mut row_query: Query<&mut Style, (With<SearchFailedRow>, Without<ReadyButton>)>,
+    mut ready_query: Query<&mut Style, With<ReadyButton>>,
+) {
+    // players() counts us too
+    let connected = (*socket).as_ref().map_or(0, |socket| players(socket).len());
+    let num_humans = queue.num_humans(*mode, &args);
+    let full = socket.is_some() && connected >= num_humans;
+    for mut style in row_query.iter_mut() {
+        show_node(&mut style, server.failed());
+    }
+    for mut style in ready_query.iter_mut() {
+        show_node(&mut style, full);
+    }
+
+    if let Some(e) = &server.error {
+        for (mut text, _) in status_query.iter_mut() {
+            text.sections[0].value = format!("Couldn't reach a matchmaking server: {}", e);
+        }
+        return;
+    }
+    if server.timed_out {
+        for (mut text, _) in status_query.iter_mut() {
+            text.sections[0].value = "No opponent found".to_string();
+        }
+        return;
+    }
+    if server.probing {
+        for (mut text, _) in status_query.iter_mut() {
+            text.sections[0].value = format!("Connecting to {}...", server.server());
+        }
+        return;
+    }
+
+    let socket = match socket.as_ref() {
+        Some(socket) => socket,
+        None => return,
+    };
+    let ready = lobby.ready as usize
+        + socket
+            .connected_peers()
+            .filter(|peer| {
+                lobby.ready_peers.get(peer).map(|(proposal, _)| proposal) == lobby.agreed.as_ref()
+            })
+            .count();
+    for (mut text, status) in status_query.iter_mut() {
+        let seconds = (time.seconds_since_startup() - status.started) as u32;
+        let progress = if full {
+            format!("{} / {} players ready", ready, connected)
+        } else {
+            format!("{} / {} players connected", connected, mode.num_players())
+        };
+        let mut value = format!("{}    {}:{:02}", progress, seconds / 60, seconds % 60);
+        for peer in socket.connected_peers() {
+            if let Some(ping) = lobby.pings.get(&peer) {
+                value += &format!("\n{}: {:.0} ms", lobby.peer_name(&peer), ping);
+            }
+            if lobby.rejected.contains(&peer) {
+                value += &format!(
+                    "\n{} proposed settings out of range, waiting for new ones",
+                    lobby.peer_name(&peer)
+                );
+            }
+        }
+        if let Some(delay) = lobby.suggested_input_delay() {
+            value += &format!("\nSuggested input delay: {} frames", delay);
+        }
+        text.sections[0].value = value;
+    }
+}
+
+pub fn show_node(style: &mut Style, shown: bool) {
+    let display = if shown { Display::Flex } else { Display::None };
+    if style.display != display {
+        style.display = display;
+    }
+}
+
+fn ready_label(ready: bool) -> &'static str {
+    if ready {
+        "Not ready"
+    } else {
+        "Ready"
+    }
+}
+
+fn press_ready_button(
+    mut socket: ResMut<Option<PeerSocket>>,
+    mut lobby: ResMut<Lobby>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<ReadyButton>)>,
+) {
+    for interaction in button_query.iter() {
+        if *interaction == Interaction::Clicked {
+            lobby.ready = !lobby.ready;
+            broadcast_ready(&mut socket, &lobby);
+        }
+    }
+}
+
+fn press_lobby_settings_buttons(
+    mut socket: ResMut<Option<PeerSocket>>,
+    queue: Res<Queue>,
+    tuning: Res<Tuning>,
+    mut mode: ResMut<GameMode>,
+    mut lobby: ResMut<Lobby>,
+    mut button_query: Query<
+        (&Interaction, &LobbySettingsButton, &mut UiColor),
+        Changed<Interaction>,
+    >,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => Color::rgb(0.35, 0.75, 0.35).into(),
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let settings = &mut lobby.proposal;
+        match button {
+            LobbySettingsButton::Mode => *mode = mode.next_alike(),
+            LobbySettingsButton::TrailLength => {
+                settings.trail_length = settings.next_trail_length()
+            }
+            LobbySettingsButton::Speed => {
+                let scale = settings.next_speed_scale(&tuning);
+                settings.move_speed = tuning.move_speed * scale;
+                settings.turn_speed = tuning.turn_speed * scale;
+            }
+            LobbySettingsButton::Rounds => settings.rounds = settings.next_rounds(),
+            LobbySettingsButton::Arena => settings.board_size = settings.next_board_size(),
+            LobbySettingsButton::Handicap(handle) => {
+                settings.handicaps[*handle] = settings.next_handicap(*handle)
+            }
+        }
+        broadcast_proposal(&mut socket, lobby.our_proposal(&queue, *mode));
+    }
+}
+
+// Hosts get buttons with what they're proposing, everyone else a line with what the host
+// proposed. The ready button's label lives here too.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_lobby_settings(
+    args: Res<Args>,
+    queue: Res<Queue>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    socket: Res<Option<PeerSocket>>,
+    lobby: Res<Lobby>,
+    mut row_query: Query<&mut Style, With<LobbySettingsRow>>,
+    label_query: Query<
+        (&Children, Option<&LobbySettingsButton>),
+        Or<(With<LobbySettingsButton>, With<ReadyButton>)>,
+    >,
+    mut text_query: Query<&mut Text, Without<LobbySettingsText>>,
+    mut summary_query: Query<&mut Text, With<LobbySettingsText>>,
+) {
+    let full = (*socket).as_ref().map_or(false, |socket| {
+        players(socket).len() >= queue.num_humans(*mode, &args)
+    });
+    let host = (*socket).as_ref().map_or(false, is_host);
+    let editable = full && host && *queue != Queue::Ranked;
+    for mut style in row_query.iter_mut() {
+        show_node(&mut style, editable);
+    }
+
+    let ours = lobby.our_proposal(&queue, *mode);
+    for (children, button) in label_query.iter() {
+        // ready gets taken back whenever the host changes something
+        let label = match button {
+            Some(button) => button.label(&ours, &tuning),
+            None => ready_label(lobby.ready).to_string(),
+        };
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                if text.sections[0].value != label {
+                    text.sections[0].value = label.clone();
+                }
+            }
+        }
+    }
+
+    // handicaps only get a mention when someone has one
+    let summary = match lobby.agreed {
+        Some(agreed) if full && !editable => LobbySettingsButton::ALL
+            .into_iter()
+            .chain(
+                (0..queue.num_humans(*mode, &args))
+                    .filter(|handle| agreed.settings.handicaps[*handle] != Handicap::default())
+                    .map(LobbySettingsButton::Handicap),
+            )
+            .map(|button| button.label(&agreed, &tuning))
+            .collect::<Vec<_>>()
+            .join("    "),
+        _ => String::new(),
+    };
+    for mut text in summary_query.iter_mut() {
+        if text.sections[0].value != summary {
+            text.sections[0].value = summary.clone();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn press_search_failed_buttons(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut state: ResMut<State<GameState>>,
+    mode: Res<GameMode>,
+    lobby: Res<Lobby>,
+    options: Res<Settings>,
+    mut bots: ResMut<BotPlayers>,
+    mut server: ResMut<ServerStatus>,
+    mut probe: NonSendMut<Option<ServerProbe>>,
+    mut button_query: Query<
+        (&Interaction, &SearchFailedButton, &mut UiColor),
+        Changed<Interaction>,
+    >,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => Color::rgb(0.35, 0.75, 0.35).into(),
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+        if *interaction != Interaction::Clicked || !server.failed() {
+            continue;
+        }
+        let difficulty = match button {
+            SearchFailedButton::Retry => {
+                retry_servers(&time, &mut server, &mut probe);
+                return;
+            }
+            SearchFailedButton::Bots => lobby.proposal.bot_difficulty,
+            SearchFailedButton::Practice => BotDifficulty::Easy,
+        };
+        let settings = MatchSettings {
+            bot_difficulty: difficulty,
+            ..lobby.proposal
+        };
+        start_local_match(
+            &mut commands,
+            &mut state,
+            *mode,
+            settings,
+            &options,
+            &mut bots,
+            false,
+        );
+        return;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn press_cancel_button(
+    mut socket: ResMut<Option<PeerSocket>>,
+    server: Res<ServerStatus>,
+    mut state: ResMut<State<GameState>>,
+    mut lobby: ResMut<Lobby>,
+    mut button_query: Query<
+        (&Interaction, &mut UiColor),
+        (Changed<Interaction>, With<CancelButton>),
+    >,
+) {
+    // already on our way into a match
+    if socket.is_none() && !server.connecting() {
+        return;
+    }
+    for (interaction, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => Color::rgb(0.75, 0.35, 0.35).into(),
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        // dropping the socket closes it, and a fresh one is opened if we search again
+        *socket = None;
+        lobby.clear_peers();
+        state.set(GameState::MainMenu).unwrap();
+        return;
+    }
+}
+
+fn cleanup_matchmaking_screen(
+    mut commands: Commands,
+    screen_query: Query<Entity, With<MatchmakingScreen>>,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn setup_main_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    options: Res<Settings>,
+    leaderboard: Res<Leaderboard>,
+) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(MainMenu)
+        .with_children(|parent| {
+            let ranked = leaderboard.endpoint.is_some();
+            let buttons = MenuButton::ALL
+                .into_iter()
+                .filter(|button| *button != MenuButton::Ranked || ranked);
+            for button in buttons {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(260.), Val::Px(56.)),
+                            margin: UiRect::all(Val::Px(8.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        color: Color::rgb(0.15, 0.15, 0.15).into(),
+                        ..default()
+                    })
+                    .insert(button)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            button.label(),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 28.,
+                                color: Color::WHITE,
+                            },
+                        ));
+                    });
+            }
+            if options.stats.matches > 0 {
+                parent.spawn_bundle(TextBundle::from_section(
+                    options.stats.summary(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 18.,
+                        color: Color::GRAY,
+                    },
+                ));
+            }
+            if let Some(summary) = options.daily.summary(today()) {
+                parent.spawn_bundle(TextBundle::from_section(
+                    summary,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 18.,
+                        color: Color::GRAY,
+                    },
+                ));
+            }
+            if !options.ratings.opponents.is_empty() {
+                parent.spawn_bundle(TextBundle::from_section(
+                    options.ratings.summary(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 18.,
+                        color: Color::GRAY,
+                    },
+                ));
+            }
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        leaderboard_label(&leaderboard),
+                        TextStyle {
+                            font,
+                            font_size: 18.,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect {
+                            top: Val::Px(16.),
+                            ..default()
+                        },
+                        ..default()
+                    }),
+                )
+                .insert(LeaderboardText);
+        });
+}
+
+// Empty until there's a board to show
+fn leaderboard_label(leaderboard: &Leaderboard) -> String {
+    if leaderboard.entries.is_empty() {
+        return String::new();
+    }
+    let mut label = "Leaderboard".to_string();
+    for (rank, entry) in leaderboard.entries.iter().enumerate() {
+        label.push_str(&format!("\n{}. {}  {}", rank + 1, entry.name, entry.score));
+    }
+    label
+}
+
+// The board is fetched each time the menu opens, so it can show up after the menu does
+fn update_leaderboard_text(
+    leaderboard: Res<Leaderboard>,
+    mut text_query: Query<&mut Text, With<LeaderboardText>>,
+) {
+    if !leaderboard.is_changed() {
+        return;
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = leaderboard_label(&leaderboard);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn press_menu_buttons(
+    mut commands: Commands,
+    time: Res<Time>,
+    args: Res<Args>,
+    mut state: ResMut<State<GameState>>,
+    mut queue: ResMut<Queue>,
+    mut mode: ResMut<GameMode>,
+    lobby: Res<Lobby>,
+    options: Res<Settings>,
+    mut bots: ResMut<BotPlayers>,
+    mut button_query: Query<(&Interaction, &MenuButton, &mut UiColor), Changed<Interaction>>,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => Color::rgb(0.35, 0.75, 0.35).into(),
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            MenuButton::PlayOnline | MenuButton::Ranked | MenuButton::CustomRoom => {
+                *queue = match button {
+                    MenuButton::Ranked => Queue::Ranked,
+                    // whoever launched with --room wants to keep meeting the same people
+                    MenuButton::CustomRoom => {
+                        Queue::Custom(args.room.clone().unwrap_or_else(|| custom_room_code(&time)))
+                    }
+                    _ => Queue::Casual,
+                };
+                state.set(GameState::Matchmaking).unwrap();
+            }
+            MenuButton::Local => start_local_match(
+                &mut commands,
+                &mut state,
+                *mode,
+                lobby.proposal,
+                &options,
+                &mut bots,
+                false,
+            ),
+            MenuButton::Practice => {
+                let settings = MatchSettings {
+                    bot_difficulty: BotDifficulty::Easy,
+                    ..lobby.proposal
+                };
+                start_local_match(
+                    &mut commands,
+                    &mut state,
+                    *mode,
+                    settings,
+                    &options,
+                    &mut bots,
+                    false,
+                )
+            }
+            MenuButton::Training => {
+                start_local_match(
+                    &mut commands,
+                    &mut state,
+                    *mode,
+                    lobby.proposal,
+                    &options,
+                    &mut bots,
+                    false,
+                );
+                // after the default one the match starts with
+                commands.insert_resource(Training {
+                    enabled: true,
+                    ..default()
+                });
+            }
+            MenuButton::Daily => {
+                let day = today();
+                // the lobby keeps the mode afterwards, like any other change to it
+                *mode = DAILY_MODE;
+                start_local_match(
+                    &mut commands,
+                    &mut state,
+                    DAILY_MODE,
+                    daily_settings(day),
+                    &options,
+                    &mut bots,
+                    false,
+                );
+                // after the default one the match starts with
+                commands.insert_resource(Daily {
+                    day: Some(day),
+                    ..default()
+                });
+            }
+            MenuButton::Settings => state.set(GameState::SettingsMenu).unwrap(),
+        }
+        // only act on the first click this frame, the state can only change once
+        return;
+    }
+}
+
+fn cleanup_main_menu(mut commands: Commands, menu_query: Query<Entity, With<MainMenu>>) {
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn setup_settings_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    options: Res<Settings>,
+    tuning: Res<Tuning>,
+    rebinding: Res<Rebinding>,
+    gamepads: Res<Gamepads>,
+) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(SettingsMenu)
+        .with_children(|parent| {
+            for button in SettingsButton::ALL
+                .into_iter()
+                .filter(SettingsButton::available)
+            {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(420.), Val::Px(32.)),
+                            margin: UiRect::all(Val::Px(2.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        color: Color::rgb(0.15, 0.15, 0.15).into(),
+                        ..default()
+                    })
+                    .insert(button)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            button.label(
+                                &options,
+                                &tuning,
+                                &rebinding,
+                                gamepads.iter().next().is_some(),
+                            ),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 24.,
+                                color: Color::WHITE,
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn press_settings_buttons(
+    mut state: ResMut<State<GameState>>,
+    mut options: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut button_query: Query<(&Interaction, &SettingsButton, &mut UiColor), Changed<Interaction>>,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => Color::rgb(0.35, 0.75, 0.35).into(),
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        rebinding.0 = None;
+        match button {
+            SettingsButton::Volume => options.volume = next_volume(options.volume),
+            SettingsButton::MusicVolume => options.music_volume = next_volume(options.music_volume),
+            SettingsButton::EffectsVolume => {
+                options.effects_volume = next_volume(options.effects_volume)
+            }
+            SettingsButton::InputDelay => {
+                options.input_delay = match options.input_delay {
+                    None => Some(0),
+                    Some(frames) if frames < 8 => Some(frames + 1),
+                    Some(_) => None,
+                };
+            }
+            SettingsButton::SpectatorDelay => {
+                options.spectator_delay = match options.spectator_delay {
+                    0 => 3,
+                    3 => 10,
+                    10 => 30,
+                    _ => 0,
+                };
+            }
+            SettingsButton::Colorblind => options.colorblind = !options.colorblind,
+            SettingsButton::Theme => options.theme = options.theme.next(),
+            SettingsButton::MouseSteering => options.mouse_steering = !options.mouse_steering,
+            SettingsButton::Camera => {
+                options.camera_mode = match options.camera_mode {
+                    CameraMode::Board => CameraMode::Follow,
+                    CameraMode::Follow => CameraMode::FitPlayers,
+                    CameraMode::FitPlayers => CameraMode::Board,
+                };
+            }
+            SettingsButton::ScreenShake => {
+                // steps of 25%, wrapping back to off
+                options.screen_shake = if options.screen_shake >= 1. {
+                    0.
+                } else {
+                    options.screen_shake + 0.25
+                };
+            }
+            SettingsButton::Glow => options.glow = !options.glow,
+            SettingsButton::Fullscreen => options.display.fullscreen = !options.display.fullscreen,
+            SettingsButton::Resolution => {
+                options.display.resolution = options.display.next_resolution()
+            }
+            SettingsButton::Vsync => options.display.vsync = !options.display.vsync,
+            SettingsButton::FrameLimit => {
+                options.display.frame_limit = options.display.next_frame_limit()
+            }
+            SettingsButton::Bind(action) => rebinding.0 = Some(*action),
+            SettingsButton::ResetControls => options.key_bindings = KeyBindings::default(),
+            // back to the match if that's where we came from
+            SettingsButton::Back if !state.inactives().is_empty() => {
+                state.pop().unwrap();
+                return;
+            }
+            SettingsButton::Back => {
+                state.set(GameState::MainMenu).unwrap();
+                return;
+            }
+        }
+    }
+}
+
+// steps of 10%, wrapping back to silent
+fn next_volume(volume: f32) -> f32 {
+    let step = (volume * 10.).round() as u32;
+    ((step + 1) % 11) as f32 / 10.
+}
+
+fn capture_rebinding(
+    keys: Res<Input<KeyCode>>,
+    mut options: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let action = match rebinding.0 {
+        Some(action) => action,
+        None => return,
+    };
+    let key = match keys.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+    rebinding.0 = None;
+    // escape backs out without changing anything
+    if key == KeyCode::Escape {
+        return;
+    }
+    options.key_bindings.bind(action, key);
+}
+
+fn update_settings_labels(
+    options: Res<Settings>,
+    tuning: Res<Tuning>,
+    rebinding: Res<Rebinding>,
+    // pads are picked up as soon as they're plugged in, so this can change under us
+    gamepads: Res<Gamepads>,
+    button_query: Query<(&SettingsButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !options.is_changed() && !rebinding.is_changed() && !gamepads.is_changed() {
+        return;
+    }
+    let gamepad = gamepads.iter().next().is_some();
+    for (button, children) in button_query.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                text.sections[0].value = button.label(&options, &tuning, &rebinding, gamepad);
+            }
+        }
+    }
+}
+
+fn cleanup_settings_menu(
+    mut commands: Commands,
+    mut rebinding: ResMut<Rebinding>,
+    menu_query: Query<Entity, With<SettingsMenu>>,
+) {
+    rebinding.0 = None;
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
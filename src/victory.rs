@@ -0,0 +1,291 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use bevy::{
+    ecs::schedule::StateError, prelude::*, render::camera::ScalingMode,
+    sprite::MaterialMesh2dBundle,
+};
+
+use crate::{
+    gameplay::{pseudo_random, GameMode, Scoreboard},
+    rendering::{start_killcam, Cosmetics, Killcam, TrailAssets},
+    tuning::Tuning,
+    GameState,
+};
+
+// A few seconds of celebrating the winners once the match is decided, before the stats table.
+// The players are gone from the simulation by then, so the lap is scripted on our end and the
+// session carries on ticking underneath without us.
+pub struct VictoryPlugin;
+
+impl Plugin for VictoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Ceremony::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(start_ceremony.after(start_killcam)),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::Victory).with_system(setup_ceremony))
+            .add_system_set(
+                SystemSet::on_update(GameState::Victory)
+                    .with_system(drive_victory_lap)
+                    .with_system(animate_confetti)
+                    .with_system(end_ceremony),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Victory).with_system(cleanup_ceremony));
+    }
+}
+
+// How far this match has got with celebrating, the stats table waits for it to finish
+#[derive(Default)]
+pub struct Ceremony {
+    pub started: bool,
+    pub finished: bool,
+    // a shared lead has nobody to celebrate and skips straight to the table
+    pub winner: Option<usize>,
+    pub timer: Timer,
+}
+
+#[derive(Component)]
+pub struct VictoryCycle {
+    // where on the lap it starts, so a winning team spreads out around it
+    pub offset: f32,
+}
+
+#[derive(Component)]
+pub struct Confetti {
+    pub fall_speed: f32,
+    pub spin: f32,
+    // of its sway, so the pieces don't all drift together
+    pub phase: f32,
+}
+
+#[derive(Component)]
+pub struct CeremonyOverlay;
+
+// After the last round's killcam, once per match
+fn start_ceremony(
+    scoreboard: Res<Scoreboard>,
+    killcam: Res<Killcam>,
+    mut ceremony: ResMut<Ceremony>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if !scoreboard.match_over {
+        if ceremony.started {
+            *ceremony = default();
+        }
+        return;
+    }
+    if ceremony.started || killcam.playing() || killcam.pending.is_some() {
+        return;
+    }
+
+    let best = scoreboard.scores.iter().max().copied().unwrap_or(0);
+    let leaders: Vec<usize> = (0..scoreboard.scores.len())
+        .filter(|team| scoreboard.scores[*team] == best)
+        .collect();
+    match leaders[..] {
+        [winner] => match state.push(GameState::Victory) {
+            Ok(()) => ceremony.winner = Some(winner),
+            // something else is changing the state this frame, try again if it leaves us here
+            Err(StateError::StateAlreadyQueued) => return,
+            Err(e) => {
+                warn!("couldn't start the victory ceremony: {:?}", e);
+                ceremony.finished = true;
+            }
+        },
+        _ => ceremony.finished = true,
+    }
+    ceremony.started = true;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup_ceremony(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    trail_assets: Res<TrailAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    cosmetics: Res<Cosmetics>,
+    scoreboard: Res<Scoreboard>,
+    mut ceremony: ResMut<Ceremony>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+) {
+    let winner = match ceremony.winner {
+        Some(winner) => winner,
+        None => return,
+    };
+    ceremony.timer = Timer::from_seconds(CEREMONY_SECONDS, false);
+    let board_size = mode.board_size(&tuning);
+    // move_camera is on hold, so frame the whole arena ourselves
+    for (mut transform, mut projection) in camera_query.iter_mut() {
+        transform.translation = Vec3::new(0., 0., transform.translation.z);
+        projection.scaling_mode = ScalingMode::FixedVertical(board_size + 1.);
+    }
+
+    let (cycle_color, trail_color) = cosmetics.team_colors(winner);
+    let handles: Vec<usize> = (0..mode.num_players())
+        .filter(|handle| mode.team_of(*handle) == winner)
+        .collect();
+    for (i, handle) in handles.iter().enumerate() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: cycle_color,
+                    custom_size: Some(Vec2::new(
+                        tuning.player_size * 1.3,
+                        tuning.player_size * 0.65,
+                    )),
+                    ..default()
+                },
+                texture: asset_server.load(cosmetics.skin(*handle).sprite),
+                transform: Transform::from_translation(Vec3::new(0., 0., 5.2)),
+                ..default()
+            })
+            .insert(VictoryCycle {
+                offset: TAU * i as f32 / handles.len() as f32,
+            });
+    }
+
+    // spread out above the top of the view, so they keep falling in for a while
+    let colors = [cycle_color, trail_color, Color::WHITE]
+        .map(|color| materials.add(ColorMaterial::from(color)));
+    let top = (board_size + 1.) / 2.;
+    for i in 0..CONFETTI_PIECES {
+        let roll = pseudo_random(scoreboard.round.wrapping_mul(977).wrapping_add(i));
+        let x = ((roll % 1000) as f32 / 1000. - 0.5) * (board_size + 1.) * CONFETTI_WIDTH;
+        let y = top + (roll / 1000 % 1000) as f32 / 1000. * board_size;
+        commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: trail_assets.particle_mesh.clone(),
+                material: colors[i as usize % colors.len()].clone(),
+                transform: Transform::from_translation(Vec3::new(x, y, 5.3))
+                    .with_scale(Vec3::new(1., 0.5, 1.)),
+                ..default()
+            })
+            .insert(Confetti {
+                fall_speed: CONFETTI_FALL_SPEED * (0.6 + (roll / 1000000 % 80) as f32 / 100.),
+                spin: CONFETTI_SPIN * if roll % 2 == 0 { 1. } else { -1. },
+                phase: (roll % 628) as f32 / 100.,
+            });
+    }
+
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    let scores = (0..mode.num_teams())
+        .map(|team| {
+            format!(
+                "{} {}",
+                cosmetics.team_name(team),
+                scoreboard.scores.get(team).copied().unwrap_or(0)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("   ");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(CeremonyOverlay)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                format!("{} wins the match!", cosmetics.team_name(winner)),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 56.,
+                    color: cycle_color,
+                },
+            ));
+            parent.spawn_bundle(TextBundle::from_section(
+                scores,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 32.,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+// One lap around the middle of the arena over the whole ceremony, counter-clockwise
+fn drive_victory_lap(
+    mode: Res<GameMode>,
+    tuning: Res<Tuning>,
+    ceremony: Res<Ceremony>,
+    mut cycle_query: Query<(&VictoryCycle, &mut Transform)>,
+) {
+    let radius = mode.board_size(&tuning) * LAP_RADIUS;
+    for (cycle, mut transform) in cycle_query.iter_mut() {
+        let angle = cycle.offset + TAU * ceremony.timer.percent();
+        transform.translation =
+            (Vec2::new(angle.cos(), angle.sin()) * radius).extend(transform.translation.z);
+        transform.rotation = Quat::from_rotation_z(angle + FRAC_PI_2);
+    }
+}
+
+fn animate_confetti(time: Res<Time>, mut confetti_query: Query<(&Confetti, &mut Transform)>) {
+    let t = time.seconds_since_startup() as f32;
+    for (confetti, mut transform) in confetti_query.iter_mut() {
+        let sway = (t * CONFETTI_SWAY_FREQUENCY + confetti.phase).sin() * CONFETTI_SWAY;
+        transform.translation += Vec3::new(sway, -confetti.fall_speed, 0.) * time.delta_seconds();
+        transform.rotate_z(confetti.spin * time.delta_seconds());
+    }
+}
+
+// Over when the time's up, or sooner for anyone who's seen enough
+fn end_ceremony(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut ceremony: ResMut<Ceremony>,
+    mut state: ResMut<State<GameState>>,
+) {
+    // not Escape, that would open the pause menu as soon as we're back
+    if keys.any_just_pressed([KeyCode::Return, KeyCode::Space]) {
+        let duration = ceremony.timer.duration();
+        ceremony.timer.set_elapsed(duration);
+    }
+    // stays finished, so if something else is changing the state this frame we go next frame
+    if ceremony.timer.tick(time.delta()).finished() {
+        let _ = state.pop();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn cleanup_ceremony(
+    mut commands: Commands,
+    mut ceremony: ResMut<Ceremony>,
+    entity_query: Query<Entity, Or<(With<VictoryCycle>, With<Confetti>, With<CeremonyOverlay>)>>,
+) {
+    ceremony.finished = true;
+    for entity in entity_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+const CEREMONY_SECONDS: f32 = 4.;
+
+// of the board size
+const LAP_RADIUS: f32 = 0.3;
+
+const CONFETTI_PIECES: u32 = 150;
+
+// of the view's height, a bit wider than that covers most windows
+const CONFETTI_WIDTH: f32 = 1.8;
+
+// units per second, give or take
+const CONFETTI_FALL_SPEED: f32 = 2.5;
+
+// radians per second
+const CONFETTI_SPIN: f32 = 4.;
+
+const CONFETTI_SWAY: f32 = 0.6;
+
+const CONFETTI_SWAY_FREQUENCY: f32 = 3.;